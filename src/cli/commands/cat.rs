@@ -0,0 +1,57 @@
+use std::{
+	fs::File,
+	io::{self, stdout, BufWriter, Write},
+};
+
+use exitcode::{DATAERR, NOINPUT, USAGE};
+use log::{debug, warn};
+
+use crate::{
+	cli::{die, Location},
+	file,
+};
+
+pub fn exec(location: Location) -> io::Result<()> {
+	let Location {
+		snapshot: Some(snap),
+		path,
+		..
+	} = location
+	else {
+		die(
+			USAGE,
+			"expected a `<site>/<snapshot>/<path>` location, exiting",
+		)
+	};
+
+	let (content_path, rec) = match snap.resolve_content(&path) {
+		Ok(Some(resolved)) => resolved,
+		Ok(None) => die(
+			NOINPUT,
+			&format!("{path:?} not found in snapshot {:?}, exiting", snap.0),
+		),
+		Err(e) if e.kind() == io::ErrorKind::InvalidInput => die(USAGE, &format!("{e}, exiting")),
+		Err(e) => return Err(e),
+	};
+	debug!("reading content of {path:?} from {content_path:?}");
+
+	let mut out = BufWriter::new(stdout().lock());
+	let (_size, hash) = file::copy_hashed(&mut File::open(&content_path)?, &mut out)?;
+	out.flush()?;
+
+	match rec.b3sum {
+		Some(expected) if expected != hash => die(
+			DATAERR,
+			&format!(
+				"BLAKE3 mismatch for {content_path:?}: recorded {}, actual {}, exiting",
+				expected.to_hex(),
+				hash.to_hex()
+			),
+		),
+		Some(_) => Ok(()),
+		None => {
+			warn!("no hash recorded for {path:?}, unable to verify its content");
+			Ok(())
+		}
+	}
+}
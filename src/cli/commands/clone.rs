@@ -0,0 +1,22 @@
+use std::{error::Error, fs, path::Path};
+
+use exitcode::USAGE;
+
+use crate::{
+	cli::{commands::pull, die},
+	repo::{tag_file, Repo},
+};
+
+pub fn exec(src: &Path, dst: &Path) -> Result<(), Box<dyn Error>> {
+	let src_repo = pull::src_repo_or_die(src);
+
+	if dst.exists() && fs::read_dir(dst)?.next().is_some() {
+		die(USAGE, &format!("destination {dst:?} is not empty, exiting"))
+	}
+	fs::create_dir_all(dst)?;
+	fs::copy(src.join(tag_file::NAME), dst.join(tag_file::NAME))?;
+	let dst_repo = Repo(dst.to_path_buf());
+	fs::create_dir(dst_repo.sites_path())?;
+
+	pull::pull(&src_repo, &dst_repo)
+}
@@ -0,0 +1,135 @@
+use std::{
+	collections::BTreeMap,
+	error::Error,
+	ffi::OsStr,
+	fs,
+	io::{self, stdout, Write},
+	os::unix::prelude::OsStrExt,
+	path::{Path, PathBuf},
+};
+
+use log::info;
+
+use crate::{
+	file::FileType,
+	repo::{meta_file::MetaRecord, snapshot::Snapshot},
+};
+
+/// Lists the differences between two snapshots, comparing the directory digests of their Merkle
+/// trees to skip unchanged subtrees
+pub fn exec(from: &Snapshot, to: &Snapshot) -> Result<(), Box<dyn Error>> {
+	let mut differ = Differ {
+		from,
+		to,
+		out: stdout().lock(),
+		skipped: 0,
+	};
+	differ.diff_dir(Path::new(""))?;
+	info!("{} unchanged subtree(s) skipped by digest", differ.skipped);
+	Ok(())
+}
+
+struct Differ<'a, W: Write> {
+	from: &'a Snapshot,
+	to: &'a Snapshot,
+	out: W,
+	skipped: u64,
+}
+
+impl<W: Write> Differ<'_, W> {
+	fn diff_dir(&mut self, rel_dir: &Path) -> io::Result<()> {
+		let from = dir_records(self.from, rel_dir)?;
+		let mut to = dir_records(self.to, rel_dir)?;
+
+		let mut names: Vec<&Vec<u8>> = from.keys().chain(to.keys()).collect();
+		names.sort();
+		names.dedup();
+		let names: Vec<Vec<u8>> = names.into_iter().cloned().collect();
+
+		for name in names {
+			let path = rel_dir.join(OsStr::from_bytes(&name));
+			let (a, b) = match (from.get(&name), to.remove(&name)) {
+				(Some(a), None) => {
+					self.line("deleted", &path, a, "")?;
+					continue;
+				}
+				(None, Some(b)) => {
+					self.line("added", &path, &b, "")?;
+					continue;
+				}
+				(Some(a), Some(b)) => (a, b),
+				(None, None) => unreachable!("names come from either snapshot"),
+			};
+
+			let changes = a.metadata_changes(&b).join(", ");
+			let both_dirs =
+				a.file_type == Some(FileType::Dir) && b.file_type == Some(FileType::Dir);
+			if both_dirs {
+				if !changes.is_empty() {
+					self.line("metadata", &path, &b, &changes)?;
+				}
+				if a.tree_b3sum.is_some() && a.tree_b3sum == b.tree_b3sum {
+					self.skipped += 1;
+				} else {
+					self.diff_dir(&path)?;
+				}
+			} else if a.file_type != b.file_type
+				|| a.b3sum != b.b3sum
+				|| self.link_target(self.from, &path, a)? != self.link_target(self.to, &path, &b)?
+			{
+				self.line("content", &path, &b, &changes)?;
+			} else if !changes.is_empty() {
+				self.line("metadata", &path, &b, &changes)?;
+			}
+		}
+		Ok(())
+	}
+
+	fn link_target(
+		&self,
+		snap: &Snapshot,
+		path: &Path,
+		rec: &MetaRecord,
+	) -> io::Result<Option<PathBuf>> {
+		match rec.file_type {
+			Some(FileType::Lnk) => fs::read_link(snap.data_dir().join(path)).map(Some),
+			_ => Ok(None),
+		}
+	}
+
+	fn line(
+		&mut self,
+		status: &str,
+		path: &Path,
+		rec: &MetaRecord,
+		details: &str,
+	) -> io::Result<()> {
+		let slash = if rec.file_type == Some(FileType::Dir) {
+			"/"
+		} else {
+			""
+		};
+		write!(self.out, "{status:<8}  ")?;
+		self.out.write_all(path.as_os_str().as_bytes())?;
+		write!(self.out, "{slash}")?;
+		if !details.is_empty() {
+			write!(self.out, "  ({details})")?;
+		}
+		writeln!(self.out)
+	}
+}
+
+/// Returns the resolved records of `rel_dir` in `snap` by name, or none if it does not exist
+fn dir_records(snap: &Snapshot, rel_dir: &Path) -> io::Result<BTreeMap<Vec<u8>, MetaRecord>> {
+	let records = match snap.dir_records(rel_dir) {
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+		res => res?,
+	};
+	records
+		.into_iter()
+		.map(|rec| {
+			let rec = snap.resolve_record(&rel_dir.join(OsStr::from_bytes(&rec.name)), rec)?;
+			Ok((rec.name.clone(), rec))
+		})
+		.collect()
+}
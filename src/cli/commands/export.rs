@@ -0,0 +1,50 @@
+use std::{
+	fs::File,
+	io::{self, stdout, BufWriter, IsTerminal, Write},
+	path::Path,
+};
+
+use clap::ValueEnum;
+use exitcode::USAGE;
+use log::info;
+
+use crate::{
+	cli::{die, human_bytes},
+	repo::{export, snapshot::Snapshot},
+};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Format {
+	/// POSIX.1-2001 pax interchange format, as read by GNU tar, bsdtar and most other tools
+	Pax,
+}
+
+pub fn exec(snap: Snapshot, path: Option<&Path>, format: Format) -> io::Result<()> {
+	let out: Box<dyn Write> = match path {
+		Some(path) => Box::new(File::create(path)?),
+		None => {
+			if stdout().is_terminal() {
+				die(
+					USAGE,
+					"refusing to write an archive to a terminal, give a file or use a redirection, \
+					exiting",
+				)
+			}
+			Box::new(stdout().lock())
+		}
+	};
+
+	let stats = match format {
+		Format::Pax => export::write_pax(&snap, BufWriter::new(out))?,
+	};
+	info!(
+		"exported {} entries, {} of file content{}",
+		stats.entries,
+		human_bytes(stats.bytes),
+		match stats.skipped {
+			0 => String::new(),
+			skipped => format!(", skipped {skipped} socket(s)"),
+		}
+	);
+	Ok(())
+}
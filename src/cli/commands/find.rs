@@ -0,0 +1,188 @@
+use std::{
+	error::Error,
+	io::{stdout, BufWriter, Write},
+	os::unix::prelude::OsStrExt,
+};
+
+use blake3::Hash;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use clap::Args;
+use log::warn;
+
+use crate::{
+	cli::repo_root_or_die,
+	file::FileType,
+	repo::{meta_file::MetaRecord, site::Site, Repo},
+	util::glob::Glob,
+};
+
+#[derive(Debug, Args)]
+pub struct FindArgs {
+	/// Only match entries whose name matches the shell glob, e.g. '*.jpg'
+	#[arg(long)]
+	name: Option<String>,
+
+	/// Only match regular files with the given BLAKE3 hash, in hex
+	#[arg(long, value_parser = parse_hash)]
+	b3sum: Option<Hash>,
+
+	/// Only match entries of at least this many bytes
+	#[arg(long)]
+	min_size: Option<u64>,
+
+	/// Only match entries of at most this many bytes
+	#[arg(long)]
+	max_size: Option<u64>,
+
+	/// Only match entries modified at or after this time, as `YYYY-MM-DD`, `YYYY-MM-DD HH:MM:SS`
+	/// (both local time) or RFC 3339
+	#[arg(long, value_parser = parse_time)]
+	newer: Option<DateTime<Utc>>,
+
+	/// Only match entries modified before this time, in the same formats as `--newer`
+	#[arg(long, value_parser = parse_time)]
+	older: Option<DateTime<Utc>>,
+
+	/// Only match entries owned by this numeric user ID
+	#[arg(long)]
+	uid: Option<u32>,
+
+	/// Only match entries of this type: fifo, chr, dir, blk, reg, lnk or sock
+	#[arg(long("type"), value_parser = parse_file_type)]
+	file_type: Option<FileType>,
+
+	/// Separate results with NUL instead of newline
+	#[arg(short('0'), long)]
+	print0: bool,
+}
+
+fn parse_hash(s: &str) -> Result<Hash, String> {
+	Hash::from_hex(s).map_err(|e| e.to_string())
+}
+
+fn parse_file_type(s: &str) -> Result<FileType, String> {
+	FileType::from_name(s).ok_or_else(|| format!("unknown file type {s:?}"))
+}
+
+fn parse_time(s: &str) -> Result<DateTime<Utc>, String> {
+	if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+		return Ok(t.with_timezone(&Utc));
+	}
+	let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S")
+		.ok()
+		.or_else(|| {
+			NaiveDate::parse_from_str(s, "%Y-%m-%d")
+				.ok()
+				.and_then(|d| d.and_hms_opt(0, 0, 0))
+		})
+		.ok_or_else(|| format!("unable to parse {s:?} as a time"))?;
+	Local
+		.from_local_datetime(&naive)
+		.earliest()
+		.map(|t| t.with_timezone(&Utc))
+		.ok_or_else(|| format!("{s:?} does not exist in the local time zone"))
+}
+
+struct Matcher {
+	name_glob: Option<Glob>,
+	args: FindArgs,
+}
+
+impl Matcher {
+	fn new(args: FindArgs) -> Result<Matcher, Box<dyn Error>> {
+		Ok(Matcher {
+			name_glob: args.name.as_deref().map(Glob::new).transpose()?,
+			args,
+		})
+	}
+
+	fn matches(&self, rec: &MetaRecord) -> bool {
+		let args = &self.args;
+
+		if self
+			.name_glob
+			.as_ref()
+			.is_some_and(|glob| !glob.matches(&rec.name))
+		{
+			return false;
+		}
+
+		if args.b3sum.is_some() && rec.b3sum != args.b3sum {
+			return false;
+		}
+		if args.file_type.is_some() && rec.file_type != args.file_type {
+			return false;
+		}
+		if args.uid.is_some() && rec.uid != args.uid {
+			return false;
+		}
+
+		if args.min_size.is_some() || args.max_size.is_some() {
+			let Some(size) = rec.size else {
+				return false;
+			};
+			if args.min_size.is_some_and(|min| size < min)
+				|| args.max_size.is_some_and(|max| size > max)
+			{
+				return false;
+			}
+		}
+
+		if args.newer.is_some() || args.older.is_some() {
+			let Some(mtime) = rec.mtime.and_then(|t| t.to_date_time()) else {
+				return false;
+			};
+			if args.newer.is_some_and(|newer| mtime < newer)
+				|| args.older.is_some_and(|older| mtime >= older)
+			{
+				return false;
+			}
+		}
+
+		true
+	}
+}
+
+pub fn exec(args: FindArgs) -> Result<(), Box<dyn Error>> {
+	let repo = Repo(repo_root_or_die()?);
+	let separator = if args.print0 { b'\0' } else { b'\n' };
+	let matcher = Matcher::new(args)?;
+
+	let mut sites: Vec<Site> = repo.sites()?.into_iter().filter_map(Result::ok).collect();
+	sites.sort_by(|a, b| a.0.cmp(&b.0));
+
+	let mut out = BufWriter::new(stdout().lock());
+	for site in sites {
+		for snap in site.snapshots_sorted()? {
+			for rec_res in snap.records()? {
+				let (path, rec) = match rec_res {
+					Ok(found) => found,
+					Err(e) => {
+						warn!("skipping unreadable metadata in {:?}: {e}", snap.0);
+						continue;
+					}
+				};
+				let rec = match snap.resolve_record(&path, rec) {
+					Ok(rec) => rec,
+					Err(e) => {
+						warn!("skipping unresolvable record: {e}");
+						continue;
+					}
+				};
+				if !matcher.matches(&rec) {
+					continue;
+				}
+
+				out.write_all(site.name().as_bytes())?;
+				out.write_all(b"/")?;
+				out.write_all(snap.name().as_bytes())?;
+				out.write_all(b"/")?;
+				out.write_all(path.as_os_str().as_bytes())?;
+				out.write_all(&[separator])?;
+			}
+		}
+	}
+	out.flush()?;
+
+	Ok(())
+}
@@ -0,0 +1,114 @@
+use std::{
+	collections::HashMap,
+	error::Error,
+	fs, io,
+	io::{stdout, Write},
+	path::PathBuf,
+};
+
+use blake3::Hash;
+use exitcode::DATAERR;
+
+use crate::{
+	cli::die,
+	file::{self, FileType},
+	repo::{
+		merkle::{self, Problem},
+		snapshot::Snapshot,
+	},
+	util::ext::PathExt,
+};
+
+/// Verifies the `(label, snapshot)` pairs, listing the problems found
+pub fn exec(snapshots: Vec<(String, Snapshot)>, quick: bool) -> Result<(), Box<dyn Error>> {
+	let mut out = stdout().lock();
+	let mut content_hashes = HashMap::new();
+	let mut total = 0;
+	for (label, snap) in snapshots {
+		let mut problems = merkle::verify(&snap)?;
+		if !quick {
+			problems.extend(verify_content(&snap, &mut content_hashes)?);
+		}
+
+		if problems.is_empty() {
+			writeln!(out, "{label}\tok")?;
+		}
+		for problem in &problems {
+			writeln!(
+				out,
+				"{label}/{}\t{}",
+				problem.path.to_string_lossy(),
+				problem.message
+			)?;
+		}
+		total += problems.len();
+	}
+
+	if total > 0 {
+		die(DATAERR, &format!("{total} problem(s) found, exiting"))
+	}
+	Ok(())
+}
+
+/// Verifies the content of the regular files in the snapshot against their recorded hashes.
+/// `hashes` caches the hashes of backing files, which are usually shared by many snapshots.
+fn verify_content(
+	snap: &Snapshot,
+	hashes: &mut HashMap<PathBuf, Hash>,
+) -> io::Result<Vec<Problem>> {
+	let data_dir = snap.data_dir();
+	let mut problems = Vec::new();
+	for rec_res in snap.records()? {
+		let (rel_path, rec) = rec_res?;
+		let (Some(FileType::Reg), None, Some(expected)) =
+			(rec.file_type, rec.same_since, rec.b3sum)
+		else {
+			continue;
+		};
+
+		let mut path = data_dir.join(&rel_path);
+		if rec.is_deduplicated {
+			let parent = path.parent().expect("records have parents");
+			path = match fs::read_link(&path) {
+				Ok(target) => parent.join(target).lexical_normalize(),
+				Err(e) => {
+					problems.push(Problem {
+						path: rel_path,
+						message: format!("unreadable deduplication symlink: {e}"),
+					});
+					continue;
+				}
+			};
+		}
+
+		let actual = match hashes.get(&path) {
+			Some(hash) => *hash,
+			None => match file::b3sum(&path) {
+				Ok(hash) => {
+					if rec.is_deduplicated {
+						hashes.insert(path.clone(), hash);
+					}
+					hash
+				}
+				Err(e) => {
+					problems.push(Problem {
+						path: rel_path,
+						message: format!("unreadable content in {path:?}: {e}"),
+					});
+					continue;
+				}
+			},
+		};
+		if actual != expected {
+			problems.push(Problem {
+				path: rel_path,
+				message: format!(
+					"BLAKE3 mismatch for {path:?}: recorded {}, actual {}",
+					expected.to_hex(),
+					actual.to_hex()
+				),
+			});
+		}
+	}
+	Ok(problems)
+}
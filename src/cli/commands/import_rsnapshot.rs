@@ -0,0 +1,310 @@
+use std::{
+	collections::HashMap,
+	error::Error,
+	ffi::OsString,
+	fs, io,
+	path::{Path, PathBuf},
+	time::Instant,
+};
+
+use chrono::{DateTime, Utc};
+use ed25519_dalek::SigningKey;
+use exitcode::{NOINPUT, USAGE};
+use log::{debug, info, warn};
+use nix::sys::stat::{mknod, Mode, SFlag};
+use serde::Serialize;
+
+use crate::{
+	cli::{create_snapshot, die, discard_on_error, finish_snapshot, human_bytes, writable_or_die},
+	error::IoResultExt,
+	file::{self, filekey::FileKey, FileType},
+	repo::{
+		dedup,
+		site::Site,
+		snap::{dump_meta, get_meta_sink},
+		snapshot::{self, Exclusion, Snapshot},
+		summary::Summary,
+	},
+	Error as BaktuError,
+};
+
+/// Recorded in place of the `baktu snap` arguments of imported snapshots
+#[derive(Serialize)]
+struct ImportArgs<'a> {
+	import_rsnapshot: &'a Path,
+}
+
+/// A regular file already stored by the import, by the source inode it was read from
+struct Stored {
+	size: u64,
+	mtime: (i64, u32),
+	hash: blake3::Hash,
+	backing_path: PathBuf,
+}
+
+pub fn exec(site: &Site, dirs: &[PathBuf], print_summary: bool) -> Result<(), Box<dyn Error>> {
+	writable_or_die(&site.repo())?;
+
+	let mut sources = Vec::new();
+	for dir in dirs {
+		let dir = match fs::canonicalize(dir) {
+			Ok(dir) if dir.is_dir() => dir,
+			Ok(_) => die(USAGE, &format!("{dir:?} is not a directory, exiting")),
+			Err(e) => die(NOINPUT, &format!("unable to access {dir:?}: {e}, exiting")),
+		};
+		let time: DateTime<Utc> = fs::metadata(&dir)?.modified()?.into();
+		sources.push((time, dir));
+	}
+	// rsnapshot touches each interval directory once it is complete, so their mtimes are the
+	// capture times, unlike the names of rotated directories such as `daily.N`
+	sources.sort();
+
+	if let (Some((first_time, first_dir)), Some(latest)) =
+		(sources.first(), site.snapshots_sorted()?.pop())
+	{
+		if let Some(summary) = latest.summary()? {
+			if *first_time < summary.start_time {
+				warn!(
+					"{first_dir:?} is older than the latest snapshot {:?}, but imported snapshots \
+					are numbered after the existing ones",
+					latest.0
+				);
+			}
+		}
+	}
+
+	let mut importer = Importer {
+		site,
+		signing_key: site.get_config()?.signing_key()?,
+		dedup_index: dedup::Index::load(&site.repo())?,
+		xattr_helper: file::xattrs::Helper::init_opt()?,
+		stored: HashMap::new(),
+		meta_name: snapshot::DEFAULT_META_NAME.into(),
+	};
+
+	for (time, dir) in sources {
+		info!("importing {dir:?}, captured at {time}");
+		let (snap_path, summary) = importer.import(&dir, time)?;
+
+		if print_summary {
+			eprintln!(
+				"snapshot {:?} imported from {dir:?} in {:.1}s: {} paths processed ({}), {} \
+				skipped",
+				snap_path,
+				summary.wall_time_secs,
+				summary.files_total(),
+				summary
+					.files
+					.iter()
+					.map(|(file_type, cnt)| format!("{cnt} {file_type}"))
+					.collect::<Vec<_>>()
+					.join(", "),
+				summary.exclusions_total(),
+			);
+			eprintln!(
+				"read {}, wrote {}, deduplicated {}, {} new unique hashes",
+				human_bytes(summary.bytes_read),
+				human_bytes(summary.bytes_written),
+				human_bytes(summary.bytes_deduplicated),
+				summary.new_unique_hashes
+			);
+		}
+	}
+
+	info!("import-rsnapshot subcommand done");
+
+	Ok(())
+}
+
+struct Importer<'a> {
+	site: &'a Site,
+	signing_key: Option<SigningKey>,
+	dedup_index: dedup::Index,
+	xattr_helper: Option<file::xattrs::Helper>,
+	/// rsnapshot hard links unchanged files across intervals, which lets us skip hashing and
+	/// comparing them again
+	stored: HashMap<FileKey, Stored>,
+	meta_name: OsString,
+}
+
+impl Importer<'_> {
+	/// Imports `dir` as a new snapshot captured at `time`, returning its path and summary
+	fn import(
+		&mut self,
+		dir: &Path,
+		time: DateTime<Utc>,
+	) -> Result<(PathBuf, Summary), Box<dyn Error>> {
+		let wall_clock = Instant::now();
+		let mut summary = Summary::new(time);
+		summary.imported_from = Some(dir.to_path_buf());
+
+		let snap = create_snapshot(
+			self.site,
+			&ImportArgs {
+				import_rsnapshot: dir,
+			},
+			&self.meta_name,
+		)?;
+		let result = self.fill(&snap, dir, &mut summary).and_then(|exclusions| {
+			// The capture time, so retention policies and listings place the snapshot correctly
+			summary.end_time = time;
+			summary.wall_time_secs = wall_clock.elapsed().as_secs_f64();
+			finish_snapshot(&snap, &exclusions, &summary, self.signing_key.as_ref())
+		});
+		discard_on_error(&snap, result)?;
+
+		Ok((snap.0, summary))
+	}
+
+	/// Copies the contents of `dir` into the data directory of `snap`, returning the paths it
+	/// excluded
+	fn fill(
+		&mut self,
+		snap: &Snapshot,
+		dir: &Path,
+		summary: &mut Summary,
+	) -> Result<Vec<Exclusion>, Box<dyn Error>> {
+		let data_path = snap.data_dir();
+		let mut exclusions = Vec::new();
+		for entry in walkdir::WalkDir::new(dir).min_depth(1).sort_by_file_name() {
+			let entry = entry?;
+			let path = entry.path();
+			let dst_path = data_path.join(path.strip_prefix(dir)?);
+			debug!("importing path {path:?}");
+
+			if entry.file_type().is_dir() && path.join(&self.meta_name).exists() {
+				return Err(BaktuError::Unsupported {
+					path: path.to_path_buf(),
+					reason: format!("already contains a file named {:?}", self.meta_name),
+				}
+				.into());
+			}
+
+			let stx = file::statx::get(path)?;
+			let mut hash = None;
+			let mut is_deduplicated = false;
+			match FileType::from_mode(stx.stx_mode as u32) {
+				Some(FileType::Dir) => fs::create_dir(&dst_path)?,
+				Some(FileType::Reg) => {
+					let (file_hash, deduplicated) = self.store(path, &stx, &dst_path, summary)?;
+					hash = Some(file_hash);
+					is_deduplicated = deduplicated;
+				}
+				Some(FileType::Lnk) => std::os::unix::fs::symlink(fs::read_link(path)?, &dst_path)?,
+				Some(_) => {
+					let (Some(kind), Some(perm)) = (
+						SFlag::from_bits(stx.stx_mode as u32 & libc::S_IFMT),
+						Mode::from_bits(stx.stx_mode as u32 & !libc::S_IFMT),
+					) else {
+						return Err(BaktuError::Unsupported {
+							path: path.to_path_buf(),
+							reason: format!("unknown mode {:o}", stx.stx_mode),
+						}
+						.into());
+					};
+					match mknod(
+						&dst_path,
+						kind,
+						perm,
+						libc::makedev(stx.stx_rdev_major, stx.stx_rdev_minor),
+					) {
+						Ok(_) => (),
+						Err(nix::errno::Errno::EPERM) => {
+							warn!("permission denied during mknod for {path:?}, skipping it");
+							exclusions.push(Exclusion {
+								path: path.to_path_buf(),
+								reason: "import-rsnapshot (mknod)".to_owned(),
+							});
+							*summary
+								.exclusions
+								.entry("import-rsnapshot".to_owned())
+								.or_default() += 1;
+							continue;
+						}
+						Err(e) => Err(io::Error::from(e)).at(&dst_path)?,
+					}
+				}
+				None => {
+					return Err(BaktuError::Unsupported {
+						path: path.to_path_buf(),
+						reason: format!(
+							"unknown file type {:o}",
+							stx.stx_mode as u32 & libc::S_IFMT
+						),
+					}
+					.into())
+				}
+			}
+
+			dump_meta(
+				&mut self.xattr_helper,
+				get_meta_sink(false, &dst_path, &self.meta_name)?,
+				path,
+				stx,
+				hash,
+				is_deduplicated,
+				true,
+			)?;
+
+			if let Some(ft) = FileType::from_mode(stx.stx_mode as u32) {
+				*summary.files.entry(ft.name().to_owned()).or_default() += 1;
+			}
+		}
+		Ok(exclusions)
+	}
+
+	/// Stores the regular file at `path` as `dst_path`, deduplicating it if possible, and returns
+	/// its hash and whether it was deduplicated
+	fn store(
+		&mut self,
+		path: &Path,
+		stx: &libc::statx,
+		dst_path: &Path,
+		summary: &mut Summary,
+	) -> Result<(blake3::Hash, bool), Box<dyn Error>> {
+		let key = FileKey::from_statx(stx)?;
+		let mtime = (stx.stx_mtime.tv_sec, stx.stx_mtime.tv_nsec);
+		if let Some(stored) = self
+			.stored
+			.get(&key)
+			.filter(|stored| stored.size == stx.stx_size && stored.mtime == mtime)
+		{
+			debug!("{path:?} is a hard link to an already stored file");
+			dedup::link(&stored.backing_path, dst_path)?;
+			summary.bytes_deduplicated += stx.stx_size;
+			return Ok((stored.hash, true));
+		}
+
+		summary.bytes_read += stx.stx_size;
+		let hash = file::b3sum(path)?;
+		if stx.stx_size < dedup::MIN_FILE_SIZE {
+			summary.bytes_written += fs::copy(path, dst_path)?;
+			return Ok((hash, false));
+		}
+
+		let (backing_path, deduplicated) = match self.dedup_index.find(hash, path)? {
+			Some(backing_path) => {
+				dedup::link(&backing_path, dst_path)?;
+				summary.bytes_deduplicated += stx.stx_size;
+				(backing_path, true)
+			}
+			None => {
+				summary.bytes_written += fs::copy(path, dst_path)?;
+				if self.dedup_index.insert(hash, dst_path.to_path_buf()) {
+					summary.new_unique_hashes += 1;
+				}
+				(dst_path.to_path_buf(), false)
+			}
+		};
+		self.stored.insert(
+			key,
+			Stored {
+				size: stx.stx_size,
+				mtime,
+				hash,
+				backing_path,
+			},
+		);
+		Ok((hash, deduplicated))
+	}
+}
@@ -0,0 +1,138 @@
+use std::{
+	error::Error,
+	fs,
+	io::{self, stdout, Write},
+	path::{Component, Path, PathBuf},
+};
+
+use chrono::Local;
+use exitcode::{NOINPUT, USAGE};
+
+use crate::{
+	cli::{die, human_bytes},
+	file::FileType,
+	repo::{meta_file::MetaRecord, site::Site, snapshot::Snapshot},
+};
+
+/// The last full record seen for the path, along with its symlink target, if any
+struct Version {
+	record: MetaRecord,
+	link_target: Option<PathBuf>,
+}
+
+impl Version {
+	fn load(snap: &Snapshot, path: &Path, record: MetaRecord) -> io::Result<Version> {
+		let link_target = match record.file_type {
+			Some(FileType::Lnk) => Some(fs::read_link(snap.data_dir().join(path))?),
+			_ => None,
+		};
+		Ok(Version {
+			record,
+			link_target,
+		})
+	}
+
+	fn content_differs(&self, other: &Version) -> bool {
+		self.record.file_type != other.record.file_type
+			|| self.record.b3sum != other.record.b3sum
+			|| self.link_target != other.link_target
+	}
+
+	fn describe(&self) -> String {
+		match (self.record.file_type, self.record.size) {
+			(Some(FileType::Reg), Some(size)) => format!("reg, {}", human_bytes(size)),
+			(Some(file_type), _) => file_type.name().to_string(),
+			(None, _) => "unknown type".to_string(),
+		}
+	}
+}
+
+pub fn exec(site: &Site, path: &Path) -> Result<(), Box<dyn Error>> {
+	if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+		die(
+			USAGE,
+			&format!(
+				"invalid path {path:?}, expected a relative path within the snapshots, exiting"
+			),
+		)
+	}
+
+	let mut out = stdout().lock();
+	let mut last: Option<Version> = None;
+	let mut present = false;
+
+	for snap in site.snapshots_sorted()? {
+		let Some(rec) = snap.record(path)? else {
+			if present {
+				log_line(&mut out, &snap, "deleted", "")?;
+				present = false;
+			}
+			continue;
+		};
+
+		// History interval records imply the path is unchanged since the previous snapshot, so
+		// there is no need to look any further
+		if rec.same_since.is_some() && present {
+			log_line(&mut out, &snap, "unchanged", "")?;
+			continue;
+		}
+
+		let cur = Version::load(&snap, path, snap.resolve_record(path, rec)?)?;
+		let (status, details) = match &last {
+			None => ("added", cur.describe()),
+			Some(prev) => {
+				let content_changed = prev.content_differs(&cur);
+				let mut changes = prev.record.metadata_changes(&cur.record);
+				if !present {
+					changes.insert(
+						0,
+						if content_changed {
+							"content changed"
+						} else {
+							"content unchanged"
+						},
+					);
+					("reappeared", changes.join(", "))
+				} else if content_changed {
+					("content", changes.join(", "))
+				} else if !changes.is_empty() {
+					("metadata", changes.join(", "))
+				} else {
+					("unchanged", String::new())
+				}
+			}
+		};
+		log_line(&mut out, &snap, status, &details)?;
+
+		last = Some(cur);
+		present = true;
+	}
+
+	if last.is_none() {
+		die(
+			NOINPUT,
+			&format!(
+				"{path:?} not found in any snapshot of {:?}, exiting",
+				site.name()
+			),
+		)
+	}
+	Ok(())
+}
+
+fn log_line(out: &mut impl Write, snap: &Snapshot, status: &str, details: &str) -> io::Result<()> {
+	let time = match snap.summary()? {
+		Some(summary) => summary
+			.start_time
+			.with_timezone(&Local)
+			.format("%Y-%m-%d %H:%M:%S")
+			.to_string(),
+		// Snapshots created before summaries were recorded
+		None => "????-??-?? ??:??:??".to_string(),
+	};
+	let line = format!(
+		"{:>4}  {time}  {status:<10}  {details}",
+		snap.name().to_string_lossy()
+	);
+	writeln!(out, "{}", line.trim_end())
+}
@@ -0,0 +1,190 @@
+use std::{
+	error::Error,
+	fs,
+	io::{stdout, Write},
+	os::unix::prelude::OsStrExt,
+	path::Path,
+};
+
+use chrono::Local;
+use exitcode::NOINPUT;
+
+use crate::{
+	cli::{die, human_bytes, repo_root_or_die, Location},
+	file::FileType,
+	repo::{meta_file::MetaRecord, site::Site, snapshot::Snapshot, Repo},
+};
+
+pub fn exec(location: Option<Location>) -> Result<(), Box<dyn Error>> {
+	match location {
+		None => list_sites(&Repo(repo_root_or_die()?)),
+		Some(Location {
+			site,
+			snapshot: None,
+			..
+		}) => list_snapshots(&site),
+		Some(Location {
+			snapshot: Some(snap),
+			path,
+			..
+		}) => list_path(&snap, &path),
+	}
+}
+
+fn list_sites(repo: &Repo) -> Result<(), Box<dyn Error>> {
+	let mut sites: Vec<Site> = repo.sites()?.into_iter().filter_map(Result::ok).collect();
+	sites.sort_by(|a, b| a.0.cmp(&b.0));
+
+	let mut out = stdout().lock();
+	for site in sites {
+		let cnt = site.snapshots_sorted()?.len();
+		out.write_all(site.name().as_bytes())?;
+		writeln!(out, "\t{cnt} snapshot{}", if cnt == 1 { "" } else { "s" })?;
+	}
+	Ok(())
+}
+
+fn list_snapshots(site: &Site) -> Result<(), Box<dyn Error>> {
+	let mut out = stdout().lock();
+	for snap in site.snapshots_sorted()? {
+		match snap.summary()? {
+			Some(summary) => writeln!(
+				out,
+				"{:>4}  {}  {:>8} paths  {:>10} ({} stored)",
+				snap.name().to_string_lossy(),
+				summary
+					.start_time
+					.with_timezone(&Local)
+					.format("%Y-%m-%d %H:%M:%S"),
+				summary.files_total(),
+				human_bytes(summary.bytes_read),
+				human_bytes(summary.bytes_written),
+			)?,
+			// Snapshots created before summaries were recorded
+			None => writeln!(
+				out,
+				"{:>4}  ????-??-?? ??:??:??         ? paths           ?",
+				snap.name().to_string_lossy(),
+			)?,
+		}
+	}
+	Ok(())
+}
+
+fn list_path(snap: &Snapshot, path: &Path) -> Result<(), Box<dyn Error>> {
+	let record = match snap.record(path)? {
+		Some(rec) => Some(snap.resolve_record(path, rec)?),
+		None if path.as_os_str().is_empty() => None,
+		None => die(
+			NOINPUT,
+			&format!("{path:?} not found in snapshot {:?}, exiting", snap.0),
+		),
+	};
+
+	let mut out = stdout().lock();
+	match record {
+		Some(rec) if rec.file_type != Some(FileType::Dir) => {
+			let parent = path.parent().unwrap_or(Path::new(""));
+			write_entry(&mut out, snap, parent, &rec)?
+		}
+		_ => {
+			for rec in snap.dir_records(path)? {
+				let rec =
+					snap.resolve_record(&path.join(std::ffi::OsStr::from_bytes(&rec.name)), rec)?;
+				write_entry(&mut out, snap, path, &rec)?
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Writes an `ls -l`-like line for `rec`, located in `rel_dir` of `snap`
+fn write_entry(
+	out: &mut impl Write,
+	snap: &Snapshot,
+	rel_dir: &Path,
+	rec: &MetaRecord,
+) -> Result<(), Box<dyn Error>> {
+	let size = match (rec.rdev_major, rec.rdev_minor) {
+		(Some(major), Some(minor)) => format!("{major}, {minor}"),
+		_ => rec.size.map_or("?".to_owned(), |size| size.to_string()),
+	};
+
+	let mtime = rec
+		.mtime
+		.and_then(|t| t.to_date_time())
+		.map_or("????-??-?? ??:??".to_owned(), |t| {
+			t.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string()
+		});
+
+	let opt = |value: Option<u32>| value.map_or("?".to_owned(), |v| v.to_string());
+
+	write!(
+		out,
+		"{} {:>3} {:>5} {:>5} {:>10} {} {} {}",
+		mode_string(rec.file_type, rec.mode),
+		opt(rec.nlink),
+		opt(rec.uid),
+		opt(rec.gid),
+		size,
+		mtime,
+		if rec.is_deduplicated { '=' } else { ' ' },
+		display_name(&rec.name)
+	)?;
+
+	if rec.file_type == Some(FileType::Lnk) {
+		let target = fs::read_link(
+			snap.data_dir()
+				.join(rel_dir)
+				.join(std::ffi::OsStr::from_bytes(&rec.name)),
+		)?;
+		write!(out, " -> {}", display_name(target.as_os_str().as_bytes()))?;
+	}
+
+	writeln!(out)?;
+	Ok(())
+}
+
+/// Formats the file type and permission bits like `ls -l`, e.g. `drwxr-xr-x`
+fn mode_string(file_type: Option<FileType>, mode: Option<u32>) -> String {
+	let type_char = match file_type {
+		Some(FileType::Fifo) => 'p',
+		Some(FileType::Chr) => 'c',
+		Some(FileType::Dir) => 'd',
+		Some(FileType::Blk) => 'b',
+		Some(FileType::Reg) => '-',
+		Some(FileType::Lnk) => 'l',
+		Some(FileType::Sock) => 's',
+		None => '?',
+	};
+
+	let Some(mode) = mode else {
+		return format!("{type_char}?????????");
+	};
+
+	let mut result = String::from(type_char);
+	for (shift, special, special_char) in [
+		(6, libc::S_ISUID, 's'),
+		(3, libc::S_ISGID, 's'),
+		(0, libc::S_ISVTX, 't'),
+	] {
+		let bits = (mode >> shift) & 0o7;
+		result.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+		result.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+		result.push(match (bits & 0o1 != 0, mode & special != 0) {
+			(true, true) => special_char,
+			(false, true) => special_char.to_ascii_uppercase(),
+			(true, false) => 'x',
+			(false, false) => '-',
+		});
+	}
+	result
+}
+
+/// Returns `name` as-is if it is printable UTF-8, escaped otherwise
+fn display_name(name: &[u8]) -> String {
+	match std::str::from_utf8(name) {
+		Ok(s) if !s.chars().any(char::is_control) => s.to_owned(),
+		_ => name.escape_ascii().to_string(),
+	}
+}
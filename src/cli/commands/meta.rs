@@ -0,0 +1,188 @@
+//! `baktu meta`, structured views of metadata records for use by other tools
+
+use std::{
+	error::Error,
+	io::{self, stdout, BufWriter, IsTerminal, Write},
+	os::unix::prelude::OsStrExt,
+	path::{Component, Path, PathBuf},
+};
+
+use clap::{Subcommand, ValueEnum};
+use exitcode::{NOINPUT, USAGE};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::{
+	cli::{die, snapshot_or_die},
+	repo::{meta_file::MetaRecord, snapshot::Snapshot},
+	util::bytes,
+};
+
+#[derive(Debug, Subcommand)]
+pub enum MetaCommand {
+	/// Print the metadata record of a path within a snapshot
+	Show {
+		#[command(flatten)]
+		output: OutputArgs,
+
+		/// Snapshot containing the path, as `<site>/<snapshot>`
+		snapshot: PathBuf,
+
+		/// Path within the snapshot
+		path: PathBuf,
+	},
+
+	/// Print all metadata records of a snapshot, directories before their entries
+	Dump {
+		#[command(flatten)]
+		output: OutputArgs,
+
+		/// Snapshot to dump, as `<site>/<snapshot>`
+		snapshot: PathBuf,
+	},
+
+	/// Print the JSON Schema of the records printed by `show` and `dump`
+	Schema,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct OutputArgs {
+	/// Output format
+	#[arg(long, value_enum, default_value_t = Format::Jsonl)]
+	format: Format,
+
+	/// Print the full records history interval records refer to, rather than the records as stored
+	#[arg(long)]
+	resolve: bool,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Format {
+	/// JSON Lines, one record per line, with byte strings as UTF-8 strings or base64
+	Jsonl,
+	/// A sequence of CBOR items (RFC 8742), one per record, with byte strings as CBOR byte strings
+	Cbor,
+}
+
+/// A metadata record along with its path, as printed by `baktu meta`
+#[derive(JsonSchema, Serialize)]
+#[schemars(title = "baktu metadata record")]
+struct Entry<'a> {
+	/// Path relative to the snapshot's data directory
+	#[serde(serialize_with = "bytes::serialize")]
+	#[schemars(with = "bytes::Bytes")]
+	path: &'a [u8],
+	#[serde(flatten)]
+	record: &'a MetaRecord,
+}
+
+pub fn exec(command: MetaCommand) -> Result<(), Box<dyn Error>> {
+	match command {
+		MetaCommand::Show {
+			output,
+			snapshot,
+			path,
+		} => {
+			let snap = snapshot_or_die(&snapshot)?;
+			if path.as_os_str().is_empty()
+				|| !path.components().all(|c| matches!(c, Component::Normal(_)))
+			{
+				die(
+					USAGE,
+					&format!(
+						"invalid path {path:?}, expected a relative path within the snapshot, \
+						exiting"
+					),
+				)
+			}
+			let Some(record) = snap.record(&path)? else {
+				die(
+					NOINPUT,
+					&format!("{path:?} not found in snapshot {:?}, exiting", snap.0),
+				)
+			};
+			let mut writer = Writer::new(&output, &snap)?;
+			writer.write(&path, record)?;
+			writer.finish()?;
+		}
+		MetaCommand::Dump { output, snapshot } => {
+			let snap = snapshot_or_die(&snapshot)?;
+			let mut writer = Writer::new(&output, &snap)?;
+			for record in snap.records()? {
+				let (path, record) = record?;
+				writer.write(&path, record)?;
+			}
+			writer.finish()?;
+		}
+		MetaCommand::Schema => println!("{}", schema()),
+	}
+	Ok(())
+}
+
+/// Returns the JSON Schema of the JSON Lines output, as included in the documentation
+fn schema() -> String {
+	serde_json::to_string_pretty(&schemars::schema_for!(Entry)).expect("schema serializes")
+}
+
+struct Writer<'a> {
+	format: Format,
+	resolve: bool,
+	snap: &'a Snapshot,
+	out: BufWriter<io::StdoutLock<'static>>,
+}
+
+impl<'a> Writer<'a> {
+	fn new(args: &OutputArgs, snap: &'a Snapshot) -> io::Result<Self> {
+		if matches!(args.format, Format::Cbor) && stdout().is_terminal() {
+			die(
+				USAGE,
+				"refusing to write CBOR to a terminal, use a redirection, exiting",
+			)
+		}
+		Ok(Writer {
+			format: args.format,
+			resolve: args.resolve,
+			snap,
+			out: BufWriter::new(stdout().lock()),
+		})
+	}
+
+	fn write(&mut self, path: &Path, record: MetaRecord) -> io::Result<()> {
+		let record = match self.resolve {
+			true => self.snap.resolve_record(path, record)?,
+			false => record,
+		};
+		let entry = Entry {
+			path: path.as_os_str().as_bytes(),
+			record: &record,
+		};
+		match self.format {
+			Format::Jsonl => {
+				serde_json::to_writer(&mut self.out, &entry)?;
+				writeln!(self.out)
+			}
+			Format::Cbor => ciborium::into_writer(&entry, &mut self.out).map_err(|e| match e {
+				ciborium::ser::Error::Io(e) => e,
+				e => io::Error::other(e.to_string()),
+			}),
+		}
+	}
+
+	fn finish(mut self) -> io::Result<()> {
+		self.out.flush()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn documented_schema() {
+		// Regenerate with `baktu meta schema > doc/mdbook/src/repositories/v1/meta.schema.json`
+		assert_eq!(
+			include_str!("../../../doc/mdbook/src/repositories/v1/meta.schema.json"),
+			schema() + "\n"
+		);
+	}
+}
@@ -0,0 +1,20 @@
+//! Implementations of subcommands, one module each
+
+pub mod cat;
+pub mod clone;
+pub mod diff;
+pub mod export;
+pub mod find;
+pub mod fsck;
+pub mod import_rsnapshot;
+pub mod log;
+pub mod ls;
+pub mod meta;
+pub mod parity;
+pub mod prune;
+pub mod pull;
+pub mod purge;
+pub mod show_config;
+pub mod signature;
+pub mod snap_from_tar;
+pub mod upgrade;
@@ -0,0 +1,139 @@
+use std::{
+	error::Error,
+	io::{stdout, Write},
+	path::PathBuf,
+};
+
+use clap::Subcommand;
+use exitcode::DATAERR;
+use log::info;
+
+use crate::{
+	cli::{die, human_bytes, snapshots_or_die, writable_or_die},
+	repo::{
+		parity::{self, Outcome},
+		snapshot::Snapshot,
+	},
+};
+
+#[derive(Debug, Subcommand)]
+pub enum ParityCommand {
+	/// Create recovery data for snapshots, replacing any existing one
+	///
+	/// The recovery data of each snapshot is written to the `parity/<snapshot>` directory of its
+	/// site, leaving the snapshot itself unmodified. It covers all regular files in the snapshot,
+	/// including its metadata files.
+	Create {
+		/// Size of the blocks files are split into. Damage is repaired in units of blocks, so
+		/// smaller blocks use recovery data more efficiently, at the cost of slower repairs
+		#[arg(long, value_name = "BYTES", default_value_t = 4096)]
+		block_size: u64,
+
+		/// Number of recovery blocks per 100 blocks of files, from 1 to 100
+		#[arg(long, value_name = "PERCENT", default_value_t = 10,
+			value_parser = clap::value_parser!(u64).range(1..=100))]
+		redundancy: u64,
+
+		/// What to protect, as `<site>[/<snapshot>]`. Protects all snapshots if omitted
+		location: Option<PathBuf>,
+	},
+
+	/// Repair damaged files from the recovery data of snapshots
+	///
+	/// Lists each damaged file as `<site>/<snapshot>/<path>`, followed by whether it was
+	/// repaired. Snapshots without recovery data are skipped.
+	Repair {
+		/// Only list the damaged files, without repairing them
+		#[arg(short('n'), long)]
+		dry_run: bool,
+
+		/// What to repair, as `<site>[/<snapshot>]`. Repairs all snapshots if omitted
+		location: Option<PathBuf>,
+	},
+}
+
+pub fn exec(command: ParityCommand) -> Result<(), Box<dyn Error>> {
+	match command {
+		ParityCommand::Create {
+			block_size,
+			redundancy,
+			location,
+		} => create(
+			snapshots_or_die(location.as_deref())?,
+			block_size,
+			redundancy,
+		),
+		ParityCommand::Repair { dry_run, location } => {
+			repair(snapshots_or_die(location.as_deref())?, dry_run)
+		}
+	}
+}
+
+fn check_writable(snapshots: &[(String, Snapshot)]) -> Result<(), Box<dyn Error>> {
+	if let Some((_, snap)) = snapshots.first() {
+		writable_or_die(&snap.site().repo())?;
+	}
+	Ok(())
+}
+
+fn create(
+	snapshots: Vec<(String, Snapshot)>,
+	block_size: u64,
+	redundancy: u64,
+) -> Result<(), Box<dyn Error>> {
+	check_writable(&snapshots)?;
+	let mut out = stdout().lock();
+	for (label, snap) in snapshots {
+		info!("creating recovery data for {label}");
+		let stats = parity::create(&snap, block_size, redundancy)?;
+		writeln!(
+			out,
+			"{label}\t{} files, {} blocks, {} recovery blocks ({})",
+			stats.files,
+			stats.blocks,
+			stats.recovery_blocks,
+			human_bytes(stats.recovery_blocks * block_size)
+		)?;
+	}
+	Ok(())
+}
+
+fn repair(snapshots: Vec<(String, Snapshot)>, dry_run: bool) -> Result<(), Box<dyn Error>> {
+	if !dry_run {
+		check_writable(&snapshots)?;
+	}
+	let mut out = stdout().lock();
+	let mut unrecoverable = 0;
+	for (label, snap) in snapshots {
+		let Some(damage) = parity::repair(&snap, dry_run)? else {
+			info!("no recovery data for {label}, skipping");
+			continue;
+		};
+		if damage.is_empty() {
+			writeln!(out, "{label}\tok")?;
+		}
+		for damaged in damage {
+			let outcome = match damaged.outcome {
+				Outcome::Repaired => "repaired",
+				Outcome::Repairable => "repairable",
+				Outcome::Unrecoverable => {
+					unrecoverable += 1;
+					"unrecoverable"
+				}
+			};
+			writeln!(
+				out,
+				"{label}/{}\t{outcome}",
+				damaged.rel_path.to_string_lossy()
+			)?;
+		}
+	}
+
+	if unrecoverable > 0 {
+		die(
+			DATAERR,
+			&format!("{unrecoverable} file(s) could not be repaired, exiting"),
+		)
+	}
+	Ok(())
+}
@@ -0,0 +1,147 @@
+use std::{
+	error::Error,
+	fs,
+	io::{self, stdout, Write},
+	path::Path,
+};
+
+use chrono::{DateTime, Local};
+use clap::Args;
+use exitcode::{DATAERR, USAGE};
+use log::info;
+
+use crate::{
+	cli::{die, writable_or_die},
+	repo::{
+		parity,
+		rehome::{DependencyIndex, Rehoming},
+		retention::Policy,
+		site::Site,
+		snapshot::Snapshot,
+		Repo,
+	},
+};
+
+#[derive(Debug, Args)]
+pub struct PruneArgs {
+	/// Keep the N latest snapshots
+	#[arg(long, value_name = "N", default_value_t = 0)]
+	keep_last: usize,
+
+	/// Keep the latest snapshot of each of the N latest days with snapshots
+	#[arg(long, value_name = "N", default_value_t = 0)]
+	keep_daily: usize,
+
+	/// Keep the latest snapshot of each of the N latest ISO weeks with snapshots
+	#[arg(long, value_name = "N", default_value_t = 0)]
+	keep_weekly: usize,
+
+	/// Keep the latest snapshot of each of the N latest months with snapshots
+	#[arg(long, value_name = "N", default_value_t = 0)]
+	keep_monthly: usize,
+
+	/// Only print the plan, without making any changes to the repository
+	#[arg(short('n'), long)]
+	dry_run: bool,
+}
+
+pub fn exec(site: &Site, args: PruneArgs) -> Result<(), Box<dyn Error>> {
+	let policy = Policy {
+		keep_last: args.keep_last,
+		keep_daily: args.keep_daily,
+		keep_weekly: args.keep_weekly,
+		keep_monthly: args.keep_monthly,
+	};
+	if policy.is_empty() {
+		die(
+			USAGE,
+			"no --keep-* option given, refusing to remove all snapshots, exiting",
+		)
+	}
+
+	writable_or_die(&site.repo())?;
+	let snapshots = site.snapshots_sorted()?;
+	let times = snapshots
+		.iter()
+		.map(snapshot_time)
+		.collect::<io::Result<Vec<_>>>()?;
+	let reasons = policy.reasons_to_keep(&times);
+
+	let mut out = stdout().lock();
+	let mut doomed = Vec::new();
+	for ((snap, time), reasons) in snapshots.into_iter().zip(&times).zip(reasons) {
+		let time = time.format("%Y-%m-%d %H:%M:%S");
+		let name = snap.name().to_string_lossy().into_owned();
+		if reasons.is_empty() {
+			writeln!(out, "remove  {name:>4}  {time}")?;
+			doomed.push(snap);
+		} else {
+			writeln!(out, "keep    {name:>4}  {time}  ({})", reasons.join(", "))?;
+		}
+	}
+	if doomed.is_empty() {
+		info!("nothing to prune");
+		return Ok(());
+	}
+
+	let repo = site.repo();
+	let rehomings = plan_or_die(&repo, |p| doomed.iter().any(|snap| p.starts_with(&snap.0)))?;
+	write_rehomings(&mut out, &repo, &rehomings)?;
+	if args.dry_run {
+		info!("dry run, not making any changes");
+		return Ok(());
+	}
+
+	for rehoming in &rehomings {
+		rehoming.apply()?;
+	}
+	for snap in doomed {
+		info!("removing snapshot {:?}", snap.0);
+		fs::remove_dir_all(&snap.0)?;
+		parity::remove(&snap)?;
+	}
+	Ok(())
+}
+
+/// Returns the creation time of the snapshot, falling back to its directory's mtime for snapshots
+/// created before summaries were recorded
+fn snapshot_time(snap: &Snapshot) -> io::Result<DateTime<Local>> {
+	Ok(match snap.summary()? {
+		Some(summary) => summary.start_time.with_timezone(&Local),
+		None => fs::metadata(&snap.0)?.modified()?.into(),
+	})
+}
+
+/// Plans the rehoming of backing files before removing all paths for which `is_doomed` returns
+/// true, exiting if that would break the repository
+pub(crate) fn plan_or_die(
+	repo: &Repo,
+	is_doomed: impl Fn(&Path) -> bool,
+) -> io::Result<Vec<Rehoming>> {
+	match DependencyIndex::build(repo)?.plan(is_doomed) {
+		Ok(rehomings) => Ok(rehomings),
+		Err(e) if e.kind() == io::ErrorKind::InvalidInput => die(DATAERR, &format!("{e}, exiting")),
+		Err(e) => Err(e),
+	}
+}
+
+/// Writes the rehomings with paths relative to the repository root
+pub(crate) fn write_rehomings(
+	out: &mut impl Write,
+	repo: &Repo,
+	rehomings: &[Rehoming],
+) -> io::Result<()> {
+	let rel = |p: &Path| p.strip_prefix(&repo.0).unwrap_or(p).to_path_buf();
+	for rehoming in rehomings {
+		writeln!(
+			out,
+			"move    {:?} -> {:?}",
+			rel(&rehoming.backing),
+			rel(&rehoming.new_home.link)
+		)?;
+		for link in &rehoming.relinked {
+			writeln!(out, "relink  {:?}", rel(link))?;
+		}
+	}
+	Ok(())
+}
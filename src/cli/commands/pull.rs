@@ -0,0 +1,134 @@
+use std::{
+	error::Error,
+	fs,
+	io::{self, stdout, Write},
+	path::Path,
+};
+
+use exitcode::{DATAERR, USAGE};
+use log::{debug, info, warn};
+
+use crate::{
+	cli::{die, human_bytes, repo_root_or_die, writable_or_die},
+	repo::{
+		site::{Site, CONFIG_FILE_NAMES},
+		snapshot::Snapshot,
+		transfer, Repo,
+	},
+};
+
+pub fn exec(src: &Path) -> Result<(), Box<dyn Error>> {
+	pull(&src_repo_or_die(src), &Repo(repo_root_or_die()?))
+}
+
+pub(crate) fn src_repo_or_die(src: &Path) -> Repo {
+	if !Repo::is_valid(src) {
+		die(
+			USAGE,
+			&format!("{src:?} is not a baktu repository, exiting"),
+		)
+	}
+	Repo(src.to_path_buf())
+}
+
+/// Copies the snapshots of `src` missing in `dst`, creating any missing sites along with their
+/// configuration
+pub(crate) fn pull(src: &Repo, dst: &Repo) -> Result<(), Box<dyn Error>> {
+	if fs::canonicalize(&src.0)? == fs::canonicalize(&dst.0)? {
+		die(
+			USAGE,
+			"source and destination are the same repository, exiting",
+		)
+	}
+	writable_or_die(dst)?;
+	// Snapshot content is only valid within repositories of the same format
+	let (src_tag, dst_tag) = (src.tag()?, dst.tag()?);
+	if src_tag.version != dst_tag.version
+		|| src_tag
+			.incompat
+			.iter()
+			.any(|f| !dst_tag.incompat.contains(f))
+	{
+		die(
+			DATAERR,
+			&format!(
+				"the format of {:?} (version {}, incompatible features {:?}) differs from that of \
+				{:?} (version {}, incompatible features {:?}), upgrade the older one first, exiting",
+				src.0, src_tag.version, src_tag.incompat, dst.0, dst_tag.version, dst_tag.incompat,
+			),
+		)
+	}
+
+	let mut sites: Vec<Site> = src.sites()?.into_iter().filter_map(Result::ok).collect();
+	sites.sort_by(|a, b| a.0.cmp(&b.0));
+
+	let mut out = stdout().lock();
+	let mut copied = Vec::new();
+	for src_site in sites {
+		let dst_site = Site(dst.sites_path().join(src_site.name()));
+		if !dst_site.0.exists() {
+			info!("creating site {:?}", src_site.name());
+			fs::create_dir(&dst_site.0)?;
+			for name in CONFIG_FILE_NAMES {
+				fs::copy(src_site.0.join(name), dst_site.0.join(name))?;
+			}
+			fs::create_dir(dst_site.snaps_path())?;
+		}
+
+		for snap in src_site.snapshots_sorted()? {
+			let dst_path = dst_site.snaps_path().join(snap.name());
+			if dst_path.exists() {
+				debug!("snapshot {dst_path:?} already present");
+				continue;
+			}
+
+			let stats = match transfer::copy_snapshot(&snap, &dst_path) {
+				Ok(stats) => stats,
+				Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+					die(DATAERR, &format!("{e}, exiting"))
+				}
+				Err(e) => return Err(e.into()),
+			};
+			writeln!(
+				out,
+				"{}/{}\t{} paths, {}, {} files verified",
+				src_site.name().to_string_lossy(),
+				snap.name().to_string_lossy(),
+				stats.files,
+				human_bytes(stats.bytes),
+				stats.verified,
+			)?;
+			copied.push(Snapshot(dst_path));
+		}
+	}
+
+	if copied.is_empty() {
+		info!("nothing to copy, destination is up to date");
+		return Ok(());
+	}
+
+	// Deduplicated files may refer to any snapshot of any site, so we can only check them once
+	// everything is copied
+	let mut dangling = 0;
+	for snap in &copied {
+		for rec_res in snap.records()? {
+			let (rel_path, rec) = rec_res?;
+			let path = snap.data_dir().join(rel_path);
+			if rec.is_deduplicated && !path.exists() {
+				warn!("deduplicated file {path:?} refers to a missing backing file");
+				dangling += 1;
+			}
+		}
+	}
+	if dangling > 0 {
+		die(
+			DATAERR,
+			&format!(
+				"{dangling} deduplicated file(s) in the copied snapshots refer to missing backing \
+				files, exiting"
+			),
+		)
+	}
+
+	Ok(())
+}
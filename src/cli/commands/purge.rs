@@ -0,0 +1,154 @@
+use std::{
+	collections::{BTreeMap, BTreeSet, HashSet},
+	error::Error,
+	fs,
+	io::{self, stdout, Write},
+	os::unix::prelude::OsStrExt,
+	path::{Path, PathBuf},
+};
+
+use clap::Args;
+use log::{info, warn};
+
+use crate::{
+	cli::{
+		commands::prune::{plan_or_die, write_rehomings},
+		writable_or_die,
+	},
+	repo::{merkle, meta_file::MetaFile, parity, signature, site::Site, snapshot::Snapshot},
+	util::glob::Glob,
+};
+
+#[derive(Debug, Args)]
+pub struct PurgeArgs {
+	/// Name of the site
+	pub site: PathBuf,
+
+	/// Shell glob matched against paths within the snapshots, e.g. 'home/user/*.iso'. Wildcards do
+	/// not match `/`. Matching directories are purged along with everything below them
+	glob: String,
+
+	/// Only print the plan, without making any changes to the repository
+	#[arg(short('n'), long)]
+	dry_run: bool,
+}
+
+pub fn exec(site: &Site, args: PurgeArgs) -> Result<(), Box<dyn Error>> {
+	writable_or_die(&site.repo())?;
+	let glob = Glob::new(&args.glob)?;
+
+	// Matching paths, along with the meta files containing their records
+	let mut doomed: Vec<(PathBuf, MetaFile)> = Vec::new();
+	let snapshots = site.snapshots_sorted()?;
+	for snap in &snapshots {
+		let data_dir = snap.data_dir();
+		let meta_name = snap.meta_name()?;
+		for rec_res in snap.records()? {
+			let (rel_path, _) = rec_res?;
+			if glob.matches_path(rel_path.as_os_str().as_bytes()) {
+				let path = data_dir.join(rel_path);
+				let meta_file = MetaFile(path.with_file_name(&meta_name));
+				doomed.push((path, meta_file));
+			}
+		}
+	}
+
+	// Paths within matching directories are removed along with them. Sorting puts directories
+	// right before their contents
+	doomed.sort_by(|a, b| a.0.cmp(&b.0));
+	let mut outermost: Vec<(PathBuf, MetaFile)> = Vec::new();
+	for entry in doomed {
+		if outermost
+			.last()
+			.is_some_and(|(dir, _)| entry.0.starts_with(dir))
+		{
+			continue;
+		}
+		outermost.push(entry);
+	}
+	let doomed = outermost;
+	if doomed.is_empty() {
+		warn!(
+			"no paths matching {:?} in site {:?}",
+			args.glob,
+			site.name()
+		);
+		return Ok(());
+	}
+
+	let repo = site.repo();
+	let rehomings = plan_or_die(&repo, |p| doomed.iter().any(|(d, _)| p.starts_with(d)))?;
+
+	let mut out = stdout().lock();
+	for (path, _) in &doomed {
+		writeln!(
+			out,
+			"purge   {:?}",
+			path.strip_prefix(&repo.0).unwrap_or(path)
+		)?;
+	}
+	write_rehomings(&mut out, &repo, &rehomings)?;
+	if args.dry_run {
+		info!("dry run, not making any changes");
+		return Ok(());
+	}
+
+	for rehoming in &rehomings {
+		rehoming.apply()?;
+	}
+
+	let mut names_by_meta_file: BTreeMap<PathBuf, HashSet<Vec<u8>>> = BTreeMap::new();
+	for (path, meta_file) in &doomed {
+		info!("removing {path:?}");
+		remove_path(path)?;
+		let name = path.file_name().expect("records have names").as_bytes();
+		names_by_meta_file
+			.entry(meta_file.0.clone())
+			.or_default()
+			.insert(name.to_vec());
+	}
+	let mut modified = BTreeSet::new();
+	for (meta_path, names) in names_by_meta_file {
+		info!("removing {} record(s) from {meta_path:?}", names.len());
+		let meta_file = MetaFile(meta_path);
+		let mut records = meta_file.records()?;
+		records.retain(|rec| !rec.name().is_some_and(|name| names.contains(&name)));
+		meta_file.write_records(&records)?;
+
+		let snap = snapshots
+			.iter()
+			.find(|snap| meta_file.0.starts_with(&snap.0))
+			.expect("purged paths are within the site's snapshots");
+		let dir = meta_file.0.parent().expect("meta file has parent");
+		merkle::reseal_from(snap, dir)?;
+		modified.insert(snap.0.clone());
+	}
+
+	for snap_path in modified {
+		if snap_path.join(signature::FNAME).exists() {
+			warn!("the signature of {snap_path:?} no longer holds, as its root hash changed");
+		}
+		if parity::remove(&Snapshot(snap_path.clone()))? {
+			warn!(
+				"removed the recovery data of {snap_path:?}, as its files changed, run \
+				`baktu parity create` to recreate it"
+			);
+		}
+	}
+
+	Ok(())
+}
+
+/// Removes `path` and everything below it. History interval records have no data path in most
+/// snapshots, so a missing path is not an error.
+fn remove_path(path: &Path) -> io::Result<()> {
+	let res = match fs::symlink_metadata(path) {
+		Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+		Ok(_) => fs::remove_file(path),
+		Err(e) => Err(e),
+	};
+	match res {
+		Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+		res => res,
+	}
+}
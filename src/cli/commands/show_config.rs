@@ -0,0 +1,48 @@
+use std::{
+	fs,
+	io::{self, stdout, Write},
+};
+
+use exitcode::NOINPUT;
+
+use crate::{
+	cli::die,
+	repo::{site, snapshot, snapshot::Snapshot},
+	util::{dsv, nsv},
+};
+
+/// Prints the site configuration files and `baktu snap` arguments recorded in `snap`. NSV entries
+/// are printed one per line, thus entries containing newlines will be ambiguous.
+pub fn exec(snap: &Snapshot) -> io::Result<()> {
+	let dir = snap.site_config_dir();
+	if !dir.is_dir() {
+		die(
+			NOINPUT,
+			&format!(
+				"{:?} contains no recorded configuration, as it was created by an older version of \
+				baktu, exiting",
+				snap.0
+			),
+		)
+	}
+
+	let mut out = stdout().lock();
+
+	for name in site::CONFIG_FILE_NAMES {
+		writeln!(out, "# {name}")?;
+		if name.ends_with(".nsv") {
+			for entry in dsv::vec_from_file(dir.join(name), nsv::SEP)? {
+				out.write_all(&entry)?;
+				writeln!(out)?;
+			}
+		} else {
+			out.write_all(&fs::read(dir.join(name))?)?;
+		}
+		writeln!(out)?;
+	}
+
+	writeln!(out, "# {}", snapshot::SNAP_ARGS_FNAME)?;
+	out.write_all(&fs::read(dir.join(snapshot::SNAP_ARGS_FNAME))?)?;
+
+	Ok(())
+}
@@ -0,0 +1,83 @@
+use std::{
+	error::Error,
+	fs, io,
+	io::{stdout, Write},
+	path::Path,
+};
+
+use ed25519_dalek::VerifyingKey;
+use exitcode::{CANTCREAT, DATAERR, NOINPUT};
+
+use crate::{
+	cli::die,
+	repo::{merkle, signature, snapshot::Snapshot},
+	util::hex,
+};
+
+pub fn gen_key(secret_path: &Path) -> Result<(), Box<dyn Error>> {
+	match signature::generate_key(secret_path) {
+		Ok(public_key) => {
+			let hex = String::from_utf8(hex::encode(public_key.as_bytes()))?;
+			println!("{hex}");
+			Ok(())
+		}
+		Err(e) if e.kind() == io::ErrorKind::AlreadyExists => die(
+			CANTCREAT,
+			&format!("key file already exists, refusing to overwrite it: {e}, exiting"),
+		),
+		Err(e) => Err(e.into()),
+	}
+}
+
+/// Returns the public key given either directly, or as the path of a file containing it
+fn public_key_or_die(spec: &str) -> io::Result<VerifyingKey> {
+	if let Ok(key) = signature::parse_public_key(spec) {
+		return Ok(key);
+	}
+	match fs::read_to_string(spec) {
+		Ok(data) => match signature::parse_public_key(&data) {
+			Ok(key) => Ok(key),
+			Err(e) => die(DATAERR, &format!("{e} in {spec:?}, exiting")),
+		},
+		Err(e) if e.kind() == io::ErrorKind::NotFound => die(
+			NOINPUT,
+			&format!("{spec:?} is neither a public key nor a file, exiting"),
+		),
+		Err(e) => Err(e),
+	}
+}
+
+/// Verifies the signatures of the `(label, snapshot)` pairs, along with the metadata covered by
+/// the signed root hashes
+pub fn verify(
+	snapshots: Vec<(String, Snapshot)>,
+	public_key_spec: &str,
+) -> Result<(), Box<dyn Error>> {
+	let public_key = public_key_or_die(public_key_spec)?;
+
+	let mut out = stdout().lock();
+	let mut failed = 0;
+	for (label, snap) in snapshots {
+		let problem = match signature::verify(&snap, &public_key)? {
+			Err(reason) => Some(reason),
+			Ok(()) => merkle::verify(&snap)?
+				.first()
+				.map(|problem| format!("{:?}: {}", problem.path, problem.message)),
+		};
+		match problem {
+			None => writeln!(out, "{label}\tok")?,
+			Some(problem) => {
+				writeln!(out, "{label}\t{problem}")?;
+				failed += 1;
+			}
+		}
+	}
+
+	if failed > 0 {
+		die(
+			DATAERR,
+			&format!("{failed} snapshot(s) failed verification, exiting"),
+		)
+	}
+	Ok(())
+}
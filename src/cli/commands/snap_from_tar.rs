@@ -0,0 +1,635 @@
+//! `baktu snap --from-tar`, creating snapshots from tar archives rather than the include paths
+
+use std::{
+	collections::{BTreeMap, HashMap},
+	error::Error,
+	ffi::{OsStr, OsString},
+	fs::{self, File, OpenOptions},
+	io::{self, stdin, BufReader, BufWriter, ErrorKind, Read, Write},
+	os::unix::prelude::OsStrExt,
+	path::{Component, Path, PathBuf},
+	time::Instant,
+};
+
+use chrono::Utc;
+use exitcode::{NOINPUT, USAGE};
+use log::{debug, info, warn};
+use nix::sys::stat::{mknod, Mode, SFlag};
+use tar::{Entry, EntryType, Header};
+
+use crate::{
+	cli::{create_snapshot, die, discard_on_error, finish_snapshot, human_bytes, SnapArgs},
+	error::{IoResultExt, Result},
+	file::{self, FileType},
+	repo::{
+		self, dedup,
+		meta_file::{MetaRecord, Timestamp},
+		site::{Config, Site},
+		snapshot::{self, Exclusion, Snapshot},
+		summary::Summary,
+	},
+	Error as BaktuError,
+};
+
+/// Prefix of the pax records holding extended attributes, as written by GNU tar and bsdtar
+const PAX_XATTR_PREFIX: &str = "SCHILY.xattr.";
+
+pub fn exec(
+	site: &Site,
+	cfg: &SnapArgs,
+	source: &Path,
+	print_summary: bool,
+) -> Result<(), Box<dyn Error>> {
+	let mut summary = Summary::new(Utc::now());
+	let wall_clock = Instant::now();
+
+	let site_conf = site.get_config()?;
+	if !site_conf.hooks.is_empty() {
+		die(
+			USAGE,
+			&format!(
+				"the site's `{}` has hooks, which are not run for snapshots created from \
+				archives, exiting",
+				repo::site::config_file::NAME
+			),
+		)
+	}
+	let signing_key = site_conf.signing_key()?;
+
+	let input: Box<dyn Read> = if source == Path::new("-") {
+		Box::new(stdin().lock())
+	} else {
+		match File::open(source) {
+			Ok(file) => Box::new(file),
+			Err(e) => die(NOINPUT, &format!("unable to open {source:?}: {e}, exiting")),
+		}
+	};
+
+	let meta_name: OsString = snapshot::DEFAULT_META_NAME.into();
+	let dedup_index = dedup::Index::load(&site.repo())?;
+	let snap = create_snapshot(site, cfg, &meta_name)?;
+	let result = (|| -> Result<(), Box<dyn Error>> {
+		let mut builder = Builder {
+			cfg,
+			site_conf: &site_conf,
+			dedup_index,
+			snap: snap.clone(),
+			meta_name,
+			records: BTreeMap::new(),
+			stored: HashMap::new(),
+			exclusions: Vec::new(),
+			summary: &mut summary,
+		};
+
+		info!("reading archive {source:?}");
+		for entry in tar::Archive::new(BufReader::new(input)).entries()? {
+			builder.add(entry?)?;
+		}
+		builder.write_records()?;
+		let exclusions = builder.exclusions;
+
+		summary.end_time = Utc::now();
+		summary.wall_time_secs = wall_clock.elapsed().as_secs_f64();
+		finish_snapshot(&snap, &exclusions, &summary, signing_key.as_ref())
+	})();
+	discard_on_error(&snap, result)?;
+
+	if print_summary {
+		eprintln!(
+			"snapshot {:?} created from {source:?} in {:.1}s: {} paths processed ({}), {} excluded",
+			snap.0,
+			summary.wall_time_secs,
+			summary.files_total(),
+			summary
+				.files
+				.iter()
+				.map(|(file_type, cnt)| format!("{cnt} {file_type}"))
+				.collect::<Vec<_>>()
+				.join(", "),
+			summary.exclusions_total(),
+		);
+		eprintln!(
+			"read {}, wrote {}, deduplicated {}, {} new unique hashes",
+			human_bytes(summary.bytes_read),
+			human_bytes(summary.bytes_written),
+			human_bytes(summary.bytes_deduplicated),
+			summary.new_unique_hashes
+		);
+	}
+
+	info!("snapshot subcommand done");
+
+	Ok(())
+}
+
+/// A regular file stored in the snapshot, by its path relative to the data directory
+struct Stored {
+	hash: blake3::Hash,
+	size: u64,
+	/// Absolute path of the file holding the content
+	backing_path: PathBuf,
+	/// Whether the content is held by the file itself, making it a backing file for
+	/// deduplication
+	is_backing: bool,
+	/// Whether later files were deduplicated against it
+	is_linked: bool,
+}
+
+struct Builder<'a> {
+	cfg: &'a SnapArgs,
+	site_conf: &'a Config,
+	dedup_index: dedup::Index,
+	snap: Snapshot,
+	meta_name: OsString,
+	/// Records by the relative path of their directory, then by name. Written once the whole
+	/// archive is read, as entries may appear in any order, and more than once.
+	records: BTreeMap<PathBuf, BTreeMap<Vec<u8>, MetaRecord>>,
+	stored: HashMap<PathBuf, Stored>,
+	exclusions: Vec<Exclusion>,
+	summary: &'a mut Summary,
+}
+
+impl Builder<'_> {
+	fn add<R: Read>(&mut self, mut entry: Entry<R>) -> Result<()> {
+		let entry_type = entry.header().entry_type();
+		let archive_path = entry.path_bytes().into_owned();
+		if entry_type.is_pax_global_extensions() {
+			debug!("ignoring global extended header");
+			return Ok(());
+		}
+
+		let Some(rel_path) = rel_path(&archive_path) else {
+			return Err(BaktuError::Unsupported {
+				path: PathBuf::from(OsStr::from_bytes(&archive_path)),
+				reason: "archive entry is outside of the archive root".to_owned(),
+			});
+		};
+		let Some(name) = rel_path.file_name().map(|name| name.as_bytes().to_vec()) else {
+			debug!("skipping entry for the archive root");
+			return Ok(());
+		};
+		if name == self.meta_name.as_bytes() {
+			return Err(BaktuError::Unsupported {
+				path: rel_path,
+				reason: format!(
+					"archive entry is named {:?}, which is used for metadata",
+					self.meta_name
+				),
+			});
+		}
+		debug!("processing archive entry {rel_path:?}");
+
+		let pax = match entry.pax_extensions()? {
+			Some(extensions) => extensions
+				.map(|ext| {
+					let ext = ext?;
+					let key = ext
+						.key()
+						.map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+					Ok((key.to_owned(), ext.value_bytes().to_vec()))
+				})
+				.collect::<io::Result<Vec<_>>>()?,
+			None => Vec::new(),
+		};
+		let mut rec = record(name, entry.header(), &pax)?;
+
+		self.add_parents(&rel_path)?;
+		let dst_path = self.snap.data_dir().join(&rel_path);
+		self.replace(&rel_path, &dst_path, entry_type == EntryType::Directory)?;
+
+		match entry_type {
+			EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => {
+				rec.file_type = Some(FileType::Reg);
+				self.store(&mut entry, &rel_path, &dst_path, &mut rec)?;
+			}
+			EntryType::Link => {
+				rec.file_type = Some(FileType::Reg);
+				let target = entry.link_name_bytes().unwrap_or_default().into_owned();
+				self.hard_link(&target, &rel_path, &dst_path, &mut rec)?;
+			}
+			EntryType::Symlink => {
+				rec.file_type = Some(FileType::Lnk);
+				let target = entry.link_name_bytes().unwrap_or_default().into_owned();
+				std::os::unix::fs::symlink(OsStr::from_bytes(&target), &dst_path)?;
+			}
+			EntryType::Directory => {
+				rec.file_type = Some(FileType::Dir);
+				if !dst_path.is_dir() {
+					fs::create_dir(&dst_path)?;
+				}
+			}
+			EntryType::Char | EntryType::Block | EntryType::Fifo => {
+				let (file_type, kind) = match entry_type {
+					EntryType::Char => (FileType::Chr, SFlag::S_IFCHR),
+					EntryType::Block => (FileType::Blk, SFlag::S_IFBLK),
+					_ => (FileType::Fifo, SFlag::S_IFIFO),
+				};
+				rec.file_type = Some(file_type);
+				if !self.make_node(&rel_path, &dst_path, kind, &rec)? {
+					return Ok(());
+				}
+			}
+			other => {
+				warn!("skipping archive entry {rel_path:?} of unsupported type {other:?}");
+				return Ok(());
+			}
+		}
+
+		let parent = rel_path.parent().unwrap_or(Path::new("")).to_path_buf();
+		self.records
+			.entry(parent)
+			.or_default()
+			.insert(rec.name.clone(), rec);
+		Ok(())
+	}
+
+	/// Creates the directories leading to `rel_path` that have no entries of their own yet,
+	/// recording them as directories with unknown metadata. Refuses paths within anything but
+	/// directories, as a symlink from an earlier entry could lead outside of the snapshot.
+	fn add_parents(&mut self, rel_path: &Path) -> Result<()> {
+		let mut ancestors: Vec<_> = rel_path.ancestors().skip(1).collect();
+		ancestors.pop(); // The data directory itself
+		for dir in ancestors.into_iter().rev() {
+			let dst_path = self.snap.data_dir().join(dir);
+			match fs::symlink_metadata(&dst_path) {
+				Ok(metadata) if metadata.is_dir() => continue,
+				Ok(_) => {
+					return Err(BaktuError::Unsupported {
+						path: rel_path.to_path_buf(),
+						reason: format!(
+							"archive entry is within {dir:?}, which is not a directory"
+						),
+					})
+				}
+				Err(e) if e.kind() == ErrorKind::NotFound => (),
+				Err(e) => return Err(e).at(&dst_path),
+			}
+			debug!("creating {dir:?}, which has no archive entry of its own");
+			fs::create_dir(&dst_path)?;
+			let name = dir.file_name().expect("ancestor has a name").as_bytes();
+			self.records
+				.entry(dir.parent().unwrap_or(Path::new("")).to_path_buf())
+				.or_default()
+				.insert(
+					name.to_vec(),
+					MetaRecord {
+						name: name.to_vec(),
+						is_approximate: true,
+						file_type: Some(FileType::Dir),
+						..Default::default()
+					},
+				);
+		}
+		Ok(())
+	}
+
+	/// Removes what an earlier entry for `rel_path` created, as later entries replace earlier ones
+	/// on extraction. Directories are kept if replaced by a directory.
+	fn replace(&mut self, rel_path: &Path, dst_path: &Path, is_dir: bool) -> Result<()> {
+		let metadata = match fs::symlink_metadata(dst_path) {
+			Ok(metadata) => metadata,
+			Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+			Err(e) => return Err(e).at(dst_path),
+		};
+		if metadata.is_dir() && is_dir {
+			return Ok(());
+		}
+
+		debug!("replacing earlier archive entry {rel_path:?}");
+		if metadata.is_dir() {
+			if self.stored.keys().any(|path| path.starts_with(rel_path)) {
+				return Err(BaktuError::Unsupported {
+					path: rel_path.to_path_buf(),
+					reason: "archive replaces the directory along with the files in it".to_owned(),
+				});
+			}
+			fs::remove_dir_all(dst_path)?;
+			self.records.retain(|dir, _| !dir.starts_with(rel_path));
+		} else {
+			if let Some(stored) = self.stored.remove(rel_path) {
+				if stored.is_linked {
+					return Err(BaktuError::Unsupported {
+						path: rel_path.to_path_buf(),
+						reason: "archive replaces the file after other files were deduplicated \
+							against it"
+							.to_owned(),
+					});
+				}
+				if stored.is_backing {
+					self.dedup_index.remove(stored.hash, &stored.backing_path);
+				}
+			}
+			fs::remove_file(dst_path)?;
+		}
+		if let Some(records) = self
+			.records
+			.get_mut(rel_path.parent().unwrap_or(Path::new("")))
+		{
+			records.remove(rel_path.file_name().unwrap_or_default().as_bytes());
+		}
+		Ok(())
+	}
+
+	/// Writes the content of a regular file entry as `dst_path`, deduplicating it if possible
+	fn store<R: Read>(
+		&mut self,
+		entry: &mut Entry<R>,
+		rel_path: &Path,
+		dst_path: &Path,
+		rec: &mut MetaRecord,
+	) -> io::Result<()> {
+		let mut out = BufWriter::new(create_new(dst_path)?);
+		let (size, hash) = file::copy_hashed(entry, &mut out)?;
+		out.into_inner().map_err(|e| e.into_error())?;
+		self.summary.bytes_read += size;
+		rec.size = Some(size);
+		rec.b3sum = Some(hash);
+
+		let mut stored = Stored {
+			hash,
+			size,
+			backing_path: dst_path.to_path_buf(),
+			is_backing: false,
+			is_linked: false,
+		};
+		if size < dedup::MIN_FILE_SIZE {
+			debug!(
+				"skipping deduplication of file smaller than {} bytes",
+				dedup::MIN_FILE_SIZE
+			);
+			self.summary.bytes_written += size;
+		} else {
+			match self.dedup_index.find(hash, dst_path)? {
+				Some(backing_path) => {
+					fs::remove_file(dst_path)?;
+					dedup::link(&backing_path, dst_path)?;
+					self.mark_linked(&backing_path);
+					rec.is_deduplicated = true;
+					self.summary.bytes_deduplicated += size;
+					stored.backing_path = backing_path;
+				}
+				None => {
+					self.summary.bytes_written += size;
+					if self.dedup_index.insert(hash, dst_path.to_path_buf()) {
+						self.summary.new_unique_hashes += 1;
+					}
+					stored.is_backing = true;
+				}
+			}
+		}
+		self.stored.insert(rel_path.to_path_buf(), stored);
+		Ok(())
+	}
+
+	/// Represents a hard link entry as a copy of the file it links to, deduplicated if possible
+	fn hard_link(
+		&mut self,
+		target: &[u8],
+		rel_path: &Path,
+		dst_path: &Path,
+		rec: &mut MetaRecord,
+	) -> Result<()> {
+		let Some(target) = self.stored.get(&self::rel_path(target).unwrap_or_default()) else {
+			return Err(BaktuError::Unsupported {
+				path: rel_path.to_path_buf(),
+				reason: format!(
+					"archive entry is a hard link to {:?}, which is not a regular file earlier in \
+					the archive",
+					OsStr::from_bytes(target)
+				),
+			});
+		};
+		let (hash, size, backing_path) = (target.hash, target.size, target.backing_path.clone());
+		rec.size = Some(size);
+		rec.b3sum = Some(hash);
+
+		let is_deduplicated = size >= dedup::MIN_FILE_SIZE;
+		if is_deduplicated {
+			dedup::link(&backing_path, dst_path)?;
+			self.mark_linked(&backing_path);
+			rec.is_deduplicated = true;
+			self.summary.bytes_deduplicated += size;
+		} else {
+			self.summary.bytes_written +=
+				io::copy(&mut File::open(&backing_path)?, &mut create_new(dst_path)?)?;
+		}
+		self.stored.insert(
+			rel_path.to_path_buf(),
+			Stored {
+				hash,
+				size,
+				backing_path: if is_deduplicated {
+					backing_path
+				} else {
+					dst_path.to_path_buf()
+				},
+				is_backing: false,
+				is_linked: false,
+			},
+		);
+		Ok(())
+	}
+
+	/// Notes that a file was deduplicated against `backing_path`, if it is part of this snapshot
+	fn mark_linked(&mut self, backing_path: &Path) {
+		if let Ok(rel_path) = backing_path.strip_prefix(self.snap.data_dir()) {
+			if let Some(stored) = self.stored.get_mut(rel_path) {
+				stored.is_linked = true;
+			}
+		}
+	}
+
+	/// Creates a device or FIFO, returning false if it was excluded instead
+	fn make_node(
+		&mut self,
+		rel_path: &Path,
+		dst_path: &Path,
+		kind: SFlag,
+		rec: &MetaRecord,
+	) -> Result<bool> {
+		match mknod(
+			dst_path,
+			kind,
+			Mode::from_bits_truncate(rec.mode.unwrap_or(0o600)),
+			libc::makedev(rec.rdev_major.unwrap_or(0), rec.rdev_minor.unwrap_or(0)),
+		) {
+			Ok(()) => Ok(true),
+			Err(nix::errno::Errno::EPERM)
+				if self.site_conf.exclude.all_eacces && self.cfg.confirm_exclude_all_eacces =>
+			{
+				let reason = repo::site::config_file::NAME.to_owned() + "/exclude.all_eacces";
+				info!("excluding {rel_path:?} due to {reason} (mknod)");
+				*self.summary.exclusions.entry(reason.clone()).or_default() += 1;
+				self.exclusions.push(Exclusion {
+					path: rel_path.to_path_buf(),
+					reason: reason + " (mknod)",
+				});
+				Ok(false)
+			}
+			Err(nix::errno::Errno::EPERM) => Err(BaktuError::Permission {
+				path: rel_path.to_path_buf(),
+				action: "mknod".to_owned(),
+			}),
+			Err(e) => Err(io::Error::from(e)).at(dst_path),
+		}
+	}
+
+	/// Writes the metadata files of all directories, and counts the recorded paths by type
+	fn write_records(&mut self) -> io::Result<()> {
+		for (rel_dir, records) in &self.records {
+			let meta_path = self.snap.data_dir().join(rel_dir).join(&self.meta_name);
+			debug!("writing metadata to {meta_path:?}");
+			let mut out = BufWriter::new(File::create(meta_path)?);
+			for rec in records.values() {
+				rec.write(&mut out)?;
+				if let Some(ft) = rec.file_type {
+					*self.summary.files.entry(ft.name().to_owned()).or_default() += 1;
+				}
+			}
+			out.flush()?;
+		}
+		Ok(())
+	}
+}
+
+/// Creates the file at `dst_path`, failing rather than following a symlink left in its place
+fn create_new(dst_path: &Path) -> io::Result<File> {
+	OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.open(dst_path)
+}
+
+/// Returns the path of an archive entry relative to the data directory, or `None` if it would be
+/// outside of it. Leading `/` and `.` components are dropped, as tar does when extracting.
+fn rel_path(archive_path: &[u8]) -> Option<PathBuf> {
+	let mut rel_path = PathBuf::new();
+	for component in Path::new(OsStr::from_bytes(archive_path)).components() {
+		match component {
+			Component::Normal(name) => rel_path.push(name),
+			Component::RootDir | Component::CurDir => (),
+			Component::ParentDir | Component::Prefix(_) => return None,
+		}
+	}
+	Some(rel_path)
+}
+
+/// Returns the record of an entry, with everything but its type and content taken from its
+/// header and `pax` extended header records
+fn record(name: Vec<u8>, header: &Header, pax: &[(String, Vec<u8>)]) -> io::Result<MetaRecord> {
+	let pax_value = |key: &str| {
+		pax.iter()
+			.find(|(k, _)| k == key)
+			.and_then(|(_, value)| std::str::from_utf8(value).ok())
+	};
+	let invalid = |field: &str| {
+		io::Error::new(
+			ErrorKind::InvalidData,
+			format!(
+				"invalid {field} in archive entry {:?}",
+				OsStr::from_bytes(&name)
+			),
+		)
+	};
+	let id = |key: &str, header_value: io::Result<u64>| -> io::Result<u32> {
+		match pax_value(key) {
+			Some(value) => value.parse().map_err(|_| invalid(key)),
+			None => u32::try_from(header_value?).map_err(|_| invalid(key)),
+		}
+	};
+	let time = |key: &str| pax_value(key).and_then(parse_pax_time);
+	let is_device = matches!(header.entry_type(), EntryType::Char | EntryType::Block);
+	let gnu_time = |time: Option<io::Result<u64>>| {
+		time.and_then(Result::ok)
+			.filter(|sec| *sec != 0)
+			.and_then(|sec| i64::try_from(sec).ok())
+			.map(|sec| Timestamp { sec, nsec: 0 })
+	};
+
+	Ok(MetaRecord {
+		uid: Some(id("uid", header.uid())?),
+		gid: Some(id("gid", header.gid())?),
+		mode: Some(header.mode()? & 0o7777),
+		mtime: match time("mtime") {
+			Some(mtime) => Some(mtime),
+			None => Some(Timestamp {
+				sec: i64::try_from(header.mtime()?).map_err(|_| invalid("mtime"))?,
+				nsec: 0,
+			}),
+		},
+		atime: time("atime").or_else(|| gnu_time(header.as_gnu().map(|gnu| gnu.atime()))),
+		ctime: time("ctime").or_else(|| gnu_time(header.as_gnu().map(|gnu| gnu.ctime()))),
+		// Only meaningful for devices, and left blank by some archivers otherwise
+		rdev_major: if is_device {
+			header.device_major()?
+		} else {
+			None
+		},
+		rdev_minor: if is_device {
+			header.device_minor()?
+		} else {
+			None
+		},
+		xattrs: pax
+			.iter()
+			.filter_map(|(key, value)| {
+				key.strip_prefix(PAX_XATTR_PREFIX)
+					.map(|key| (key.as_bytes().to_vec(), value.clone()))
+			})
+			.collect(),
+		name,
+		..Default::default()
+	})
+}
+
+/// Parses a pax timestamp, i.e. decimal seconds that may be negative or have a fractional part
+fn parse_pax_time(value: &str) -> Option<Timestamp> {
+	let (negative, digits) = match value.strip_prefix('-') {
+		Some(digits) => (true, digits),
+		None => (false, value),
+	};
+	let (sec, frac) = digits.split_once('.').unwrap_or((digits, ""));
+	if sec.is_empty() || !sec.bytes().chain(frac.bytes()).all(|c| c.is_ascii_digit()) {
+		return None;
+	}
+	let sec: i64 = sec.parse().ok()?;
+	// Digits beyond nanoseconds are truncated
+	let nsec: u32 = format!("{:0<9}", &frac[..frac.len().min(9)]).parse().ok()?;
+
+	Some(match (negative, nsec) {
+		(false, _) => Timestamp { sec, nsec },
+		(true, 0) => Timestamp { sec: -sec, nsec },
+		// Recorded timestamps are rounded down, with a positive nanosecond part
+		(true, _) => Timestamp {
+			sec: -sec - 1,
+			nsec: 1_000_000_000 - nsec,
+		},
+	})
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn pax_times() {
+		let ts = |sec, nsec| Some(Timestamp { sec, nsec });
+		assert_eq!(parse_pax_time("1700000000"), ts(1_700_000_000, 0));
+		assert_eq!(parse_pax_time("5.000000001"), ts(5, 1));
+		assert_eq!(parse_pax_time("5.25"), ts(5, 250_000_000));
+		assert_eq!(parse_pax_time("5.1234567891"), ts(5, 123_456_789));
+		assert_eq!(parse_pax_time("-1.5"), ts(-2, 500_000_000));
+		assert_eq!(parse_pax_time("-3"), ts(-3, 0));
+		assert_eq!(parse_pax_time(""), None);
+		assert_eq!(parse_pax_time("1.2.3"), None);
+		assert_eq!(parse_pax_time("-.5"), None);
+	}
+
+	#[test]
+	fn archive_rel_paths() {
+		assert_eq!(rel_path(b"./a/b"), Some(PathBuf::from("a/b")));
+		assert_eq!(rel_path(b"/a//b/"), Some(PathBuf::from("a/b")));
+		assert_eq!(rel_path(b"./"), Some(PathBuf::new()));
+		assert_eq!(rel_path(b"a/../../b"), None);
+	}
+}
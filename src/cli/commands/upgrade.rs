@@ -0,0 +1,39 @@
+use std::{
+	error::Error,
+	io::{stdout, Write},
+};
+
+use exitcode::DATAERR;
+use log::info;
+
+use crate::{
+	cli::{die, repo_root_or_die},
+	repo::{migrations, Repo},
+};
+
+pub fn exec(dry_run: bool) -> Result<(), Box<dyn Error>> {
+	let repo = Repo(repo_root_or_die()?);
+	let mut tag = repo.tag()?;
+
+	// Older versions are what this command is for, so only refuse newer or unknown formats
+	if let Err(e) = tag.check_readable() {
+		die(DATAERR, &format!("{e}, exiting"))
+	}
+
+	let mut out = stdout().lock();
+	for migration in migrations::pending(tag.version) {
+		writeln!(
+			out,
+			"version {} -> {}: {}",
+			migration.from,
+			migration.from + 1,
+			migration.description
+		)?;
+		if !dry_run {
+			migration.apply(&repo, &mut tag)?;
+		}
+	}
+	info!("repository is at format version {}", tag.version);
+
+	Ok(())
+}
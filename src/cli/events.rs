@@ -0,0 +1,98 @@
+//! Event stream of `baktu snap --events`, as JSON Lines for other tools to follow along
+
+use std::{
+	fs::{File, OpenOptions},
+	io::{self, LineWriter, Write},
+	os::fd::{FromRawFd, RawFd},
+	path::Path,
+};
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use nix::fcntl::{fcntl, FcntlArg};
+use serde::Serialize;
+
+use crate::repo::snap::{Event, EVENTS_VERSION};
+
+/// An event as written to the stream, one per line
+#[derive(Serialize)]
+struct Line<'a> {
+	/// [`EVENTS_VERSION`], so consumers can detect incompatible changes
+	version: u32,
+	time: DateTime<Utc>,
+	#[serde(flatten)]
+	event: &'a Event<'a>,
+}
+
+pub struct Sink(Option<LineWriter<File>>);
+
+impl Sink {
+	/// Opens the event stream `spec`, an inherited file descriptor number if it is all digits, or
+	/// a file path to append to otherwise. Files named like numbers can be given as `./<number>`.
+	pub fn open(spec: &Path) -> io::Result<Self> {
+		let file = match spec.to_str().and_then(|s| s.parse::<RawFd>().ok()) {
+			Some(fd) => {
+				// Fail early rather than on the first event if the descriptor was not inherited
+				fcntl(fd, FcntlArg::F_GETFD)?;
+				// SAFETY: the descriptor is open, and nothing else in baktu uses it
+				unsafe { File::from_raw_fd(fd) }
+			}
+			None => OpenOptions::new().create(true).append(true).open(spec)?,
+		};
+		Ok(Sink(Some(LineWriter::new(file))))
+	}
+
+	/// Writes `event`, giving up on the stream at the first error rather than failing the snapshot
+	pub fn write(&mut self, event: &Event) {
+		let Some(out) = &mut self.0 else { return };
+		let line = Line {
+			version: EVENTS_VERSION,
+			time: Utc::now(),
+			event,
+		};
+		let result = serde_json::to_writer(&mut *out, &line)
+			.map_err(io::Error::from)
+			.and_then(|()| writeln!(out));
+		if let Err(e) = result {
+			warn!("unable to write to the event stream: {e}, no longer writing events");
+			self.0 = None;
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::path::Path;
+
+	use crate::repo::snap::{ErrorAction, Event, WarningKind};
+
+	// The serialized form is an interface, changes to it need an EVENTS_VERSION increase unless
+	// they only add events or fields
+	#[test]
+	fn stable_format() {
+		let json = |event| serde_json::to_string(&event).unwrap();
+		assert_eq!(
+			json(Event::Excluded {
+				path: Path::new("/src/cache"),
+				reason: "config.toml/exclude.cachedir_tag",
+			}),
+			r#"{"event":"excluded","path":"/src/cache","reason":"config.toml/exclude.cachedir_tag"}"#
+		);
+		assert_eq!(
+			json(Event::Warning {
+				kind: WarningKind::Nodump,
+				path: Some(Path::new("/src/\u{e9}")),
+				message: "marked as nodump",
+			}),
+			r#"{"event":"warning","kind":"nodump","path":"/src/é","message":"marked as nodump"}"#
+		);
+		assert_eq!(
+			json(Event::Error {
+				path: None,
+				message: "disk full".to_owned(),
+				action: ErrorAction::Abort,
+			}),
+			r#"{"event":"error","path":null,"message":"disk full","action":"abort"}"#
+		);
+	}
+}
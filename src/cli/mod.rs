@@ -2,28 +2,30 @@
 
 use std::env::current_dir;
 use std::error::Error;
-use std::ffi::{c_char, CString, OsString};
-use std::fs::{self, read_dir, File};
+use std::ffi::OsString;
+use std::fs::{self, read_dir};
 use std::path::{Path, PathBuf};
 
-use std::collections::{HashMap, HashSet};
-use std::io::{self, stdout, ErrorKind, Read, Write};
+use std::io::{self, Write};
 use std::os::unix::prelude::OsStrExt;
+use std::time::Duration;
 
 use clap::{Args, Parser, Subcommand};
-use exitcode::{ExitCode, CANTCREAT, DATAERR, IOERR, NOINPUT, SOFTWARE, USAGE};
-use libc::faccessat;
-use log::{debug, info, trace, warn, LevelFilter};
-use nix::sys::stat::{mknod, Mode, SFlag};
-use pathdiff::diff_paths;
-use walkdir::DirEntry;
-
-use crate::file::filekey::FileKey;
+use ed25519_dalek::SigningKey;
+use exitcode::{ExitCode, DATAERR, IOERR, NOINPUT, NOPERM, UNAVAILABLE, USAGE};
+use log::{info, warn};
+use serde::Serialize;
+
 use crate::repo::site::Site;
-use crate::repo::snapshot::Snapshot;
-use crate::repo::{snapshot, Repo};
-use crate::util::{hex, nsv};
-use crate::{file, repo};
+use crate::repo::snapshot::{Exclusion, Snapshot};
+use crate::repo::summary::Summary;
+use crate::repo::{snap, snapshot, Repo};
+use crate::util::nsv;
+use crate::{repo, Error as BaktuError};
+
+mod commands;
+mod events;
+mod progress;
 
 // Structure based on the recommendations in
 // https://rust-cli-recommendations.sunshowers.io/handling-arguments.html
@@ -114,6 +116,191 @@ enum Command {
 
 	/// Create a new snapshot within the current site
 	Snap(SnapArgs),
+
+	/// Import rsnapshot or other rsync hard link trees as snapshots of the current site
+	///
+	/// Each directory becomes a snapshot, in the order of their modification times, which
+	/// rsnapshot sets when an interval is complete. Content is deduplicated as with `snap`, while
+	/// metadata is read from the trees themselves, and thus marked as approximate.
+	ImportRsnapshot {
+		/// Directories to import, such as `daily.0`, each holding the backed up paths
+		#[arg(required = true, value_name = "DIR")]
+		dirs: Vec<PathBuf>,
+	},
+
+	/// List sites, the snapshots of a site, or directory contents within a snapshot
+	///
+	/// Entries are listed in a `ls -l`-like layout of type and permissions, hard link count,
+	/// numeric owner and group, size, mtime and name. Deduplicated files are marked with `=` before
+	/// their name.
+	Ls {
+		/// What to list, as `<site>[/<snapshot>[/<path>]]`. Lists all sites if omitted
+		location: Option<PathBuf>,
+	},
+
+	/// Write the content of a file in a snapshot to stdout, verifying its BLAKE3 hash
+	///
+	/// Deduplicated files and history intervals are resolved via the metadata records. If the
+	/// content does not match the recorded hash, baktu exits with an error after writing it.
+	Cat {
+		/// File to write, as `<site>/<snapshot>/<path>`
+		location: PathBuf,
+	},
+
+	/// Print metadata records as JSON Lines or CBOR, for use by other tools
+	///
+	/// Names and extended attributes are printed losslessly, see `baktu meta schema` for the
+	/// record format.
+	Meta {
+		#[clap(subcommand)]
+		command: commands::meta::MetaCommand,
+	},
+
+	/// Export a snapshot as an archive
+	///
+	/// Entries carry the recorded metadata, including nanosecond timestamps and extended
+	/// attributes, with deduplicated files and history intervals resolved to their content.
+	Export {
+		/// Archive format
+		#[arg(long, value_enum, default_value_t = commands::export::Format::Pax)]
+		format: commands::export::Format,
+
+		/// Snapshot to export, as `<site>/<snapshot>`
+		snapshot: PathBuf,
+
+		/// File to write the archive to. Writes to stdout if omitted
+		path: Option<PathBuf>,
+	},
+
+	/// Find entries across all sites and snapshots by name, hash or metadata
+	///
+	/// All given predicates must match. Results are printed as `<site>/<snapshot>/<path>`, one per
+	/// line, and are found via the metadata records alone, without reading any file content.
+	Find(commands::find::FindArgs),
+
+	/// List the history of a path across all snapshots of a site
+	///
+	/// Each snapshot the path exists in is listed with whether its content or only its metadata
+	/// changed since the previous version. Deletions and reappearances are listed as well.
+	Log {
+		/// Name of the site
+		site: PathBuf,
+
+		/// Path within the snapshots, e.g. `home/user/notes.txt`
+		path: PathBuf,
+	},
+
+	/// Remove the snapshots of the current site not kept by any of the given retention rules
+	///
+	/// Files in other snapshots or sites deduplicated against files in the removed snapshots are
+	/// kept intact, by moving the backing file into the oldest such file and relinking the rest to
+	/// it. The plan is printed before being carried out.
+	Prune(commands::prune::PruneArgs),
+
+	/// Remove paths matching a glob from all snapshots of a site, e.g. accidentally backed up
+	/// secrets or huge files
+	///
+	/// Both the data and the metadata records of matching paths are removed. Files elsewhere in
+	/// the repository deduplicated against removed files are kept intact, as with `prune`. The
+	/// removed paths and rewritten files are printed before the changes are carried out.
+	Purge(commands::purge::PurgeArgs),
+
+	/// Copy a baktu repository, e.g. to a mounted external drive
+	///
+	/// Snapshots are copied one by one, preserving the relative symlinks of deduplicated files and
+	/// verifying the BLAKE3 hashes of the copied files. Use `pull` to update the copy later.
+	Clone {
+		/// Path of the repository to copy
+		src: PathBuf,
+
+		/// Path of the new repository, which must not exist or be an empty directory
+		dst: PathBuf,
+	},
+
+	/// Copy the snapshots missing in the current repository from another one
+	///
+	/// Sites missing in the current repository are created with the configuration of the other
+	/// one. Snapshots are copied as with `clone`.
+	Pull {
+		/// Path of the repository to copy from
+		src: PathBuf,
+	},
+
+	/// Upgrade the current repository to the latest format version
+	///
+	/// Migrations are applied one version at a time, updating `BAKTU_REPO.TAG` after each, so an
+	/// interrupted upgrade can be resumed by running it again. Other commands refuse to modify
+	/// repositories using an older format version.
+	Upgrade {
+		/// Only list the migrations to apply, without making any changes to the repository
+		#[arg(short('n'), long)]
+		dry_run: bool,
+	},
+
+	/// Verify the integrity of snapshots
+	///
+	/// Checks each directory's metadata against the digest recorded in its parent, starting from
+	/// the snapshot's root hash, as well as the content of regular files against their recorded
+	/// BLAKE3 hashes. Problems are listed as `<site>/<snapshot>/<path>` followed by a description.
+	Fsck {
+		/// Only verify the metadata, skipping the much slower content verification
+		#[arg(long)]
+		quick: bool,
+
+		/// What to check, as `<site>[/<snapshot>]`. Checks all snapshots if omitted
+		location: Option<PathBuf>,
+	},
+
+	/// List the differences between two snapshots, possibly of different sites
+	///
+	/// Each added, deleted or changed path is listed after its status: `added`, `deleted`,
+	/// `content` or `metadata`, with the changed metadata fields in parentheses. Only the topmost
+	/// path of added or deleted directories is listed, and subtrees with identical digests are
+	/// skipped without being read.
+	Diff {
+		/// Snapshot to compare from, as `<site>/<snapshot>`
+		from: PathBuf,
+
+		/// Snapshot to compare to, as `<site>/<snapshot>`
+		to: PathBuf,
+	},
+
+	/// Create recovery data for snapshots, or repair them from it
+	Parity {
+		#[clap(subcommand)]
+		command: commands::parity::ParityCommand,
+	},
+
+	/// Generate an ed25519 key pair for signing snapshots
+	///
+	/// The hex-encoded secret key is written to FILE, readable only by its owner, and the public
+	/// key to FILE.pub, as well as stdout. Set the secret key as `signing.key` in a site's
+	/// `config.toml` to sign its snapshots.
+	GenKey {
+		/// File to write the secret key to, which must not exist
+		#[arg(value_name = "FILE")]
+		secret_key: PathBuf,
+	},
+
+	/// Verify the signatures of snapshots
+	///
+	/// Checks that each snapshot's signature was made with the given key over its current root
+	/// hash and summary, and that its metadata matches the root hash. File content is not
+	/// checked, use `fsck` for that.
+	VerifySignature {
+		/// Hex-encoded public key, or a file containing one, as created by `gen-key`
+		#[arg(long, value_name = "KEY")]
+		public_key: String,
+
+		/// What to check, as `<site>[/<snapshot>]`. Checks all snapshots if omitted
+		location: Option<PathBuf>,
+	},
+
+	/// Show the site configuration and `snap` arguments recorded in a snapshot
+	ShowConfig {
+		/// Snapshot to inspect, as `<site>/<snapshot>`
+		snapshot: PathBuf,
+	},
 }
 
 #[derive(Debug, Args)]
@@ -131,7 +318,7 @@ pub struct GlobalOpts {
 	pub silent: bool,
 }
 
-#[derive(Debug, Args)]
+#[derive(Debug, Args, Serialize)]
 struct SnapArgs {
 	/// Do not error out on nonexistent exclude paths
 	#[arg(long)]
@@ -149,13 +336,61 @@ struct SnapArgs {
 	#[arg(long)]
 	confirm_exclude_all_eacces: bool,
 
+	/// Do not descend into other filesystems, skipping all mount points under the include roots.
+	/// Equivalent to `exclude.one_file_system` in the site config
+	#[arg(short('x'), long)]
+	one_file_system: bool,
+
 	/// Do not make any changes to the filesystem
 	#[arg(short('n'), long)]
 	dry_run: bool,
+
+	/// What to do about errors reading single source paths, e.g. a file that vanished or can not
+	/// be read. `record` lists the skipped paths in the exclusion log of the snapshot
+	#[arg(long, value_enum, default_value_t = snap::ErrorAction::Abort)]
+	on_error: snap::ErrorAction,
+
+	/// Show progress on stderr: paths and bytes stored, throughput and deduplicated files. Printed
+	/// as periodic log lines when stderr is not a terminal
+	#[arg(long)]
+	#[serde(skip)]
+	progress: bool,
+
+	/// Count the paths and bytes under the include roots first, to show totals and an estimated
+	/// time remaining along with the progress. Implies `--progress`
+	#[arg(long)]
+	#[serde(skip)]
+	prescan: bool,
+
+	/// Interval between the progress log lines printed when stderr is not a terminal, e.g. `1m`.
+	/// Defaults to 10s. Implies `--progress`
+	#[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+	#[serde(skip)]
+	progress_interval: Option<Duration>,
+
+	/// Write events such as exclusions, deduplicated files, warnings and errors as JSON Lines to an
+	/// inherited file descriptor, e.g. `3`, or append them to a file, e.g. `./events.jsonl`
+	#[arg(long, value_name = "FD|PATH")]
+	#[serde(skip)]
+	events: Option<PathBuf>,
+
+	/// Create the snapshot from an uncompressed tar archive instead of the include paths, with
+	/// metadata from its headers. `-` reads the archive from stdin. Refused if the site config
+	/// has hooks, as they are not run
+	#[arg(long, value_name = "FILE", conflicts_with_all = ["dry_run", "one_file_system", "progress", "prescan", "progress_interval", "events"])]
+	from_tar: Option<PathBuf>,
 }
 
 impl Baktu {
-	pub fn exec(self) -> Result<(), Box<dyn Error>> {
+	/// Runs the command, mapping any error to an exit code
+	pub fn run(self) -> std::process::ExitCode {
+		match self.exec() {
+			Ok(()) => std::process::ExitCode::SUCCESS,
+			Err(e) => std::process::ExitCode::from(report(e.as_ref()) as u8),
+		}
+	}
+
+	fn exec(self) -> Result<(), Box<dyn Error>> {
 		self.init_logging();
 
 		info!("version {} starting up", env!("CARGO_PKG_VERSION"));
@@ -167,8 +402,51 @@ impl Baktu {
 			Init => Self::repo_init()?,
 			AddSite { name } => Self::site_add(name)?,
 			NsvAddTo { file, path } => nsv::append(&file, path.as_os_str().as_bytes())?,
-			NsvRmFrom { file, path } => nsv::filter_not(file, path.as_os_str().as_bytes())?,
-			Snap(args) => Self::snapshot(args)?,
+			NsvRmFrom { file, path } => {
+				if !nsv::filter_not(&file, path.as_os_str().as_bytes())? {
+					// TODO: (S) visualize the entry in a more user-friendly way
+					die(USAGE, &format!("{path:?} not found in {file:?}"))
+				}
+			}
+			Snap(args) => {
+				let print_summary = !(self.global_opts.quiet || self.global_opts.silent);
+				Self::snapshot(args, print_summary)?
+			}
+			ImportRsnapshot { dirs } => {
+				let print_summary = !(self.global_opts.quiet || self.global_opts.silent);
+				commands::import_rsnapshot::exec(&repo_site_or_die()?, &dirs, print_summary)?
+			}
+			Ls { location } => commands::ls::exec(match location {
+				Some(spec) => Some(location_or_die(&spec)?),
+				None => None,
+			})?,
+			Cat { location } => commands::cat::exec(location_or_die(&location)?)?,
+			Export {
+				format,
+				snapshot,
+				path,
+			} => commands::export::exec(snapshot_or_die(&snapshot)?, path.as_deref(), format)?,
+			Meta { command } => commands::meta::exec(command)?,
+			Find(args) => commands::find::exec(args)?,
+			Log { site, path } => commands::log::exec(&site_or_die(&site)?, &path)?,
+			Prune(args) => commands::prune::exec(&repo_site_or_die()?, args)?,
+			Purge(args) => commands::purge::exec(&site_or_die(&args.site)?, args)?,
+			Clone { src, dst } => commands::clone::exec(&src, &dst)?,
+			Pull { src } => commands::pull::exec(&src)?,
+			Upgrade { dry_run } => commands::upgrade::exec(dry_run)?,
+			Fsck { quick, location } => {
+				commands::fsck::exec(snapshots_or_die(location.as_deref())?, quick)?
+			}
+			Diff { from, to } => {
+				commands::diff::exec(&snapshot_or_die(&from)?, &snapshot_or_die(&to)?)?
+			}
+			Parity { command } => commands::parity::exec(command)?,
+			GenKey { secret_key } => commands::signature::gen_key(&secret_key)?,
+			VerifySignature {
+				public_key,
+				location,
+			} => commands::signature::verify(snapshots_or_die(location.as_deref())?, &public_key)?,
+			ShowConfig { snapshot } => commands::show_config::exec(&snapshot_or_die(&snapshot)?)?,
 		}
 
 		info!("process exiting successfully");
@@ -222,7 +500,9 @@ impl Baktu {
 	}
 
 	fn site_add(name: String) -> io::Result<()> {
-		let sites_path = repo_root_or_die()?.join("sites");
+		let repo = Repo(repo_root_or_die()?);
+		writable_or_die(&repo)?;
+		let sites_path = repo.sites_path();
 
 		if !sites_path.exists() {
 			die(DATAERR, "repo corrupt: sites directory does not exist")
@@ -240,726 +520,213 @@ impl Baktu {
 		Ok(())
 	}
 
-	fn snapshot(cfg: SnapArgs) -> Result<(), Box<dyn Error>> {
+	fn snapshot(cfg: SnapArgs, print_summary: bool) -> Result<(), Box<dyn Error>> {
 		let site = repo_site_or_die()?;
+		writable_or_die(&site.repo())?;
+
+		if let Some(source) = &cfg.from_tar {
+			return commands::snap_from_tar::exec(&site, &cfg, source, print_summary);
+		}
 
-		let includes = {
-			let result = site.get_included()?;
-			if result.is_empty() {
+		let options = snap::Options::from(&cfg);
+		let events = cfg.events.as_deref().map(|spec| {
+			events::Sink::open(spec).unwrap_or_else(|e| {
 				die(
-					DATAERR,
-					&format!(
-						"no paths have been included, run `baktu nsv-add-to {} <PATH>` first",
-						repo::site::INCLUDES_NAME
-					),
+					USAGE,
+					&format!("unable to open event stream {spec:?}: {e}, exiting"),
 				)
-			}
-
-			let nonexistent: Vec<_> = result.iter().filter(|path| !path.exists()).collect();
-			if !nonexistent.is_empty() {
-				// Print them all out, so we don't have to do an edit-rerun loop in case of multiple
-				// nonexistent ones
-				for p in nonexistent {
-					log::error!("included path {p:?} doesn't exist")
-				}
-				die(DATAERR, "found nonexistent included paths")
-			} else {
-				result
-			}
+			})
+		});
+		// The estimate of `--prescan` arrives once the pre_snap hook has run
+		let progress = (cfg.progress || cfg.prescan || cfg.progress_interval.is_some())
+			.then(|| progress::Display::new(cfg.progress_interval));
+		let mut observer = SnapObserver {
+			on_error: options.on_error,
+			progress,
+			events,
+			aborted: false,
 		};
+		let result = site.snapshot_with(&options, &mut observer);
+		observer.finish(result.as_ref().err());
+		let (snap, summary) = result?;
+
+		if print_summary {
+			eprintln!(
+				"{}snapshot {:?} done in {:.1}s: {} paths processed ({}), {} excluded",
+				if cfg.dry_run { "(dry run) " } else { "" },
+				snap.0,
+				summary.wall_time_secs,
+				summary.files_total(),
+				summary
+					.files
+					.iter()
+					.map(|(file_type, cnt)| format!("{cnt} {file_type}"))
+					.collect::<Vec<_>>()
+					.join(", "),
+				summary.exclusions_total(),
+			);
+			eprintln!(
+				"read {}, wrote {}, deduplicated {}, {} new unique hashes",
+				human_bytes(summary.bytes_read),
+				human_bytes(summary.bytes_written),
+				human_bytes(summary.bytes_deduplicated),
+				summary.new_unique_hashes
+			);
+		}
 
-		// TODO: (S) decide how to handle exclude paths outside of srcRoot, e.g. error out + add an
-		// allow flag
-		let excludes: HashSet<FileKey> = {
-			let seq = site.get_excluded()?;
-			let nonexistent: Vec<_> = seq.iter().filter(|path| !path.exists()).collect();
-			if !nonexistent.is_empty() {
-				// Print them all out, so we don't have to do an edit-rerun loop in case of multiple
-				// nonexistent ones
-				for p in nonexistent {
-					log::error!("excluded path {p:?} doesn't exist")
-				}
-				die(DATAERR, "found nonexistent excluded paths")
-			} else {
-				HashSet::from_iter(
-					seq.into_iter()
-						.map(|path| FileKey::from_path(&path).expect("unable to create FileKey")),
-				)
-			}
-		};
+		info!("snapshot subcommand done");
 
-		let site_conf = site.get_config()?;
-
-		// Excluded path counts from the is_included lambda, and from the rest of the main loop,
-		// respectively
-		let mut excluded_cnt_is_included = 0u64;
-		let mut excluded_cnt_loop = 0u64;
-
-		let mut is_included = |dir_entry: &DirEntry| {
-			trace!("testing is_included({:?})", &dir_entry);
-			let mut exclude = |reason| -> bool {
-				info!("excluding {:?} due to {}", dir_entry.path(), reason);
-				excluded_cnt_is_included += 1;
-				false
-			};
-
-			let mut die_or_log_exclude_all_eacces = |denied_action| {
-				if site_conf.exclude.all_eacces && cfg.confirm_exclude_all_eacces {
-					exclude(
-						repo::site::config_file::NAME.to_owned()
-							+ &format!("/exclude.all_eacces ({denied_action})"),
-					);
-				} else {
-					die(
-						NOINPUT,
-						&format!(
-							// TODO: (C) look into capabilities or other security
-							//	mechanisms as a more fine-grained way to allow baktu
-							//	access to [path]
-							// TODO: (S) DRY the confirm flag in all locations
-							"Permission denied during {denied_action} for {:?}, exiting. You can \
-								either \
-								1) exclude the path explicitly and re-run, \
-								2) re-run `baktu` with sudo or equivalent, or \
-								3) set `exclude.all_eacces` in the site `{}` and \
-									re-run with `--confirm-exclude-all-eacces`",
-							&dir_entry.path(),
-							repo::site::config_file::NAME
-						),
-					)
-				}
-			};
+		Ok(())
+	}
+}
 
-			let stx = match file::statx::get(dir_entry.path()) {
-				Ok(res) => res,
-				Err(e) if e.kind() == ErrorKind::PermissionDenied => {
-					die_or_log_exclude_all_eacces("statx");
-					return false;
-				}
-				Err(e) => die(
-					SOFTWARE,
-					&format!(
-						"statx({:?}): unexpected error {:?}, exiting",
-						&dir_entry.path(),
-						e,
-					),
-				),
-			};
-
-			// TODO: (S) switch to lexical canonicalization instead:
-			//   1. Fixes hard link aliasing issue
-			//   2. May save us an extra system call in some situations
-			let fk = FileKey::from_statx(&stx);
-
-			// TODO: (C) consider flattening the decision tree to improve readability, if we can do
-			// so without increasing the risk of bugs too much
-			if excludes.contains(&fk) {
-				exclude(repo::site::EXCLUDES_NAME.to_owned())
-			} else {
-				// obeying clippy's lint here would result in less obvious code structure
-				#[allow(clippy::collapsible_else_if)]
-				if site_conf.exclude.cachedir_tag
-					&& dir_entry.file_type().is_dir()
-					&& dir_entry.path().join("CACHEDIR.TAG").exists()
-					&& file::is_valid_cachedir_tag(dir_entry.path().join("CACHEDIR.TAG").as_path())
-				{
-					exclude(repo::site::config_file::NAME.to_owned() + "/exclude.cachedir_tag")
-				} else {
-					if site_conf.exclude.nodump
-						&& stx.stx_attributes_mask & libc::STATX_ATTR_NODUMP as u64 != 0
-						&& stx.stx_attributes & libc::STATX_ATTR_NODUMP as u64 != 0
-					{
-						exclude(repo::site::config_file::NAME.to_owned() + "/exclude.nodump")
-					} else {
-						fn readable(p: &Path) -> bool {
-							/* TODO: (S) consider switching to nix, as AT_EACCESS is now supported
-								- issue: https://github.com/nix-rust/nix/pull/1995
-								- in since 0.27.0, see
-									https://github.com/nix-rust/nix/blob/master/CHANGELOG.md
-							// recommended in https://github.com/nix-rust/nix/issues/1340
-							const dirfd: libc::c_int = libc::AT_FDCWD;
-							nix::unistd::faccessat(
-								Some(dirfd),
-								p,
-								nix::unistd::AccessFlags::R_OK,
-								AT_EACCESS | nix::fcntl::AtFlags::AT_SYMLINK_NOFOLLOW).is_ok()
-							*/
-
-							// from https://docs.rs/faccess/0.2.4/src/faccess/lib.rs.html#92
-							// modified with AT_SYMLINK_NOFOLLOW
-							let path =
-								CString::new(p.as_os_str().as_bytes()).expect("p can't contain 0");
-
-							unsafe {
-								faccessat(
-									libc::AT_FDCWD,
-									path.as_ptr() as *const c_char,
-									libc::R_OK,
-									libc::AT_EACCESS | libc::AT_SYMLINK_NOFOLLOW,
-								) == 0
-							}
-						}
-
-						if !readable(dir_entry.path()) {
-							die_or_log_exclude_all_eacces("faccessat(READ)");
-							false
-						} else {
-							true
-						}
-					}
-				}
-			}
-		};
+/// Observer of `baktu snap`, applying `--on-error` and reporting via `--progress` and `--events`
+struct SnapObserver {
+	on_error: snap::ErrorAction,
+	progress: Option<progress::Display>,
+	events: Option<events::Sink>,
+	/// Whether an error event has already ended the event stream
+	aborted: bool,
+}
 
-		let mut xattr_helper = file::xattrs::Helper::init_opt()?;
+impl SnapObserver {
+	/// Clears the progress line and reports `error`, if the snapshot failed
+	fn finish(&mut self, error: Option<&BaktuError>) {
+		if let Some(progress) = &mut self.progress {
+			progress.clear();
+		}
+		if let (Some(events), Some(e), false) = (&mut self.events, error, self.aborted) {
+			events.write(&snap::Event::Error {
+				path: e.path(),
+				message: e.to_string(),
+				action: snap::ErrorAction::Abort,
+			});
+		}
+	}
+}
 
-		// FIXME: remove the hardcoding once we can create more than the initial snapshot
-		// TODO: (S) refactor this into a Site impl fn
-		const ZEROTH_SNAP_NAME: &str = "0";
-		let snap_path = site.snaps_path().join(ZEROTH_SNAP_NAME);
+impl snap::Observer for SnapObserver {
+	fn on_error(&mut self, _e: &BaktuError) -> snap::ErrorAction {
+		self.on_error
+	}
 
-		// TODO: (S) abstract over run dryness, so we end up with a single `if cfg.dry_run`
-		if cfg.dry_run {
-			info!("(fake) creating snapshot dir {snap_path:?}");
-		} else {
-			info!("creating snapshot dir {snap_path:?}");
-			fs::create_dir(&snap_path)?;
+	fn on_estimate(&mut self, estimate: snap::Estimate) {
+		if let Some(display) = &mut self.progress {
+			display.set_estimate(estimate);
 		}
+	}
 
-		// TODO: (S) strongly consider converting to Snapshot type earlier
-		// TODO: (S) smaller-scope: get rid of unnecessary clone
-		let snap_data_path = Snapshot::from_dryable(snap_path.clone(), cfg.dry_run)?.data_dir();
-		if cfg.dry_run {
-			info!("(fake) creating snapshot data dir {snap_data_path:?}");
-		} else {
-			info!("creating snapshot data dir {snap_data_path:?}");
-			fs::create_dir(&snap_data_path)?;
+	fn on_progress(&mut self, progress: &snap::Progress) {
+		if let Some(display) = &mut self.progress {
+			display.update(progress);
 		}
+	}
 
-		let meta_name: OsString = {
-			// FIXME: should find a unique META_NAME given the current input file name set, so we
-			// never get collisions. Adjust accordingly if we start supporting things that might
-			// break the invariants, for example incremental snapshotting.
-			".baktu.meta.brj".into()
-		};
-		let meta_name_fpath = snap_path.join(snapshot::META_NAME_FNAME);
-		if cfg.dry_run {
-			info!("(fake) writing metadata file name to {meta_name_fpath:?}");
-		} else {
-			info!("writing metadata file name to {meta_name_fpath:?}");
-			std::fs::OpenOptions::new()
-				.create_new(true)
-				.write(true)
-				.open(meta_name_fpath)?
-				.write_all(meta_name.as_bytes())?;
+	fn on_event(&mut self, event: &snap::Event) {
+		// The logged message would otherwise be appended to the progress line
+		if let (Some(progress), snap::Event::Warning { .. } | snap::Event::Error { .. }) =
+			(&mut self.progress, event)
+		{
+			progress.clear();
 		}
-
-		let mut processed_cnt = 0u64;
-
-		// TODO: (S) consider the trade-offs of doing a lazy initialization here, especially if we
-		// switch to duplicate detection mechanisms that require slower initialization. We'll need
-		// this "only" when we have to process regular files, so in the spirit of KISS we can
-		// consider that to always be the case, with rare and unusual exceptions.
-
-		// Map from file hash to set of destination paths of files with that hash. Excludes files
-		// too small to be deduplicated.
-		let mut hash2paths: HashMap<blake3::Hash, Vec<PathBuf>> = HashMap::new();
-
-		// TODO: (S) dedup code below, figure out what I don't understand to make it work with the
-		//	borrow checker.
-		// let mut add_hash_path = |h: Hash, p: PathBuf| {
-		// };
-
-		// TODO: (C) consider if using a segment trie for path storage would make
-		//   sense - trading space (RAM, in this case) for time - given that we'll
-		//   be dealing with something on the order of 10^5 entries at a minimum.
-
-		// TODO: (S) make projections for when we'll need to switch to a more
-		//   appropriate key-value storage approach, do preliminary research on
-		//   possible alternatives (e.g. sqlite) and their trade-offs.
-		for site_res in site.repo().sites()? {
-			for snap_res in site_res.expect("site error").snapshots()? {
-				for meta_res in snap_res.expect("snap error").meta_files()? {
-					let meta = meta_res.expect("meta_file error");
-					for record in meta.records()? {
-						if let Some((h, p)) = record.get_hash_path_opt(&meta.0)? {
-							// add_hash_path(h, p);
-							// TODO: (C) consider keeping a histogram of bucket sizes while
-							// figuring out how much of the file to use for fake_b3sum
-							match hash2paths.get_mut(&h) {
-								Some(paths) => {
-									warn!("hash collision with file {p:?}");
-									paths.push(p)
-								}
-								None => {
-									hash2paths.insert(h, vec![p]);
-								}
-							}
-						}
-					}
-				}
-			}
+		if let snap::Event::Error {
+			action: snap::ErrorAction::Abort,
+			..
+		} = event
+		{
+			self.aborted = true;
 		}
-
-		// FIXME: handle roots with the same basename appropriately
-		for include_root in includes {
-			info!("processing include_root {include_root:?}");
-			let include_root = {
-				let resolved = fs::canonicalize(&include_root).expect("could not canonicalize");
-				if resolved != include_root {
-					info!("\twhich resolves to {resolved:?}");
-				}
-				resolved
-			};
-
-			let dst_inc_root_path = snap_data_path.join(
-				include_root
-					.file_name()
-					.expect("canonicalized path can not contain trailing .."),
-			);
-
-			// TODO: (C) improve performance by DIYing the walking, since for each dentry:
-			//   - WalkDir has an fd
-			//   - WalkDir does a statx
-			//   - we do a statx to generate the FileKey
-			//   - we later do a statx again (up to and including DIOALIGN)
-			//   - we'll need an fd for FS_IOC_GETFLAGS
-			for entry in walkdir::WalkDir::new(&include_root)
-				.sort_by_file_name()
-				.into_iter()
-				.filter_entry(&mut is_included)
-			{
-				// FIXME: handle permission errors. Error out and suggest to a) fix permissions,
-				// b) exclude, or c) re-run with appropriate UID/GID/permissions
-				let entry = entry?;
-
-				let path = entry.path();
-				debug!("processing path {path:?}");
-
-				// This should stay even after we switch to finding a meta_name that doesn't occur
-				// in the source file set, so we can fail early and loudly in TOCTOU situations,
-				// instead of risking data corruption
-				if entry.file_type().is_dir() && entry.path().join(&meta_name).exists() {
-					die(
-						DATAERR,
-						&format!(
-							"source dir {:?} already contains file named {meta_name:?}",
-							entry.path()
-						),
-					);
-				}
-
-				if !cfg.no_report_cachedir_tag
-					&& entry.file_type().is_file()
-					&& entry.file_name() == "CACHEDIR.TAG"
-				{
-					if file::is_valid_cachedir_tag(entry.path()) {
-						warn!(
-							"Found valid and unexcluded CACHEDIR.TAG at {:?}. Rerun with \
-							--no-report-cachedir-tag or enable 'exclude.cachedir_tag' in the site \
-							'{}' to hide this warning.",
-							entry.path(),
-							repo::site::config_file::NAME
-						);
-					} else {
-						warn!(
-							"Found invalid CACHEDIR.TAG at {:?}. Rerun with \
-							--no-report-cachedir-tag to hide this warning.",
-							entry.path()
-						);
-					}
-				}
-
-				let stx = file::statx::get(path).expect(
-					"statx should not fail as it has already \
-					been invoked on the path in is_included()",
-				);
-
-				if !cfg.no_report_nodump
-					&& stx.stx_attributes_mask & libc::STATX_ATTR_NODUMP as u64 != 0
-					&& stx.stx_attributes & libc::STATX_ATTR_NODUMP as u64 != 0
-				{
-					warn!(
-						"{:?} is marked as nodump, but not excluded. Rerun with --no-report-nodump \
-						or enable 'exclude.nodump' in the site '{}' to hide this warning.",
-						entry.path(),
-						repo::site::config_file::NAME
-					);
-				}
-
-				let root_rel_path = path.strip_prefix(&include_root)?;
-				let dst_path = if root_rel_path == Path::new("") {
-					// include root is a file, not a directory
-					dst_inc_root_path.clone()
-				} else {
-					dst_inc_root_path.join(root_rel_path)
-				};
-
-				let hash = if entry.file_type().is_file() {
-					Some(file::b3sum(path)?)
-				} else {
-					None
-				};
-
-				let repo_find_dup =
-					|hash: blake3::Hash, path: &Path| -> io::Result<Option<PathBuf>> {
-						// Short-circuiting byte-by-byte comparison.
-						// Of course the usual considerations apply:
-						// - read files in chunks [f9]
-						// - compare at least usize bytes at a time [f10]
-						// - SIMD if easy enough, might help or not
-						//
-						// cmp perf with 4K in case we get no gains from going down to a "sector"
-						// size, see [f9]
-						//
-						// semi-related: https://lib.rs/crates/dupe-krill from [f11] describes an
-						// interesting approach to dedup via LazilyHashing<File> -> Vec<Path>
-
-						// [f9]  https://users.rust-lang.org/t/efficient-way-of-checking-if-two-files-have-the-same-content/74735/9
-						// [f10] https://users.rust-lang.org/t/efficient-way-of-checking-if-two-files-have-the-same-content/74735/10
-						// [f11] https://users.rust-lang.org/t/efficient-way-of-checking-if-two-files-have-the-same-content/74735/11
-						fn file_cmp(p1: &Path, p2: &Path) -> io::Result<bool> {
-							let mut f1 = File::open(p1)?;
-							let mut f2 = File::open(p2)?;
-
-							if f1.metadata()?.len() != f2.metadata()?.len() {
-								return Ok(false);
-							}
-
-							const BUF_SIZE: usize = 64 * 1024;
-							let b1 = &mut [0; BUF_SIZE];
-							let b2 = &mut [0; BUF_SIZE];
-
-							loop {
-								let f1_read_len = f1.read(b1)?;
-								let f2_read_len = f2.read(b2)?;
-
-								if f1_read_len != f2_read_len {
-									die(IOERR,
-										&format!("mismatched read lengths on equally sized files {p1:?} and {p2:?}"));
-								}
-
-								// Second part is redundant, but keep it in for robustness
-								if f1_read_len == 0 && f2_read_len == 0 {
-									return Ok(true);
-								}
-
-								if b1[0..f1_read_len] != b1[0..f2_read_len] {
-									return Ok(false);
-								}
-							}
-						}
-
-						// might need to bump fake_b3sum to 512 or more bytes if too many false
-						// positives. See "histogram" TODO above
-						match hash2paths.get(&hash) {
-							None => Ok(None),
-							Some(paths) => {
-								for candidate in paths {
-									if file_cmp(path, candidate)? {
-										return Ok(Some(candidate.to_path_buf()));
-									}
-								}
-								Ok(None)
-							}
-						}
-					};
-
-				// Only true if we deduplicate the file. False even if there's other files with the
-				// same content, but we choose to not deduplicate (e.g. due to size too small)
-				let mut is_deduplicated = false;
-
-				debug!("creating at destination {dst_path:?}");
-				// Note that initially we're only focusing on recreating the non-meta state of the
-				// file, as all meta-information should be recorded in the meta dump afterwards.
-				// However recreating more of the meta state (permissions, etc) is a Could, or
-				// ideally even a Should task for later, as this would provide more of the source
-				// state at later stages of the repo's graceful degradation.
-				assert!(stx.stx_mask & libc::STATX_TYPE != 0);
-				match stx.stx_mode as u32 & libc::S_IFMT {
-					libc::S_IFDIR => {
-						if cfg.dry_run {
-							info!("(fake) mkdir {dst_path:?}")
-						} else {
-							fs::create_dir(&dst_path)?
-						}
-					}
-					libc::S_IFREG => {
-						// We ought to need at least a byte to create a meaningful symlink when
-						// deduplicating, thus a minimum sensible threshold would be larger.
-						const DEDUP_MIN_FSIZE: u64 = 2;
-						if entry.metadata()?.len() < DEDUP_MIN_FSIZE {
-							debug!("skipping deduplication of file smaller than {DEDUP_MIN_FSIZE} bytes");
-							if cfg.dry_run {
-								info!("(fake) cp {path:?} {dst_path:?}");
-							} else {
-								fs::copy(path, &dst_path)?;
-							}
-						} else {
-							// TODO: (C) consider encoding file type + hash as a sum type
-							// [later edit] possibly moot, as we don't always need the hash here
-							let hash = hash.expect("hash not Some while REG");
-							match repo_find_dup(hash, path)? {
-								Some(preexisting_path) => {
-									is_deduplicated = true;
-									if cfg.dry_run {
-										info!(
-											"(fake) dedup src={path:?} \
-											dst={dst_path:?} preexisting={preexisting_path:?}"
-										);
-									} else {
-										// Pros/cons of using hard links:
-										//	- introduces limits - 65K on ext4, according to
-										//		https://unix.stackexchange.com/questions/5629/is-there-a-limit-of-hardlinks-for-one-file
-										//	- removes the possibility to optimize duplicate
-										//		detection in repo clients by checking
-										//		meta.is_deduplicated only for symlinks
-										//	- introduces hard links into the repo, which introduces
-										//		additional concerns for operating on it with tar,
-										//		rsync, etc.
-										//	± duplicate representation at the data level does not
-										//		depend on which one is encountered first. Meta level
-										//		is still affected, which might need to be taken into
-										//		account
-										//	+ presumably saves a few bytes in some cases, as we can
-										//		just use a dentry, not needing space for the
-										//		relative path.
-
-										// create relative symlink to the preexisting path
-										// TODO: (M) test that moving a baktu repo doesn't break
-										// these
-										std::os::unix::fs::symlink(
-											diff_paths(
-												preexisting_path,
-												dst_path.parent().expect("dedup dest has parent"),
-											)
-											.expect("should work for 2 absolute paths"),
-											&dst_path,
-										)?;
-									}
-								}
-								None => {
-									if cfg.dry_run {
-										info!("(fake) cp {path:?} {dst_path:?}");
-									} else {
-										fs::copy(path, &dst_path)?;
-									}
-
-									// We use the source path when we're "creating" a dry-run
-									// snapshot, so there's something to compare for subsequent
-									// dedup byte-by-byte checks
-									let backing_path = if cfg.dry_run {
-										path.to_path_buf()
-									} else {
-										dst_path.clone()
-									};
-
-									trace!("new data, adding to map: {hash:?} {backing_path:?}");
-									// add_hash_path(hash, dst_path);
-
-									// TODO: (C) consider keeping a histogram of bucket sizes while
-									// figuring out how much of the file to use for fake_b3sum, *if*
-									// we switch back to fake_b3sum
-									match hash2paths.get_mut(&hash) {
-										Some(paths) => {
-											warn!("hash collision with file {backing_path:?}");
-											paths.push(backing_path)
-										}
-										None => {
-											hash2paths.insert(hash, vec![backing_path]);
-										}
-									}
-								}
-							}
-						}
-					}
-					libc::S_IFLNK => {
-						// TODO: (M) ensure this preserves the symlink as is
-						std::os::unix::fs::symlink(fs::read_link(path)?, &dst_path)?
-					}
-					libc::S_IFBLK | libc::S_IFCHR | libc::S_IFIFO | libc::S_IFSOCK => {
-						assert!(stx.stx_mask & libc::STATX_MODE != 0);
-						assert!(stx.stx_mask & libc::STATX_TYPE != 0);
-
-						// S_IFMT bit twiddling from
-						// https://man7.org/linux/man-pages/man2/statx.2.html
-
-						// dev ignored if not CHR/BLK, according to
-						// https://man7.org/linux/man-pages/man2/mknod.2.html
-						match mknod(
-							&dst_path,
-							SFlag::from_bits(stx.stx_mode as u32 & libc::S_IFMT)
-								.expect("bits to kind"),
-							Mode::from_bits(stx.stx_mode as u32 & !libc::S_IFMT)
-								.expect("bits to perms"),
-							libc::makedev(stx.stx_rdev_major, stx.stx_rdev_minor),
-						) {
-							Ok(_) => (),
-							Err(nix::errno::Errno::EPERM) => {
-								// TODO: (M) DRY the exclude/exit logic here
-								if site_conf.exclude.all_eacces && cfg.confirm_exclude_all_eacces {
-									info!(
-										"excluding {:?} due to {} (mknod)",
-										entry.path(),
-										repo::site::config_file::NAME.to_owned()
-											+ "/exclude.all_eacces"
-									);
-									excluded_cnt_loop += 1;
-									continue;
-								} else {
-									die(
-										CANTCREAT,
-										&format!(
-											// TODO: (C) look into capabilities or other security
-											//	mechanisms as a more fine-grained way to allow baktu
-											//	access to [path]
-											// TODO: (S) DRY the confirm flag in all locations
-											"Permission denied during mknod for {:?}, exiting. You \
-												can either \
-												1) exclude the path explicitly and re-run, \
-												2) re-run `baktu` with sudo or equivalent, or \
-												3) set `exclude.all_eacces` in the site `{}` and \
-													re-run with `--confirm-exclude-all-eacces`",
-											&entry.path(),
-											repo::site::config_file::NAME
-										),
-									)
-								}
-							}
-							Err(e) => die(
-								SOFTWARE,
-								&format!(
-									"mknod({:?}): unexpected error {:?}, exiting",
-									&entry.path(),
-									e,
-								),
-							),
-						};
-					}
-					ft => die(DATAERR, &format!("unknown file type {ft}")),
-				}
-
-				dump_meta(
-					&mut xattr_helper,
-					get_meta_sink(cfg.dry_run, &dst_path, &meta_name),
-					path,
-					stx,
-					hash,
-					is_deduplicated,
-				)?;
-
-				processed_cnt += 1;
-			}
+		if let Some(events) = &mut self.events {
+			events.write(event);
 		}
-
-		info!(
-			"{} files excluded (not counting children), {} files processed",
-			excluded_cnt_is_included + excluded_cnt_loop,
-			processed_cnt
-		);
-
-		// xattr_helper: no need for explicit cleanup, as it will automatically have its stdin
-		// closed, and will exit normally
-
-		info!("snapshot subcommand done");
-
-		Ok(())
 	}
 }
 
-fn get_meta_sink(dry_run: bool, dst_path: &Path, meta_name: &OsString) -> Box<dyn Write> {
-	if dry_run {
-		if log::max_level() >= LevelFilter::Debug {
-			debug!("dumping meta to stdout");
-			Box::new(stdout())
-		} else {
-			Box::new(
-				std::fs::OpenOptions::new()
-					.append(true)
-					.open("/dev/null")
-					.expect("opening /dev/null should not fail"),
-			)
+impl From<&SnapArgs> for snap::Options {
+	fn from(args: &SnapArgs) -> Self {
+		snap::Options {
+			allow_nonexistent_exclude_paths: args.allow_nonexistent_exclude_paths,
+			no_report_cachedir_tag: args.no_report_cachedir_tag,
+			no_report_nodump: args.no_report_nodump,
+			confirm_exclude_all_eacces: args.confirm_exclude_all_eacces,
+			one_file_system: args.one_file_system,
+			dry_run: args.dry_run,
+			on_error: args.on_error,
+			prescan: args.prescan,
 		}
-	} else {
-		// Can be optimized to be calculated once per entering a directory, at the trade-off
-		// of keeping mutable context between loop iterations due to the current flattened
-		// mode of walking the tree
-		let meta_path = dst_path
-			.parent()
-			.expect("parent must exist given definition of dst_path")
-			.join(meta_name);
-		debug!("dumping metadata to {meta_path:?}");
-		Box::new(
-			std::fs::OpenOptions::new()
-				.append(true)
-				.create(true)
-				.open(meta_path)
-				.expect("should not fail if the FIXME above is implemented"),
-			// [edit] reference above no longer clear. Possibly the non-collision meta filename
-		)
 	}
 }
 
-fn dump_meta(
-	xattr_helper: &mut Option<file::xattrs::Helper>,
-	mut sink: Box<dyn Write>,
-	path: &std::path::Path,
-	stx: libc::statx,
-	hash: Option<blake3::Hash>,
-	is_deduplicated: bool,
-) -> Result<(), Box<dyn Error>> {
-	// Write this first, so we don't waste time while building the hash->path map during dedup
-	if is_deduplicated {
-		sink.write_all(repo::meta_file::line::IS_DEDUPLICATED)?;
-		writeln!(sink)?;
+/// Creates the next snapshot of `site` when not walking its include paths, recording the site
+/// configuration along with `args`, in place of the `baktu snap` flags
+fn create_snapshot(
+	site: &Site,
+	args: &impl Serialize,
+	meta_name: &OsString,
+) -> Result<Snapshot, Box<dyn Error>> {
+	let snap = Snapshot(site.next_snapshot_path()?);
+	info!("creating snapshot dir {:?}", snap.0);
+	fs::create_dir(&snap.0)?;
+
+	let site_config_dir = snap.site_config_dir();
+	info!("recording site config and arguments in {site_config_dir:?}");
+	fs::create_dir(&site_config_dir)?;
+	for name in repo::site::CONFIG_FILE_NAMES {
+		fs::copy(site.0.join(name), site_config_dir.join(name))?;
 	}
+	fs::write(
+		site_config_dir.join(snapshot::SNAP_ARGS_FNAME),
+		toml::to_string(args)?,
+	)?;
+
+	fs::create_dir(snap.data_dir())?;
+	std::fs::OpenOptions::new()
+		.create_new(true)
+		.write(true)
+		.open(snap.0.join(snapshot::META_NAME_FNAME))?
+		.write_all(meta_name.as_bytes())?;
+
+	Ok(snap)
+}
 
-	// TODO: (M) switch to space-separated key-raw/hex pairs for all the other keys
-	sink.write_all(repo::meta_file::line::PFX_NAME)?;
-	write!(sink, " ")?;
-	sink.write_all(&hex::tagged_rawhex::encode(
-		false,
-		path.file_name()
-			.expect("has last component, not ending in ..")
-			.as_bytes(),
-	))?;
-	writeln!(sink)?;
-
-	if let Some(h) = hash {
-		sink.write_all(repo::meta_file::line::PFX_HASH)?;
-		writeln!(sink, " {}", h.to_hex())?;
+/// Removes a snapshot created via [`create_snapshot`] if filling it in failed, so that no
+/// incomplete snapshot is left behind, and passes on `result`
+fn discard_on_error<T>(
+	snap: &Snapshot,
+	result: Result<T, Box<dyn Error>>,
+) -> Result<T, Box<dyn Error>> {
+	if result.is_err() {
+		info!("removing incomplete snapshot {:?}", snap.0);
+		if let Err(e) = fs::remove_dir_all(&snap.0) {
+			warn!("unable to remove incomplete snapshot {:?}: {e}", snap.0);
+		}
 	}
+	result
+}
 
-	file::statx::dump(stx, &mut sink)?;
-
-	// lsattr
-	assert!(stx.stx_mask & libc::STATX_TYPE != 0);
-	use libc::{S_IFBLK, S_IFCHR, S_IFIFO, S_IFLNK, S_IFSOCK};
-	match stx.stx_mode as u32 & libc::S_IFMT {
-		// FS_IOC_GETFLAGS not supported on char/block devices, see ioctl supported only
-		// for dirs and regular files, see also
-		// https://bugs.debian.org/cgi-bin/bugreport.cgi?bug=152029
-		S_IFCHR | S_IFBLK => (),
-		// Symlinks:
-		// - https://lore.kernel.org/linux-xfs/20171101235007.GF22894@wotan.suse.de/T/
-		// - getting a fd usable by the ioctl proves to be difficult
-		// Sockets fail too
-		S_IFSOCK | S_IFLNK => (),
-		// Causes hang on ~/.steam/steam.pipe
-		S_IFIFO => (),
-		// At the moment we emulate lsattr(1), and only handle regular files and
-		// directories
-		_otherwise => file::ioctl_getflags::dump(path, &mut sink)?,
-	}
+/// Completes a snapshot created via [`create_snapshot`] once its data and metadata are written, by
+/// recording its exclusion log, root hash and summary, and signing it if `signing_key` is given
+fn finish_snapshot(
+	snap: &Snapshot,
+	exclusions: &[Exclusion],
+	summary: &Summary,
+	signing_key: Option<&SigningKey>,
+) -> Result<(), Box<dyn Error>> {
+	snapshot::write_exclusions(snap.0.join(snapshot::EXCLUDED_FNAME), exclusions)?;
 
-	file::xattrs::dump(xattr_helper, path, &mut sink)?;
+	let root = repo::merkle::seal(snap)?;
+	info!("snapshot root hash: {}", root.to_hex());
 
-	writeln!(sink, "--")?;
+	fs::write(snap.0.join(repo::summary::FNAME), summary.to_toml()?)?;
 
+	if let Some(key) = signing_key {
+		info!("signing snapshot");
+		repo::signature::sign(snap, key)?;
+	}
 	Ok(())
 }
 
-fn repo_root_or_die() -> io::Result<PathBuf> {
+pub(crate) fn repo_root_or_die() -> io::Result<PathBuf> {
 	let cwd = current_dir()?;
 	let Some(root) = cwd.ancestors().find(|p| Repo::is_valid(p)) else {
 		die(
@@ -968,7 +735,149 @@ fn repo_root_or_die() -> io::Result<PathBuf> {
 			`baktu init <repo_name>`",
 		)
 	};
-	Ok(root.to_path_buf())
+	Ok(Repo::open(root)?.0)
+}
+
+/// Exits if this version of baktu must not modify `repo`, e.g. due to an older format version
+pub(crate) fn writable_or_die(repo: &Repo) -> io::Result<()> {
+	if let Err(e) = repo.check_writable() {
+		die(DATAERR, &format!("{e}, refusing to modify it, exiting"))
+	}
+	Ok(())
+}
+
+/// Formats a byte count using binary prefixes, e.g. `1.5 MiB`
+pub(crate) fn human_bytes(bytes: u64) -> String {
+	const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+	if bytes < 1024 {
+		return format!("{bytes} B");
+	}
+	let mut value = bytes as f64 / 1024.0;
+	let mut unit = 0;
+	while value >= 1024.0 && unit < UNITS.len() - 1 {
+		value /= 1024.0;
+		unit += 1;
+	}
+	format!("{value:.1} {}", UNITS[unit])
+}
+
+/// A location in the current repository, specified as `<site>[/<snapshot>[/<path>]]`
+pub(crate) struct Location {
+	pub site: Site,
+	pub snapshot: Option<Snapshot>,
+	/// Path relative to the snapshot data directory, empty if not specified
+	pub path: PathBuf,
+}
+
+/// Resolves a `<site>[/<snapshot>[/<path>]]` specification relative to the current repository
+fn location_or_die(spec: &Path) -> io::Result<Location> {
+	use std::path::Component;
+
+	let repo = Repo(repo_root_or_die()?);
+
+	let mut components = spec.components().map(|c| match c {
+		Component::Normal(name) => name,
+		_ => die(
+			USAGE,
+			&format!("invalid location {spec:?}, expected `<site>[/<snapshot>[/<path>]]`, exiting"),
+		),
+	});
+
+	let Some(site_name) = components.next() else {
+		die(USAGE, "empty location, exiting")
+	};
+	let site = Site(repo.sites_path().join(site_name));
+	if !site.0.is_dir() {
+		die(NOINPUT, &format!("site {site_name:?} not found, exiting"))
+	}
+
+	let snapshot = match components.next() {
+		None => None,
+		Some(snap_name) => match Snapshot::try_from(site.snaps_path().join(snap_name)) {
+			Ok(snap) => Some(snap),
+			Err(_) => die(
+				NOINPUT,
+				&format!("snapshot {snap_name:?} not found in site {site_name:?}, exiting"),
+			),
+		},
+	};
+
+	Ok(Location {
+		site,
+		snapshot,
+		path: components.collect(),
+	})
+}
+
+/// Resolves a `<site>/<snapshot>` specification relative to the current repository
+fn snapshot_or_die(spec: &Path) -> io::Result<Snapshot> {
+	match location_or_die(spec)? {
+		Location {
+			snapshot: Some(snap),
+			path,
+			..
+		} if path.as_os_str().is_empty() => Ok(snap),
+		_ => die(
+			USAGE,
+			&format!("invalid snapshot {spec:?}, expected `<site>/<snapshot>`, exiting"),
+		),
+	}
+}
+
+/// Returns the snapshots selected by an optional `<site>[/<snapshot>]` spec, all of them if
+/// omitted, each along with its `<site>/<snapshot>` label
+fn snapshots_or_die(spec: Option<&Path>) -> io::Result<Vec<(String, Snapshot)>> {
+	let label = |site: &Site, snap: &Snapshot| {
+		format!(
+			"{}/{}",
+			site.name().to_string_lossy(),
+			snap.name().to_string_lossy()
+		)
+	};
+	let sites = match spec.map(location_or_die).transpose()? {
+		None => {
+			let repo = Repo(repo_root_or_die()?);
+			let mut sites: Vec<Site> = repo.sites()?.into_iter().filter_map(Result::ok).collect();
+			sites.sort_by(|a, b| a.0.cmp(&b.0));
+			sites
+		}
+		Some(Location {
+			site,
+			snapshot: None,
+			..
+		}) => vec![site],
+		Some(Location {
+			site,
+			snapshot: Some(snap),
+			path,
+		}) if path.as_os_str().is_empty() => return Ok(vec![(label(&site, &snap), snap)]),
+		Some(_) => die(
+			USAGE,
+			&format!("invalid location {spec:?}, expected `<site>[/<snapshot>]`, exiting"),
+		),
+	};
+
+	let mut snapshots = Vec::new();
+	for site in sites {
+		for snap in site.snapshots_sorted()? {
+			snapshots.push((label(&site, &snap), snap));
+		}
+	}
+	Ok(snapshots)
+}
+
+fn site_or_die(spec: &Path) -> io::Result<Site> {
+	match location_or_die(spec)? {
+		Location {
+			site,
+			snapshot: None,
+			..
+		} => Ok(site),
+		_ => die(
+			USAGE,
+			&format!("invalid site {spec:?}, expected `<site>`, exiting"),
+		),
+	}
 }
 
 fn repo_site_or_die() -> io::Result<Site> {
@@ -983,9 +892,36 @@ fn repo_site_or_die() -> io::Result<Site> {
 	Ok(Site(site_path.to_owned()))
 }
 
-// TODO: (S) look into reorganizing code to only invoke die() in the CLI code
 // TODO: (S) find if we can avoid having to manually do &format!() for [msg]
 pub fn die(code: ExitCode, msg: &str) -> ! {
 	log::error!("{}", msg);
 	std::process::exit(code)
 }
+
+/// Maps errors returned by commands to exit codes, logging them along with hints where there are
+/// any
+fn report(e: &(dyn Error + 'static)) -> ExitCode {
+	log::error!("{e}, exiting");
+	match e.downcast_ref::<BaktuError>() {
+		Some(BaktuError::Repo { .. } | BaktuError::Corrupt { .. }) => DATAERR,
+		Some(BaktuError::Config { .. } | BaktuError::Unsupported { .. }) => DATAERR,
+		Some(BaktuError::NotFound { .. }) => NOINPUT,
+		Some(BaktuError::Permission { .. }) => {
+			// TODO: (C) look into capabilities or other security mechanisms as a more
+			//	fine-grained way to allow baktu access to [path]
+			log::error!(
+				"You can either 1) exclude the path explicitly and re-run, 2) re-run `baktu` with \
+				sudo or equivalent, or 3) set `exclude.all_eacces` in the site `{}` and re-run with \
+				`--confirm-exclude-all-eacces`",
+				repo::site::config_file::NAME
+			);
+			NOPERM
+		}
+		Some(BaktuError::Source { .. } | BaktuError::Io { .. }) => IOERR,
+		Some(BaktuError::Hook { .. }) => UNAVAILABLE,
+		None => match e.downcast_ref::<io::Error>() {
+			Some(_) => IOERR,
+			None => 1,
+		},
+	}
+}
@@ -0,0 +1,136 @@
+//! Progress display of `baktu snap --progress` on stderr
+
+use std::{
+	io::{stderr, IsTerminal, Write},
+	time::{Duration, Instant},
+};
+
+use log::info;
+
+use crate::{
+	cli::human_bytes,
+	repo::snap::{Estimate, Progress},
+};
+
+/// Interval between redraws of the progress line on a terminal
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Default interval between progress lines when stderr is not a terminal, e.g. in cron jobs
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Shows the progress of a snapshot as a single line redrawn in place on a terminal, or as a line
+/// every [`LOG_INTERVAL`] or given interval otherwise
+pub struct Display {
+	estimate: Option<Estimate>,
+	log_interval: Duration,
+	start: Instant,
+	last_shown: Instant,
+	terminal: bool,
+	/// Whether the progress line is currently on the terminal and needs clearing before other output
+	drawn: bool,
+}
+
+impl Display {
+	pub fn new(log_interval: Option<Duration>) -> Self {
+		let start = Instant::now();
+		Display {
+			estimate: None,
+			log_interval: log_interval.unwrap_or(LOG_INTERVAL),
+			start,
+			last_shown: start,
+			terminal: stderr().is_terminal(),
+			drawn: false,
+		}
+	}
+
+	/// Shows totals and the estimated time remaining from now on. The time spent so far, on the
+	/// pre-scan itself, does not count towards the throughput.
+	pub fn set_estimate(&mut self, estimate: Estimate) {
+		info!(
+			"pre-scan counted {} paths, {}",
+			estimate.files,
+			human_bytes(estimate.bytes)
+		);
+		self.estimate = Some(estimate);
+		self.start = Instant::now();
+		self.last_shown = self.start;
+	}
+
+	/// Clears the progress line, if drawn, so that other output starts on a clean line
+	pub fn clear(&mut self) {
+		if self.drawn {
+			eprint!("\r\x1b[K");
+			self.drawn = false;
+		}
+	}
+
+	fn line(&self, progress: &Progress) -> String {
+		let elapsed = self.start.elapsed().as_secs_f64();
+		let throughput = match elapsed > 0.0 {
+			true => (progress.bytes_read as f64 / elapsed) as u64,
+			false => 0,
+		};
+		let mut line = match self.estimate {
+			Some(estimate) => format!(
+				"{}/{} paths, {}/{}",
+				progress.files,
+				estimate.files,
+				human_bytes(progress.bytes_read),
+				human_bytes(estimate.bytes)
+			),
+			None => format!(
+				"{} paths, {}",
+				progress.files,
+				human_bytes(progress.bytes_read)
+			),
+		};
+		line += &format!(
+			" ({}/s), {} deduplicated",
+			human_bytes(throughput),
+			progress.dedup_hits
+		);
+		if let Some(eta) = self.eta(progress, elapsed) {
+			line += &format!(", ETA {}", humantime::format_duration(eta));
+		}
+		line
+	}
+
+	/// Extrapolates the time remaining from the share of bytes read so far, or of paths for
+	/// snapshots without file contents to speak of
+	fn eta(&self, progress: &Progress, elapsed: f64) -> Option<Duration> {
+		let estimate = self.estimate?;
+		let (done, total) = match estimate.bytes {
+			0 => (progress.files, estimate.files),
+			_ => (progress.bytes_read, estimate.bytes),
+		};
+		if done == 0 {
+			return None;
+		}
+		// The source may have grown since the pre-scan, so clamp rather than go negative
+		let remaining = total.saturating_sub(done) as f64 * elapsed / done as f64;
+		Some(Duration::from_secs(remaining.round() as u64))
+	}
+
+	/// Shows `progress`, unless it has been shown too recently
+	pub fn update(&mut self, progress: &Progress) {
+		let interval = match self.terminal {
+			true => REDRAW_INTERVAL,
+			false => self.log_interval,
+		};
+		if self.last_shown.elapsed() < interval {
+			return;
+		}
+		self.last_shown = Instant::now();
+
+		let line = self.line(progress);
+		if self.terminal {
+			let mut stderr = stderr().lock();
+			// Best effort, a broken stderr is no reason to abort the snapshot
+			let _ = write!(stderr, "\r{line}\x1b[K");
+			let _ = stderr.flush();
+			self.drawn = true;
+		} else {
+			eprintln!("progress: {line}");
+		}
+	}
+}
@@ -0,0 +1,136 @@
+//! Errors returned by the library API
+
+use std::{
+	fmt, io,
+	path::{Path, PathBuf},
+};
+
+/// An error, along with the path it concerns where there is one
+#[derive(Debug)]
+pub enum Error {
+	/// `path` is not a baktu repository, or one this version of baktu must not modify
+	Repo { path: PathBuf, reason: String },
+	/// The repository is corrupt, with `path` being the offending file or directory
+	Corrupt { path: PathBuf, reason: String },
+	/// `path` does not exist, e.g. a site or snapshot that was asked for
+	NotFound { path: PathBuf },
+	/// The site configuration is incomplete or invalid, with `path` being the offending file or
+	/// configured path
+	Config { path: PathBuf, reason: String },
+	/// Permission was denied for `action` on `path`, e.g. `statx` on a source path
+	Permission { path: PathBuf, action: String },
+	/// Reading `path` from the source dataset failed
+	Source { path: PathBuf, source: io::Error },
+	/// `path` in the source dataset can not be represented in a snapshot
+	Unsupported { path: PathBuf, reason: String },
+	/// The site's `name` hook, e.g. `pre_snap`, could not be run or failed
+	Hook { name: String, reason: String },
+	/// Any other I/O error, concerning `path` where it is known
+	Io {
+		path: Option<PathBuf>,
+		source: io::Error,
+	},
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+	/// Returns the path the error concerns, if known
+	pub fn path(&self) -> Option<&Path> {
+		match self {
+			Error::Repo { path, .. }
+			| Error::Corrupt { path, .. }
+			| Error::NotFound { path }
+			| Error::Config { path, .. }
+			| Error::Permission { path, .. }
+			| Error::Source { path, .. }
+			| Error::Unsupported { path, .. } => Some(path),
+			Error::Io { path, .. } => path.as_deref(),
+			Error::Hook { .. } => None,
+		}
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::Repo { path, reason } => write!(f, "{path:?}: {reason}"),
+			Error::Corrupt { path, reason } => write!(f, "repo corrupt: {path:?}: {reason}"),
+			Error::NotFound { path } => write!(f, "{path:?} not found"),
+			Error::Config { path, reason } => write!(f, "{path:?}: {reason}"),
+			Error::Permission { path, action } => {
+				write!(f, "permission denied during {action} for {path:?}")
+			}
+			Error::Source { path, source } => write!(f, "unable to read {path:?}: {source}"),
+			Error::Unsupported { path, reason } => write!(f, "{path:?}: {reason}"),
+			Error::Io {
+				path: Some(path),
+				source,
+			} => write!(f, "{path:?}: {source}"),
+			Error::Io { path: None, source } => source.fmt(f),
+			Error::Hook { name, reason } => write!(f, "{name} hook failed: {reason}"),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Source { source, .. } | Error::Io { source, .. } => Some(source),
+			_ => None,
+		}
+	}
+}
+
+impl From<io::Error> for Error {
+	fn from(source: io::Error) -> Self {
+		Error::Io { path: None, source }
+	}
+}
+
+impl From<walkdir::Error> for Error {
+	fn from(e: walkdir::Error) -> Self {
+		Error::Io {
+			path: e.path().map(Path::to_path_buf),
+			source: e.into(),
+		}
+	}
+}
+
+/// Lets code still returning [`io::Result`] use `?` on library calls
+impl From<Error> for io::Error {
+	fn from(e: Error) -> Self {
+		match e {
+			Error::Io { path: None, source } => source,
+			Error::NotFound { .. } => io::Error::new(io::ErrorKind::NotFound, e),
+			Error::Permission { .. } => io::Error::new(io::ErrorKind::PermissionDenied, e),
+			Error::Corrupt { .. } => io::Error::new(io::ErrorKind::InvalidData, e),
+			e => io::Error::other(e),
+		}
+	}
+}
+
+/// Attaches paths to I/O errors
+pub(crate) trait IoResultExt<T> {
+	/// Converts the error to [`Error::Io`] concerning `path`
+	fn at(self, path: &Path) -> Result<T>;
+
+	/// Converts the error to [`Error::Source`], for errors reading `path` from the source dataset
+	fn reading(self, path: &Path) -> Result<T>;
+}
+
+impl<T> IoResultExt<T> for io::Result<T> {
+	fn at(self, path: &Path) -> Result<T> {
+		self.map_err(|source| Error::Io {
+			path: Some(path.to_path_buf()),
+			source,
+		})
+	}
+
+	fn reading(self, path: &Path) -> Result<T> {
+		self.map_err(|source| Error::Source {
+			path: path.to_path_buf(),
+			source,
+		})
+	}
+}
@@ -18,11 +18,11 @@ impl FileKey {
 		})
 	}
 
-	pub fn from_statx(stx: &statx) -> FileKey {
-		assert!(stx.stx_mask & libc::STATX_INO != 0);
-		FileKey {
+	pub fn from_statx(stx: &statx) -> std::io::Result<FileKey> {
+		super::statx::require(stx, libc::STATX_INO, "ino")?;
+		Ok(FileKey {
 			dev: libc::makedev(stx.stx_dev_major, stx.stx_dev_minor),
 			ino: stx.stx_ino,
-		}
+		})
 	}
 }
@@ -0,0 +1,111 @@
+use std::{io, path::Path};
+
+use nix::sys::statfs::{self, statfs};
+use serde::Deserialize;
+
+/// Filesystem types that can be detected via the `f_type` magic number returned by `statfs(2)`.
+///
+/// Note that some filesystems share their magic number, e.g. `devtmpfs` is reported as `tmpfs`,
+/// and `ext2`/`ext3` as `ext4`.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FsType {
+	Autofs,
+	Bpf,
+	Btrfs,
+	Cgroup,
+	Cgroup2,
+	Debugfs,
+	Devpts,
+	Ext4,
+	Fuse,
+	Hugetlbfs,
+	Nfs,
+	Nsfs,
+	Overlay,
+	Proc,
+	Securityfs,
+	Sysfs,
+	Tmpfs,
+	Tracefs,
+	Xfs,
+}
+
+impl FsType {
+	const ALL: [FsType; 19] = [
+		FsType::Autofs,
+		FsType::Bpf,
+		FsType::Btrfs,
+		FsType::Cgroup,
+		FsType::Cgroup2,
+		FsType::Debugfs,
+		FsType::Devpts,
+		FsType::Ext4,
+		FsType::Fuse,
+		FsType::Hugetlbfs,
+		FsType::Nfs,
+		FsType::Nsfs,
+		FsType::Overlay,
+		FsType::Proc,
+		FsType::Securityfs,
+		FsType::Sysfs,
+		FsType::Tmpfs,
+		FsType::Tracefs,
+		FsType::Xfs,
+	];
+
+	fn magic(self) -> statfs::FsType {
+		match self {
+			FsType::Autofs => statfs::AUTOFS_SUPER_MAGIC,
+			FsType::Bpf => statfs::BPF_FS_MAGIC,
+			FsType::Btrfs => statfs::BTRFS_SUPER_MAGIC,
+			FsType::Cgroup => statfs::CGROUP_SUPER_MAGIC,
+			FsType::Cgroup2 => statfs::CGROUP2_SUPER_MAGIC,
+			FsType::Debugfs => statfs::DEBUGFS_MAGIC,
+			FsType::Devpts => statfs::DEVPTS_SUPER_MAGIC,
+			FsType::Ext4 => statfs::EXT4_SUPER_MAGIC,
+			FsType::Fuse => statfs::FUSE_SUPER_MAGIC,
+			FsType::Hugetlbfs => statfs::HUGETLBFS_MAGIC,
+			FsType::Nfs => statfs::NFS_SUPER_MAGIC,
+			FsType::Nsfs => statfs::NSFS_MAGIC,
+			FsType::Overlay => statfs::OVERLAYFS_SUPER_MAGIC,
+			FsType::Proc => statfs::PROC_SUPER_MAGIC,
+			FsType::Securityfs => statfs::SECURITYFS_MAGIC,
+			FsType::Sysfs => statfs::SYSFS_MAGIC,
+			FsType::Tmpfs => statfs::TMPFS_MAGIC,
+			FsType::Tracefs => statfs::TRACEFS_MAGIC,
+			FsType::Xfs => statfs::XFS_SUPER_MAGIC,
+		}
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			FsType::Autofs => "autofs",
+			FsType::Bpf => "bpf",
+			FsType::Btrfs => "btrfs",
+			FsType::Cgroup => "cgroup",
+			FsType::Cgroup2 => "cgroup2",
+			FsType::Debugfs => "debugfs",
+			FsType::Devpts => "devpts",
+			FsType::Ext4 => "ext4",
+			FsType::Fuse => "fuse",
+			FsType::Hugetlbfs => "hugetlbfs",
+			FsType::Nfs => "nfs",
+			FsType::Nsfs => "nsfs",
+			FsType::Overlay => "overlay",
+			FsType::Proc => "proc",
+			FsType::Securityfs => "securityfs",
+			FsType::Sysfs => "sysfs",
+			FsType::Tmpfs => "tmpfs",
+			FsType::Tracefs => "tracefs",
+			FsType::Xfs => "xfs",
+		}
+	}
+
+	/// Returns the type of the filesystem containing `path`, or `None` if it is not one we know
+	/// of. Follows symlinks, like `statfs(2)` itself.
+	pub fn of(path: &Path) -> io::Result<Option<FsType>> {
+		let magic = statfs(path)?.filesystem_type();
+		Ok(Self::ALL.into_iter().find(|t| t.magic() == magic))
+	}
+}
@@ -1,6 +1,7 @@
-use std::{error::Error, fs::OpenOptions, io::Write, os::fd::AsRawFd};
+use std::{fs::OpenOptions, io, os::fd::AsRawFd};
 
-pub fn dump(path: &std::path::Path, sink: &mut Box<dyn Write>) -> Result<(), Box<dyn Error>> {
+/// Returns the inode flags of `path` in `lsattr(1)` notation, without the dashes for unset flags
+pub fn lsattr(path: &std::path::Path) -> io::Result<String> {
 	let flags = {
 		// auto-closed (ignoring errors) by Drop impl
 		let file = OpenOptions::new().read(true).open(path)?;
@@ -8,13 +9,15 @@ pub fn dump(path: &std::path::Path, sink: &mut Box<dyn Write>) -> Result<(), Box
 		let fd = file.as_raw_fd();
 		let mut flags: std::os::raw::c_long = 0;
 		let ret = unsafe { ioctls::fs_ioc_getflags(fd, &mut flags) };
-		assert!(ret == 0);
+		if ret != 0 {
+			return Err(io::Error::last_os_error());
+		}
 		flags
 	};
 
 	use linux_raw_sys::general::*;
 
-	write!(sink, "lsattr ")?;
+	let mut lsattr = String::new();
 	for (ch, flag) in [
 		('A', FS_NOATIME_FL),
 		('C', FS_NOCOW_FL),
@@ -40,9 +43,8 @@ pub fn dump(path: &std::path::Path, sink: &mut Box<dyn Write>) -> Result<(), Box
 		('x', FS_DAX_FL),
 	] {
 		if flags & flag as i64 != 0 {
-			write!(sink, "{}", ch)?;
+			lsattr.push(ch);
 		}
 	}
-	writeln!(sink)?;
-	Ok(())
+	Ok(lsattr)
 }
@@ -1,22 +1,98 @@
 pub mod filekey;
+pub mod fs_type;
 pub mod ioctl_getflags;
 pub mod statx;
 pub mod xattrs;
 
 use std::{
 	fs::File,
-	io::{self, BufReader, Read},
+	io::{self, BufReader, Read, Write},
 	path::Path,
 };
 
 use blake3::Hash;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 use crate::util::ext::PathExt;
 
+/// File types, named as in the `type` line of metadata records
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileType {
+	Fifo,
+	Chr,
+	Dir,
+	Blk,
+	Reg,
+	Lnk,
+	Sock,
+}
+
+impl FileType {
+	/// Maps the `S_IFMT` bits of `mode` to a file type
+	pub fn from_mode(mode: u32) -> Option<FileType> {
+		match mode & libc::S_IFMT {
+			libc::S_IFIFO => Some(FileType::Fifo),
+			libc::S_IFCHR => Some(FileType::Chr),
+			libc::S_IFDIR => Some(FileType::Dir),
+			libc::S_IFBLK => Some(FileType::Blk),
+			libc::S_IFREG => Some(FileType::Reg),
+			libc::S_IFLNK => Some(FileType::Lnk),
+			libc::S_IFSOCK => Some(FileType::Sock),
+			_ => None,
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<FileType> {
+		[
+			FileType::Fifo,
+			FileType::Chr,
+			FileType::Dir,
+			FileType::Blk,
+			FileType::Reg,
+			FileType::Lnk,
+			FileType::Sock,
+		]
+		.into_iter()
+		.find(|ft| ft.name() == name)
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			FileType::Fifo => "fifo",
+			FileType::Chr => "chr",
+			FileType::Dir => "dir",
+			FileType::Blk => "blk",
+			FileType::Reg => "reg",
+			FileType::Lnk => "lnk",
+			FileType::Sock => "sock",
+		}
+	}
+}
+
 pub fn is_valid_cachedir_tag(path: &Path) -> bool {
 	path.content_starts_with(b"Signature: 8a477f597d28d172789f06886806bc55")
 }
 
+/// Copies all of `reader` to `writer`, returning the number of bytes copied and their BLAKE3
+/// digest
+pub fn copy_hashed(reader: &mut impl Read, writer: &mut impl Write) -> io::Result<(u64, Hash)> {
+	let mut hasher = blake3::Hasher::new();
+	let mut buffer = vec![0; 65536];
+	let mut total = 0u64;
+	loop {
+		match reader.read(&mut buffer)? {
+			0 => return Ok((total, hasher.finalize())),
+			n => {
+				hasher.update(&buffer[..n]);
+				writer.write_all(&buffer[..n])?;
+				total += n as u64;
+			}
+		}
+	}
+}
+
 /// Calculates BLAKE3 digest of file
 pub fn b3sum(file_path: &Path) -> io::Result<Hash> {
 	let file = File::open(file_path)?;
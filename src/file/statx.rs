@@ -1,8 +1,9 @@
 use std::{
-	io::{self, Write},
+	io,
 	mem::MaybeUninit,
 	os::unix::prelude::OsStrExt,
 	path::Path,
+	time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use libc::statx;
@@ -37,109 +38,37 @@ pub fn get(path: &Path) -> io::Result<libc::statx> {
 	}
 }
 
-/// Writes a lossless textual representation of `statx()` data of `path` to `sink`. Explicitly not
-/// UTF-8 safe. Does not follow symlinks.
-pub fn dump(stx: statx, sink: &mut Box<dyn Write>) -> io::Result<()> {
-	writeln!(sink, "blksize {}", stx.stx_blksize)?;
-
-	write!(sink, "attributes")?;
-	let mut print_attr = |flag: i32, name: &str| -> std::io::Result<()> {
-		if (stx.stx_attributes_mask & flag as u64) != 0 && (stx.stx_attributes & flag as u64) != 0 {
-			write!(sink, " {}", name)
-		} else {
-			Ok(())
-		}
-	};
-	print_attr(libc::STATX_ATTR_COMPRESSED, "compressed")?;
-	print_attr(libc::STATX_ATTR_IMMUTABLE, "immutable")?;
-	print_attr(libc::STATX_ATTR_APPEND, "append")?;
-	print_attr(libc::STATX_ATTR_NODUMP, "nodump")?;
-	print_attr(libc::STATX_ATTR_ENCRYPTED, "encrypted")?;
-	print_attr(libc::STATX_ATTR_VERITY, "verity")?;
-	print_attr(libc::STATX_ATTR_DAX, "dax")?;
-	writeln!(sink)?;
-
-	assert!(stx.stx_mask & libc::STATX_NLINK != 0);
-	writeln!(sink, "nlink {}", stx.stx_nlink)?;
-
-	assert!(stx.stx_mask & libc::STATX_UID != 0);
-	writeln!(sink, "uid {}", stx.stx_uid)?;
-
-	assert!(stx.stx_mask & libc::STATX_GID != 0);
-	writeln!(sink, "gid {}", stx.stx_gid)?;
-
-	// ~S_IFMT from https://man7.org/linux/man-pages/man2/statx.2.html
-	assert!(stx.stx_mask & libc::STATX_MODE != 0);
-	writeln!(sink, "mode {:o}", stx.stx_mode as u32 & !libc::S_IFMT)?;
-
-	assert!(stx.stx_mask & libc::STATX_TYPE != 0);
-	match stx.stx_mode as u32 & libc::S_IFMT {
-		libc::S_IFIFO => writeln!(sink, "type fifo")?,
-		libc::S_IFCHR => writeln!(sink, "type chr")?,
-		libc::S_IFDIR => writeln!(sink, "type dir")?,
-		libc::S_IFBLK => writeln!(sink, "type blk")?,
-		libc::S_IFREG => writeln!(sink, "type reg")?,
-		libc::S_IFLNK => writeln!(sink, "type lnk")?,
-		libc::S_IFSOCK => writeln!(sink, "type sock")?,
-		unknown => writeln!(sink, "type unknown: {}", unknown)?,
-	}
-
-	assert!(stx.stx_mask & libc::STATX_INO != 0);
-	writeln!(sink, "ino {}", stx.stx_ino)?;
-
-	assert!(stx.stx_mask & libc::STATX_SIZE != 0);
-	writeln!(sink, "size {}", stx.stx_size)?;
-
-	assert!(stx.stx_mask & libc::STATX_BLOCKS != 0);
-	writeln!(sink, "blocks {}", stx.stx_blocks)?;
-
-	assert!(stx.stx_mask & libc::STATX_ATIME != 0);
-	writeln!(
-		sink,
-		"atime {}.{:09}",
-		stx.stx_atime.tv_sec, stx.stx_atime.tv_nsec
-	)?;
-
-	assert!(stx.stx_mask & libc::STATX_BTIME != 0);
-	writeln!(
-		sink,
-		"btime {}.{:09}",
-		stx.stx_btime.tv_sec, stx.stx_btime.tv_nsec
-	)?;
-
-	assert!(stx.stx_mask & libc::STATX_CTIME != 0);
-	writeln!(
-		sink,
-		"ctime {}.{:09}",
-		stx.stx_ctime.tv_sec, stx.stx_ctime.tv_nsec
-	)?;
-
-	assert!(stx.stx_mask & libc::STATX_MTIME != 0);
-	writeln!(
-		sink,
-		"mtime {}.{:09}",
-		stx.stx_mtime.tv_sec, stx.stx_mtime.tv_nsec
-	)?;
-
-	assert!(stx.stx_mask & libc::STATX_TYPE != 0);
-	match stx.stx_mode as u32 & libc::S_IFMT {
-		libc::S_IFCHR | libc::S_IFBLK => {
-			writeln!(sink, "rdev_major {}", stx.stx_rdev_major)?;
-			writeln!(sink, "rdev_minor {}", stx.stx_rdev_minor)?;
-		}
-		_ => (),
+/// Converts a `statx()` timestamp to a `SystemTime`, handling pre-epoch values
+pub fn to_system_time(ts: libc::statx_timestamp) -> SystemTime {
+	let since_epoch = Duration::new(ts.tv_sec.unsigned_abs(), 0);
+	let base = if ts.tv_sec < 0 {
+		UNIX_EPOCH - since_epoch
+	} else {
+		UNIX_EPOCH + since_epoch
 	};
+	base + Duration::from_nanos(ts.tv_nsec.into())
+}
 
-	writeln!(sink, "dev_major {}", stx.stx_dev_major)?;
-	writeln!(sink, "dev_minor {}", stx.stx_dev_minor)?;
-
-	assert!(stx.stx_mask & libc::STATX_MNT_ID != 0);
-	writeln!(sink, "mnt_id {}", stx.stx_mnt_id)?;
-
-	if stx.stx_mask & libc::STATX_DIOALIGN != 0 {
-		writeln!(sink, "dio_mem_align {}", stx.stx_dio_mem_align)?;
-		writeln!(sink, "dio_offset_align {}", stx.stx_dio_offset_align)?;
+/// Fails with [`io::ErrorKind::Unsupported`] unless `statx()` returned the `flag` field, named
+/// `field` in the error. Not all kernels and filesystems provide all fields, e.g. btime or mnt_id.
+pub fn require(stx: &statx, flag: u32, field: &str) -> io::Result<()> {
+	if stx.stx_mask & flag != 0 {
+		Ok(())
+	} else {
+		Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			format!("statx() did not return {field}"),
+		))
 	}
+}
 
-	Ok(())
+/// Returns the `(device, mount ID)` pair identifying the mount `stx` is from, which tells apart
+/// bind mounts of the same filesystem too. Fails like [`require`] unless `statx()` returned the
+/// mount ID.
+pub fn mount(stx: &statx) -> io::Result<(u64, u64)> {
+	require(stx, libc::STATX_MNT_ID, "mnt_id")?;
+	Ok((
+		libc::makedev(stx.stx_dev_major, stx.stx_dev_minor),
+		stx.stx_mnt_id,
+	))
 }
@@ -1,5 +1,4 @@
 use std::{
-	error::Error,
 	io::{self, BufRead, BufReader, Write},
 	os::unix::prelude::OsStrExt,
 	process::{Child, ChildStdin},
@@ -8,22 +7,18 @@ use std::{
 use caps::{CapSet, Capability};
 use log::info;
 
-use crate::{cli::die, util::hex};
+use crate::{
+	error::{Error, IoResultExt, Result},
+	util::hex,
+};
 
-/// Writes a lossless textual representation of *all* extended attributes of `path` to `sink` by
-/// requiring `CAP_SYS_ADMIN`. Explicitly not UTF-8 safe. Does not follow symlinks.
-pub fn dump(
+/// Returns *all* extended attributes of `path` as key-value pairs by requiring `CAP_SYS_ADMIN`.
+/// Does not follow symlinks.
+pub fn get(
 	xattr_helper: &mut Option<Helper>,
 	path: &std::path::Path,
-	m: &mut Box<dyn Write>,
-) -> Result<(), Box<dyn Error>> {
-	fn print_kv(sink: &mut Box<dyn Write>, key: Vec<u8>, value: Vec<u8>) -> io::Result<()> {
-		write!(sink, "x k.")?;
-		sink.write_all(&hex::tagged_rawhex::encode(true, &key))?;
-		write!(sink, " v.")?;
-		sink.write_all(&hex::tagged_rawhex::encode(false, &value))?;
-		writeln!(sink)
-	}
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+	let mut xattrs = Vec::new();
 
 	// Note that we're explicitly not sorting by key here, to preserve some implementations-specific
 	// information. For example, it seems key fetching order is consistent with key creation order,
@@ -37,50 +32,64 @@ pub fn dump(
 			stdin.write_all(path.as_os_str().as_bytes())?;
 			stdin.write_all(b"\0")?;
 
-			for line in stdout_lines
-				.map(|line_res| line_res.expect("reading from the helper should not fail"))
-				.take_while(|line| line != "--")
-			{
-				let tokens: Vec<_> = line.split(' ').collect();
+			for line in stdout_lines {
+				let line = line?;
+				if line == "--" {
+					break;
+				}
 
-				print_kv(
-					m,
-					hex::decode(tokens[0].as_bytes()),
-					hex::decode(tokens[1].as_bytes()),
-				)?;
+				let kv = line.split_once(' ').and_then(|(k, v)| {
+					Some((
+						hex::try_decode(k.as_bytes())?,
+						hex::try_decode(v.as_bytes())?,
+					))
+				});
+				let Some((key, value)) = kv else {
+					return Err(io::Error::new(
+						io::ErrorKind::InvalidData,
+						format!("malformed output from `get-all-xattrs`: {line:?}"),
+					)
+					.into());
+				};
+				xattrs.push((key, value));
 			}
 
 			if let Ok(Some(status)) = process.try_wait() {
-				die(
-					exitcode::SOFTWARE,
-					&format!(
-						"Unexpected exit of `get-all-xattrs` with '{}', exiting.",
-						status
-					),
-				);
+				return Err(io::Error::other(format!(
+					"unexpected exit of `get-all-xattrs` with '{status}' while reading {path:?}"
+				))
+				.into());
 			}
 		}
 		None => {
 			// TODO: (C) can we do this with a context manager?
 			log::trace!("raising CAP_SYS_ADMIN before getting xattrs");
-			caps::raise(None, CapSet::Effective, Capability::CAP_SYS_ADMIN)?;
-
-			for key in xattr::list(path).unwrap() {
-				print_kv(
-					m,
-					key.as_bytes().to_owned(),
-					// FIXME: deeper investigation into the double wrapping, swap the unwraps for
-					// appropriate expects and error handling
-					xattr::get(path, key).unwrap().unwrap(),
-				)?;
-			}
+			caps::raise(None, CapSet::Effective, Capability::CAP_SYS_ADMIN).map_err(|e| {
+				Error::Permission {
+					path: path.to_path_buf(),
+					action: format!("raising CAP_SYS_ADMIN ({e})"),
+				}
+			})?;
+
+			let res = (|| -> Result<()> {
+				for key in xattr::list(path).reading(path)? {
+					// None if the attribute was removed since listing it
+					if let Some(value) = xattr::get(path, &key).reading(path)? {
+						xattrs.push((key.as_bytes().to_vec(), value));
+					}
+				}
+				Ok(())
+			})();
 
+			// Dropped even if reading failed, so the snapshot can go on with the next path
 			log::trace!("dropping CAP_SYS_ADMIN after getting xattrs");
-			caps::drop(None, CapSet::Effective, Capability::CAP_SYS_ADMIN)?;
+			caps::drop(None, CapSet::Effective, Capability::CAP_SYS_ADMIN)
+				.map_err(io::Error::other)?;
+			res?;
 		}
 	};
 
-	Ok(())
+	Ok(xattrs)
 }
 
 pub struct Helper {
@@ -90,13 +99,15 @@ pub struct Helper {
 }
 
 impl Helper {
-	pub fn init_opt() -> Result<Option<Helper>, Box<dyn Error>> {
+	pub fn init_opt() -> io::Result<Option<Helper>> {
 		use std::process::Stdio;
 
 		use std::process::Command;
 
 		Ok(
-			if caps::has_cap(None, CapSet::Permitted, Capability::CAP_SYS_ADMIN)? {
+			if caps::has_cap(None, CapSet::Permitted, Capability::CAP_SYS_ADMIN)
+				.map_err(io::Error::other)?
+			{
 				None
 			} else {
 				info!(
@@ -129,14 +140,15 @@ impl Helper {
 							stdout_lines,
 						})
 					}
-					Err(e) => die(
-						exitcode::SOFTWARE,
-						&format!(
-							"Got {:?} while trying to spawn `get-all-xattrs`, exiting. Ensure that \
-							you're not using '~' instead of the full path in your PATH variable.",
-							e
-						),
-					),
+					Err(e) => {
+						return Err(io::Error::new(
+							e.kind(),
+							format!(
+								"unable to spawn `get-all-xattrs`: {e}. Ensure that you're not using \
+								'~' instead of the full path in your PATH variable."
+							),
+						))
+					}
 				}
 			},
 		)
@@ -1,7 +1,16 @@
+//! Creation and manipulation of backup repositories in a simple augmented rsnapshot-like format
+//!
+//! The library API opens repositories via [`repo::Repo::open`], creates snapshots via
+//! [`repo::site::Site::snapshot`] and reads their metadata via [`repo::snapshot::Snapshot::records`].
+//! Failures are returned as [`Error`], leaving it to the caller to decide how to handle them.
+
 mod cli;
+pub mod error;
 mod file;
-mod repo;
+pub mod repo;
 mod util;
 
 #[doc(hidden)]
 pub use cli::Baktu;
+pub use error::{Error, Result};
+pub use file::FileType;
@@ -1,4 +1,4 @@
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> std::process::ExitCode {
 	use clap::Parser;
-	baktu::Baktu::parse().exec()
+	baktu::Baktu::parse().run()
 }
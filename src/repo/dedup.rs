@@ -0,0 +1,202 @@
+//! Content deduplication against the files already stored in a repository
+
+use std::{
+	collections::HashMap,
+	fs::File,
+	io::{self, Read},
+	path::{Path, PathBuf},
+};
+
+use blake3::Hash;
+use log::warn;
+use pathdiff::diff_paths;
+
+use super::Repo;
+use crate::error::Result;
+
+/// Size below which files are copied rather than deduplicated. We ought to need at least a byte to
+/// create a meaningful symlink when deduplicating, thus a minimum sensible threshold would be
+/// larger.
+pub const MIN_FILE_SIZE: u64 = 2;
+
+/// Map from file hash to the backing files in the repository with that hash. Excludes files too
+/// small to be deduplicated.
+// TODO: (C) consider if using a segment trie for path storage would make sense - trading space
+//   (RAM, in this case) for time - given that we'll be dealing with something on the order of 10^5
+//   entries at a minimum.
+// TODO: (S) make projections for when we'll need to switch to a more appropriate key-value storage
+//   approach, do preliminary research on possible alternatives (e.g. sqlite) and their trade-offs.
+#[derive(Debug, Default)]
+pub struct Index {
+	hash2paths: HashMap<Hash, Vec<PathBuf>>,
+}
+
+impl Index {
+	/// Builds the index from the records of all numbered snapshots in the repository, skipping
+	/// partial copies made by `pull`
+	pub fn load(repo: &Repo) -> Result<Self> {
+		let mut index = Index::default();
+		for site_res in repo.sites()? {
+			for snap in site_res?.snapshots_sorted()? {
+				for meta_res in snap.meta_files()? {
+					let meta = meta_res?;
+					for record in meta.records()? {
+						if let Some((h, p)) = record.get_hash_path_opt(&meta.0)? {
+							index.insert(h, p);
+						}
+					}
+				}
+			}
+		}
+		Ok(index)
+	}
+
+	/// Adds a backing file with content hash `hash`, returning whether the hash is new to the
+	/// index
+	// TODO: (C) consider keeping a histogram of bucket sizes while figuring out how much of the
+	// file to use for fake_b3sum, *if* we switch back to fake_b3sum
+	pub fn insert(&mut self, hash: Hash, path: PathBuf) -> bool {
+		match self.hash2paths.get_mut(&hash) {
+			Some(paths) => {
+				warn!("hash collision with file {path:?}");
+				paths.push(path);
+				false
+			}
+			None => {
+				self.hash2paths.insert(hash, vec![path]);
+				true
+			}
+		}
+	}
+
+	/// Drops a backing file that is about to be removed or overwritten
+	pub fn remove(&mut self, hash: Hash, path: &Path) {
+		if let Some(paths) = self.hash2paths.get_mut(&hash) {
+			paths.retain(|p| p != path);
+			if paths.is_empty() {
+				self.hash2paths.remove(&hash);
+			}
+		}
+	}
+
+	/// Returns a backing file with the same content as `path`, whose hash is `hash`, if any. The
+	/// content of candidates is compared byte by byte, so hash collisions can not cause data loss.
+	pub fn find(&self, hash: Hash, path: &Path) -> io::Result<Option<PathBuf>> {
+		// might need to bump fake_b3sum to 512 or more bytes if too many false positives. See
+		// "histogram" TODO above
+		match self.hash2paths.get(&hash) {
+			None => Ok(None),
+			Some(paths) => {
+				for candidate in paths {
+					if file_cmp(path, candidate)? {
+						return Ok(Some(candidate.to_path_buf()));
+					}
+				}
+				Ok(None)
+			}
+		}
+	}
+}
+
+/// Represents `dst` as a deduplicated file, via a relative symlink to `backing`, an absolute path
+pub fn link(backing: &Path, dst: &Path) -> io::Result<()> {
+	// Pros/cons of using hard links:
+	//	- introduces limits - 65K on ext4, according to
+	//		https://unix.stackexchange.com/questions/5629/is-there-a-limit-of-hardlinks-for-one-file
+	//	- removes the possibility to optimize duplicate detection in repo clients by checking
+	//		meta.is_deduplicated only for symlinks
+	//	- introduces hard links into the repo, which introduces additional concerns for operating
+	//		on it with tar, rsync, etc.
+	//	± duplicate representation at the data level does not depend on which one is encountered
+	//		first. Meta level is still affected, which might need to be taken into account
+	//	+ presumably saves a few bytes in some cases, as we can just use a dentry, not needing
+	//		space for the relative path.
+
+	// TODO: (M) test that moving a baktu repo doesn't break these
+	std::os::unix::fs::symlink(
+		diff_paths(backing, dst.parent().expect("dedup dest has parent"))
+			.expect("should work for 2 absolute paths"),
+		dst,
+	)
+}
+
+/// Short-circuiting byte-by-byte comparison.
+/// Of course the usual considerations apply:
+/// - read files in chunks [f9]
+/// - compare at least usize bytes at a time [f10]
+/// - SIMD if easy enough, might help or not
+///
+/// cmp perf with 4K in case we get no gains from going down to a "sector" size, see [f9]
+///
+/// semi-related: <https://lib.rs/crates/dupe-krill> from [f11] describes an interesting approach
+/// to dedup via LazilyHashing<File> -> Vec<Path>
+///
+/// [f9]: https://users.rust-lang.org/t/efficient-way-of-checking-if-two-files-have-the-same-content/74735/9
+/// [f10]: https://users.rust-lang.org/t/efficient-way-of-checking-if-two-files-have-the-same-content/74735/10
+/// [f11]: https://users.rust-lang.org/t/efficient-way-of-checking-if-two-files-have-the-same-content/74735/11
+fn file_cmp(p1: &Path, p2: &Path) -> io::Result<bool> {
+	let mut f1 = File::open(p1)?;
+	let mut f2 = File::open(p2)?;
+
+	if f1.metadata()?.len() != f2.metadata()?.len() {
+		return Ok(false);
+	}
+
+	const BUF_SIZE: usize = 64 * 1024;
+	let b1 = &mut [0; BUF_SIZE];
+	let b2 = &mut [0; BUF_SIZE];
+
+	loop {
+		// read() may return short counts, so fill as much of both buffers as the files allow
+		let f1_read_len = read_full(&mut f1, b1)?;
+		let f2_read_len = read_full(&mut f2, b2)?;
+
+		if f1_read_len != f2_read_len {
+			// Equally sized files changing under us
+			return Ok(false);
+		}
+
+		if f1_read_len == 0 {
+			return Ok(true);
+		}
+
+		if b1[..f1_read_len] != b2[..f2_read_len] {
+			return Ok(false);
+		}
+	}
+}
+
+/// Reads until `buf` is full or the end of the file is reached, returning the number of bytes read
+fn read_full(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+	let mut len = 0;
+	while len < buf.len() {
+		match file.read(&mut buf[len..])? {
+			0 => break,
+			n => len += n,
+		}
+	}
+	Ok(len)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn file_cmp_content() {
+		let dir = assert_fs::TempDir::new().unwrap();
+		let write = |name: &str, content: &[u8]| {
+			let path = dir.path().join(name);
+			std::fs::write(&path, content).unwrap();
+			path
+		};
+		let a = write("a", b"same size 1");
+		let b = write("b", b"same size 2");
+		let c = write("c", b"same size 1");
+		let d = write("d", b"other size");
+
+		assert!(file_cmp(&a, &c).unwrap());
+		assert!(!file_cmp(&a, &b).unwrap());
+		assert!(!file_cmp(&a, &d).unwrap());
+	}
+}
@@ -0,0 +1,318 @@
+//! Exporting snapshots as archives in the POSIX.1-2001 pax interchange format
+//!
+//! Entries are built from the metadata records rather than the files in the repository, which
+//! neither carry the recorded metadata, nor are necessarily of the recorded type, as with
+//! deduplicated files. Extended headers carry what the ustar headers can not: nanosecond
+//! timestamps, extended attributes as `SCHILY.xattr.<key>` records, as well as long paths and
+//! out-of-range numbers.
+
+use std::{
+	ffi::OsStr,
+	fs::{self, File},
+	io::{self, BufReader, Write},
+	os::unix::prelude::OsStrExt,
+	path::Path,
+};
+
+use log::warn;
+use tar::{EntryType, Header};
+
+use crate::{
+	file::{self, FileType},
+	repo::{
+		meta_file::{MetaRecord, Timestamp},
+		snapshot::Snapshot,
+	},
+};
+
+const BLOCK_SIZE: usize = 512;
+
+/// Largest value of the 8-byte octal ustar fields, such as `uid`
+const MAX_OCTAL_8: u64 = 0o7777777;
+/// Largest value of the 12-byte octal ustar fields, such as `size`
+const MAX_OCTAL_12: u64 = 0o77777777777;
+
+#[derive(Debug, Default)]
+pub struct ExportStats {
+	pub entries: u64,
+	pub bytes: u64,
+	/// Sockets, which archives can not represent
+	pub skipped: u64,
+}
+
+/// Writes a pax archive of the snapshot to `out`, with paths relative to its data directory
+pub fn write_pax(snap: &Snapshot, out: impl Write) -> io::Result<ExportStats> {
+	let mut exporter = Exporter {
+		out,
+		stats: ExportStats::default(),
+	};
+	exporter.children(snap, Path::new(""))?;
+
+	// End-of-archive marker
+	exporter.out.write_all(&[0; 2 * BLOCK_SIZE])?;
+	exporter.out.flush()?;
+	Ok(exporter.stats)
+}
+
+struct Exporter<W: Write> {
+	out: W,
+	stats: ExportStats,
+}
+
+impl<W: Write> Exporter<W> {
+	fn children(&mut self, snap: &Snapshot, rel_dir: &Path) -> io::Result<()> {
+		let mut records = snap.dir_records(rel_dir)?;
+		records.sort_by(|a, b| a.name.cmp(&b.name));
+		for rec in records {
+			self.entry(snap, &rel_dir.join(OsStr::from_bytes(&rec.name)), rec)?;
+		}
+		Ok(())
+	}
+
+	/// Writes the entry of the record, along with those of its children for directories
+	fn entry(&mut self, snap: &Snapshot, rel_path: &Path, rec: MetaRecord) -> io::Result<()> {
+		// The snapshot holding the full record, along with its data
+		let holder = match rec.same_since {
+			Some(since) => snap.since_snapshot(since),
+			None => snap.clone(),
+		};
+		let rec = snap.resolve_record(rel_path, rec)?;
+		let data_path = holder.data_dir().join(rel_path);
+
+		let mut pax = Vec::new();
+		let mut header = header(rel_path, &rec, &mut pax)?;
+		match rec.file_type {
+			Some(FileType::Reg) => {
+				let content_path = if rec.is_deduplicated {
+					let target = fs::read_link(&data_path)?;
+					data_path
+						.parent()
+						.expect("data path should have a parent")
+						.join(target)
+				} else {
+					data_path
+				};
+				self.file(header, pax, rel_path, &rec, &content_path)?;
+			}
+			Some(FileType::Dir) => {
+				header.set_entry_type(EntryType::Directory);
+				self.write_header(header, &pax)?;
+				self.children(&holder, rel_path)?;
+			}
+			Some(FileType::Lnk) => {
+				let target = fs::read_link(&data_path)?;
+				header.set_entry_type(EntryType::Symlink);
+				if header.set_link_name(&target).is_err() {
+					let target = target.as_os_str().as_bytes();
+					set_truncated(&mut header.as_old_mut().linkname, target);
+					pax.push(("linkpath".to_owned(), target.to_vec()));
+					if std::str::from_utf8(target).is_err() {
+						pax.push(("hdrcharset".to_owned(), b"BINARY".to_vec()));
+					}
+				}
+				self.write_header(header, &pax)?;
+			}
+			Some(file_type @ (FileType::Chr | FileType::Blk)) => {
+				header.set_entry_type(if file_type == FileType::Chr {
+					EntryType::Char
+				} else {
+					EntryType::Block
+				});
+				header.set_device_major(rec.rdev_major.unwrap_or(0))?;
+				header.set_device_minor(rec.rdev_minor.unwrap_or(0))?;
+				self.write_header(header, &pax)?;
+			}
+			Some(FileType::Fifo) => {
+				header.set_entry_type(EntryType::Fifo);
+				self.write_header(header, &pax)?;
+			}
+			Some(FileType::Sock) | None => {
+				warn!("skipping {rel_path:?}, as sockets can not be archived");
+				self.stats.skipped += 1;
+			}
+		}
+		Ok(())
+	}
+
+	fn file(
+		&mut self,
+		mut header: Header,
+		mut pax: Vec<(String, Vec<u8>)>,
+		rel_path: &Path,
+		rec: &MetaRecord,
+		content_path: &Path,
+	) -> io::Result<()> {
+		let mut content = BufReader::new(File::open(content_path)?);
+		let size = content.get_ref().metadata()?.len();
+		header.set_entry_type(EntryType::Regular);
+		header.set_size(size.min(MAX_OCTAL_12));
+		if size > MAX_OCTAL_12 {
+			pax.push(("size".to_owned(), size.to_string().into_bytes()));
+		}
+		self.write_header(header, &pax)?;
+
+		let (copied, hash) = file::copy_hashed(&mut content, &mut self.out)?;
+		if copied != size || rec.b3sum.is_some_and(|b3sum| b3sum != hash) {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("content of {rel_path:?} in {content_path:?} does not match its record"),
+			));
+		}
+		self.pad(size)?;
+		self.stats.bytes += size;
+		Ok(())
+	}
+
+	/// Writes `header`, preceded by an extended header if there are `pax` records
+	fn write_header(&mut self, mut header: Header, pax: &[(String, Vec<u8>)]) -> io::Result<()> {
+		if !pax.is_empty() {
+			let mut data = Vec::new();
+			for (key, value) in pax {
+				data.extend(pax_record(key, value));
+			}
+
+			let mut pax_header = Header::new_ustar();
+			let name = header.path_bytes();
+			let base = name.rsplit(|c| *c == b'/').next().unwrap_or_default();
+			let mut pax_name = b"PaxHeaders/".to_vec();
+			pax_name.extend_from_slice(base);
+			set_truncated(&mut pax_header.as_old_mut().name, &pax_name);
+			pax_header.set_entry_type(EntryType::XHeader);
+			pax_header.set_mode(0o644);
+			pax_header.set_size(data.len() as u64);
+			pax_header.set_mtime(header.mtime().unwrap_or(0));
+			pax_header.set_cksum();
+
+			self.out.write_all(pax_header.as_bytes())?;
+			self.out.write_all(&data)?;
+			self.pad(data.len() as u64)?;
+		}
+
+		header.set_cksum();
+		self.out.write_all(header.as_bytes())?;
+		self.stats.entries += 1;
+		Ok(())
+	}
+
+	/// Pads data of `size` bytes to a whole number of blocks
+	fn pad(&mut self, size: u64) -> io::Result<()> {
+		let remainder = size as usize % BLOCK_SIZE;
+		if remainder != 0 {
+			self.out.write_all(&[0; BLOCK_SIZE][remainder..])?;
+		}
+		Ok(())
+	}
+}
+
+/// Returns the ustar header of the record, adding the pax records needed for the metadata it can
+/// not hold to `pax`
+fn header(
+	rel_path: &Path,
+	rec: &MetaRecord,
+	pax: &mut Vec<(String, Vec<u8>)>,
+) -> io::Result<Header> {
+	let mut header = Header::new_ustar();
+
+	if header.set_path(rel_path).is_err() {
+		let path = rel_path.as_os_str().as_bytes();
+		set_truncated(&mut header.as_ustar_mut().expect("ustar header").name, path);
+		pax.push(("path".to_owned(), path.to_vec()));
+		if std::str::from_utf8(path).is_err() {
+			pax.push(("hdrcharset".to_owned(), b"BINARY".to_vec()));
+		}
+	}
+
+	header.set_size(0);
+	header.set_mode(rec.mode.unwrap_or(0o644));
+	for (key, id, set) in [
+		("uid", rec.uid, Header::set_uid as fn(&mut Header, u64)),
+		("gid", rec.gid, Header::set_gid),
+	] {
+		let id = u64::from(id.unwrap_or(0));
+		set(&mut header, id.min(MAX_OCTAL_8));
+		if id > MAX_OCTAL_8 {
+			pax.push((key.to_owned(), id.to_string().into_bytes()));
+		}
+	}
+
+	let mtime = rec.mtime.unwrap_or_default();
+	header.set_mtime(u64::try_from(mtime.sec).unwrap_or(0).min(MAX_OCTAL_12));
+	for (key, time) in [
+		("mtime", rec.mtime),
+		("atime", rec.atime),
+		("ctime", rec.ctime),
+	] {
+		if let Some(time) = time {
+			pax.push((key.to_owned(), pax_time(time).into_bytes()));
+		}
+	}
+
+	for (key, value) in &rec.xattrs {
+		match std::str::from_utf8(key) {
+			Ok(key) => pax.push((format!("SCHILY.xattr.{key}"), value.clone())),
+			Err(_) => warn!(
+				"skipping extended attribute {:?} of {rel_path:?}, as its name is not UTF-8",
+				OsStr::from_bytes(key)
+			),
+		}
+	}
+
+	Ok(header)
+}
+
+/// Copies as much of `value` as fits into the header `field`
+fn set_truncated(field: &mut [u8], value: &[u8]) {
+	let len = value.len().min(field.len());
+	field[..len].copy_from_slice(&value[..len]);
+}
+
+/// Formats a timestamp as decimal seconds, as used by pax records
+fn pax_time(time: Timestamp) -> String {
+	if time.sec < 0 && time.nsec > 0 {
+		// `sec` is rounded down, while the decimal representation is rounded towards zero
+		format!("-{}.{:09}", -(time.sec + 1), 1_000_000_000 - time.nsec)
+	} else {
+		format!("{}.{:09}", time.sec, time.nsec)
+	}
+}
+
+/// Returns a pax extended header record, `<length> <key>=<value>\n`, with the length including
+/// itself
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+	let rest = key.len() + value.len() + 3; // space, `=` and newline
+	let mut len = rest + 1;
+	while len != rest + len.to_string().len() {
+		len = rest + len.to_string().len();
+	}
+
+	let mut record = format!("{len} {key}=").into_bytes();
+	record.extend_from_slice(value);
+	record.push(b'\n');
+	record
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn pax_record_length() {
+		assert_eq!(pax_record("a", b"b"), b"6 a=b\n");
+		assert_eq!(
+			pax_record("path", &[b'x'; 91]),
+			[&b"101 path="[..], &[b'x'; 91], b"\n"].concat()
+		);
+	}
+
+	#[test]
+	fn pax_times() {
+		assert_eq!(pax_time(Timestamp { sec: 5, nsec: 1 }), "5.000000001");
+		assert_eq!(
+			pax_time(Timestamp {
+				sec: -2,
+				nsec: 500_000_000
+			}),
+			"-1.500000000"
+		);
+	}
+}
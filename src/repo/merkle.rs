@@ -0,0 +1,203 @@
+//! A Merkle tree over the metadata of a snapshot
+//!
+//! The digest of a directory is the BLAKE3 hash of its meta file, which holds the records of its
+//! children. Records of regular files contain the hash of their content, and records of
+//! directories contain their digest under `tree-b3sum`, so the digest of the data directory,
+//! recorded as the snapshot's root hash, covers all data and metadata in the snapshot.
+//!
+//! `is-deduplicated` lines are left out of digests, as rehoming backing files when pruning removes
+//! them without changing the represented data.
+
+use std::{
+	collections::HashMap,
+	ffi::OsStr,
+	fs, io,
+	os::unix::prelude::OsStrExt,
+	path::{Path, PathBuf},
+};
+
+use blake3::Hash;
+
+use crate::repo::{
+	meta_file::{line, MetaFile, MetaRecord, Record},
+	snapshot::Snapshot,
+};
+
+pub const ROOT_FNAME: &str = "root.b3sum";
+
+/// Returns the digest of a meta file with `records`
+pub fn digest_records(records: &[Record]) -> Hash {
+	let mut hasher = blake3::Hasher::new();
+	for record in records {
+		for line in record.0.iter().filter(|l| l.0 != line::IS_DEDUPLICATED) {
+			hasher.update(&line.0);
+			hasher.update(b"\n");
+		}
+		hasher.update(b"--\n");
+	}
+	hasher.finalize()
+}
+
+/// Returns the records of the meta file, or none if it does not exist, as meta files are only
+/// created for directories with entries
+fn records_or_empty(meta_file: &MetaFile) -> io::Result<Vec<Record>> {
+	match meta_file.records() {
+		Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+		res => res,
+	}
+}
+
+fn root_path(snap: &Snapshot) -> PathBuf {
+	snap.0.join(ROOT_FNAME)
+}
+
+/// Returns the recorded root hash of the snapshot, if any
+pub fn root_hash(snap: &Snapshot) -> io::Result<Option<Hash>> {
+	let data = match fs::read_to_string(root_path(snap)) {
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+		res => res?,
+	};
+	Hash::from_hex(data.trim_end()).map(Some).map_err(|e| {
+		io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("malformed root hash in {:?}: {e}", snap.0),
+		)
+	})
+}
+
+fn write_root_hash(snap: &Snapshot, root: Hash) -> io::Result<()> {
+	fs::write(root_path(snap), format!("{}\n", root.to_hex()))
+}
+
+/// Records the digests of all directories in the snapshot, bottom-up, along with its root hash
+pub fn seal(snap: &Snapshot) -> io::Result<Hash> {
+	let meta_name = snap.meta_name()?;
+	let data_dir = snap.data_dir();
+
+	// Digests of the directories whose parents are yet to be processed
+	let mut digests: HashMap<PathBuf, Hash> = HashMap::new();
+	for entry in walkdir::WalkDir::new(&data_dir)
+		.contents_first(true)
+		.sort_by_file_name()
+	{
+		let entry = entry?;
+		if !entry.file_type().is_dir() {
+			continue;
+		}
+		let dir = entry.path();
+		let meta_file = MetaFile(dir.join(&meta_name));
+
+		let mut records = records_or_empty(&meta_file)?;
+		let mut changed = false;
+		for record in records.iter_mut() {
+			let Some(name) = record.name() else {
+				continue;
+			};
+			if let Some(digest) = digests.remove(&dir.join(OsStr::from_bytes(&name))) {
+				record.set(line::PFX_TREE_HASH, digest.to_hex().as_bytes());
+				changed = true;
+			}
+		}
+		if changed {
+			meta_file.write_records(&records)?;
+		}
+		digests.insert(dir.to_path_buf(), digest_records(&records));
+	}
+
+	let root = digests
+		.remove(&data_dir)
+		.expect("the data dir is walked last");
+	write_root_hash(snap, root)?;
+	Ok(root)
+}
+
+/// Updates the digests from `dir`, whose meta file changed, up to the root hash
+pub fn reseal_from(snap: &Snapshot, dir: &Path) -> io::Result<Hash> {
+	let meta_name = snap.meta_name()?;
+	let data_dir = snap.data_dir();
+
+	let mut dir = dir.to_path_buf();
+	loop {
+		let digest = digest_records(&records_or_empty(&MetaFile(dir.join(&meta_name)))?);
+		if dir == data_dir {
+			write_root_hash(snap, digest)?;
+			return Ok(digest);
+		}
+
+		let name = dir
+			.file_name()
+			.expect("dir within data dir")
+			.as_bytes()
+			.to_vec();
+		dir.pop();
+		let parent_meta = MetaFile(dir.join(&meta_name));
+		let mut records = parent_meta.records()?;
+		for record in records.iter_mut() {
+			if record.name().as_ref() == Some(&name) {
+				record.set(line::PFX_TREE_HASH, digest.to_hex().as_bytes());
+			}
+		}
+		parent_meta.write_records(&records)?;
+	}
+}
+
+/// An inconsistency found while verifying a snapshot
+#[derive(Debug)]
+pub struct Problem {
+	/// Path relative to the data directory
+	pub path: PathBuf,
+	pub message: String,
+}
+
+/// Verifies the metadata of the snapshot top-down, starting from the root hash. Each directory's
+/// meta file is checked against the digest recorded in its parent, so a modified meta file is
+/// only reported for its own directory.
+pub fn verify(snap: &Snapshot) -> io::Result<Vec<Problem>> {
+	let mut problems = Vec::new();
+	let Some(root) = root_hash(snap)? else {
+		problems.push(Problem {
+			path: PathBuf::new(),
+			message: "no root hash recorded".to_owned(),
+		});
+		return Ok(problems);
+	};
+
+	let meta_name = snap.meta_name()?;
+	let data_dir = snap.data_dir();
+	let mut pending = vec![(PathBuf::new(), root)];
+	while let Some((rel_dir, expected)) = pending.pop() {
+		let dir = data_dir.join(&rel_dir);
+		if !dir.is_dir() {
+			problems.push(Problem {
+				path: rel_dir,
+				message: "directory missing".to_owned(),
+			});
+			continue;
+		}
+
+		let meta_file = MetaFile(dir.join(&meta_name));
+		let records = records_or_empty(&meta_file)?;
+		let actual = digest_records(&records);
+		if actual != expected {
+			problems.push(Problem {
+				path: rel_dir,
+				message: format!(
+					"metadata digest mismatch: recorded {}, actual {}",
+					expected.to_hex(),
+					actual.to_hex()
+				),
+			});
+			continue;
+		}
+
+		for record in &records {
+			let rec = MetaRecord::parse(record, &meta_file.0)?;
+			if let Some(tree_b3sum) = rec.tree_b3sum {
+				pending.push((rel_dir.join(OsStr::from_bytes(&rec.name)), tree_b3sum));
+			}
+		}
+	}
+
+	problems.sort_by(|a, b| a.path.cmp(&b.path));
+	Ok(problems)
+}
@@ -1,15 +1,22 @@
 use std::{
-	error::Error,
-	ffi::OsString,
-	fs::File,
-	io::{self, BufRead, BufReader},
-	os::unix::prelude::OsStringExt,
+	ffi::{OsStr, OsString},
+	fs::{self, File},
+	io::{self, BufRead, BufReader, BufWriter, Write},
+	os::unix::prelude::{OsStrExt, OsStringExt},
 	path::{Path, PathBuf},
 };
 
-use blake3::{Hash, HexError};
+use blake3::Hash;
+use log::debug;
+use schemars::JsonSchema;
+use serde::{Serialize, Serializer};
 
-#[derive(Debug)]
+use crate::{
+	file::FileType,
+	util::{bytes, hex},
+};
+
+#[derive(Clone, Debug)]
 pub struct MetaFile(pub PathBuf);
 #[derive(Debug)]
 pub struct Record(pub Vec<Line>);
@@ -17,16 +24,17 @@ pub struct Record(pub Vec<Line>);
 pub mod line {
 	// Update appropriate doc/repositories/<version>/index.md if you change these
 	pub const IS_DEDUPLICATED: &[u8] = b"is-deduplicated";
-	// Commented out until we start using it. Added so we don't forget to update the docs if we
-	// 	decide on some other representation.
-	// pub const PFX_END_MARKER: &[u8] = b"same-since";
+	pub const IS_APPROXIMATE: &[u8] = b"is-approximate";
+	pub const PFX_END_MARKER: &[u8] = b"same-since";
 	pub const PFX_NAME: &[u8] = b"name";
 	pub const PFX_HASH: &[u8] = b"b3sum";
+	pub const PFX_TREE_HASH: &[u8] = b"tree-b3sum";
+	pub const PFX_XATTR: &[u8] = b"x";
 }
 #[derive(Debug)]
 pub struct Line(pub Vec<u8>);
 impl Line {
-	fn parse<P: AsRef<Path>>(&self, meta_file_path: P) -> Result<ParsedLine, Box<dyn Error>> {
+	fn parse<P: AsRef<Path>>(&self, meta_file_path: P) -> io::Result<ParsedLine> {
 		// obeying clippy's lint here would result in less obvious code structure
 		#[allow(clippy::collapsible_else_if)]
 		if self.0.starts_with(line::PFX_HASH) {
@@ -35,7 +43,18 @@ impl Line {
 			))
 		} else {
 			if self.0.starts_with(line::PFX_NAME) {
-				let rel_path = OsString::from_vec(self.0[line::PFX_NAME.len() + 1..].to_vec());
+				let name = hex::tagged_rawhex::decode(&self.0[line::PFX_NAME.len() + 1..])
+					.ok_or_else(|| {
+						io::Error::new(
+							io::ErrorKind::InvalidData,
+							format!(
+								"malformed name in {:?}: {:?}",
+								meta_file_path.as_ref(),
+								OsStr::from_bytes(&self.0)
+							),
+						)
+					})?;
+				let rel_path = OsString::from_vec(name);
 				let meta_parent = meta_file_path
 					.as_ref()
 					.parent()
@@ -45,7 +64,7 @@ impl Line {
 				if self.0 == line::IS_DEDUPLICATED {
 					Ok(ParsedLine::IsDeduplicated)
 				} else {
-					unimplemented!("todo other variants");
+					Ok(ParsedLine::Other)
 				}
 			}
 		}
@@ -57,9 +76,414 @@ enum ParsedLine {
 	Path(PathBuf),
 	Hash(Vec<u8>),
 	IsDeduplicated,
+	/// Lines not needed for building the deduplication index
+	Other,
+}
+
+/// A `statx()` timestamp, as recorded in `<sec>.<nsec>` form
+#[derive(Clone, Copy, Debug, Default, Eq, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct Timestamp {
+	/// Seconds since the Unix epoch, negative for earlier times
+	pub sec: i64,
+	/// Nanoseconds after `sec`, below 1000000000
+	pub nsec: u32,
+}
+
+impl Timestamp {
+	fn parse(value: &str) -> Option<Timestamp> {
+		let (sec, nsec) = value.split_once('.')?;
+		Some(Timestamp {
+			sec: sec.parse().ok()?,
+			nsec: nsec.parse().ok()?,
+		})
+	}
+
+	pub fn to_date_time(self) -> Option<chrono::DateTime<chrono::Utc>> {
+		chrono::DateTime::from_timestamp(self.sec, self.nsec)
+	}
+}
+
+impl std::fmt::Display for Timestamp {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}.{:09}", self.sec, self.nsec)
+	}
+}
+
+/// A fully decoded metadata record. Everything but the name is optional, as `same-since` records
+/// only contain the name, and not all sources provide all `statx()` fields.
+///
+/// Serializes to the structured form of `baktu meta`, whose schema is generated from this type.
+/// Byte strings are serialized via `bytes::serialize`, hashes as lowercase hex, and absent
+/// fields as null.
+#[derive(Clone, Debug, Default, JsonSchema, PartialEq, Serialize)]
+pub struct MetaRecord {
+	/// File name
+	#[serde(serialize_with = "bytes::serialize")]
+	#[schemars(with = "bytes::Bytes")]
+	pub name: Vec<u8>,
+	/// Whether the file is stored as a relative symlink to a backing file with the same content
+	pub is_deduplicated: bool,
+	/// Whether the metadata was reconstructed after the fact, rather than captured from the
+	/// source, as with imported snapshots
+	pub is_approximate: bool,
+	/// Number of the snapshot containing the full record, for history interval records
+	pub same_since: Option<u64>,
+	/// BLAKE3 hash of the content, for regular files
+	#[serde(serialize_with = "serialize_hash")]
+	#[schemars(with = "Option<String>")]
+	pub b3sum: Option<Hash>,
+	/// Digest of the directory's meta file, for directories
+	#[serde(serialize_with = "serialize_hash")]
+	#[schemars(with = "Option<String>")]
+	pub tree_b3sum: Option<Hash>,
+	pub blksize: Option<u32>,
+	/// Names of the set `statx()` attributes, such as `nodump`
+	pub attributes: Option<Vec<String>>,
+	pub nlink: Option<u32>,
+	pub uid: Option<u32>,
+	pub gid: Option<u32>,
+	/// Permission bits, i.e. `stx_mode & ~S_IFMT`
+	pub mode: Option<u32>,
+	#[serde(rename = "type")]
+	pub file_type: Option<FileType>,
+	pub ino: Option<u64>,
+	pub size: Option<u64>,
+	pub blocks: Option<u64>,
+	pub atime: Option<Timestamp>,
+	pub btime: Option<Timestamp>,
+	pub ctime: Option<Timestamp>,
+	pub mtime: Option<Timestamp>,
+	pub rdev_major: Option<u32>,
+	pub rdev_minor: Option<u32>,
+	pub dev_major: Option<u32>,
+	pub dev_minor: Option<u32>,
+	pub mnt_id: Option<u64>,
+	pub dio_mem_align: Option<u32>,
+	pub dio_offset_align: Option<u32>,
+	/// Inode flags in `lsattr(1)` notation
+	pub lsattr: Option<String>,
+	/// Extended attributes, in their recorded order
+	#[serde(serialize_with = "serialize_xattrs")]
+	#[schemars(with = "Vec<Xattr>")]
+	pub xattrs: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+/// An extended attribute
+#[derive(JsonSchema, Serialize)]
+struct Xattr<'a> {
+	#[serde(serialize_with = "bytes::serialize")]
+	#[schemars(with = "bytes::Bytes")]
+	name: &'a [u8],
+	#[serde(serialize_with = "bytes::serialize")]
+	#[schemars(with = "bytes::Bytes")]
+	value: &'a [u8],
+}
+
+fn serialize_hash<S: Serializer>(hash: &Option<Hash>, serializer: S) -> Result<S::Ok, S::Error> {
+	match hash {
+		Some(hash) => serializer.serialize_str(hash.to_hex().as_str()),
+		None => serializer.serialize_none(),
+	}
+}
+
+fn serialize_xattrs<S: Serializer>(
+	xattrs: &[(Vec<u8>, Vec<u8>)],
+	serializer: S,
+) -> Result<S::Ok, S::Error> {
+	serializer.collect_seq(xattrs.iter().map(|(name, value)| Xattr { name, value }))
+}
+
+impl MetaRecord {
+	/// Decodes `record`, using `meta_file_path` only for error messages
+	pub fn parse<P: AsRef<Path>>(record: &Record, meta_file_path: P) -> io::Result<MetaRecord> {
+		let invalid = |line: &Line| {
+			io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!(
+					"malformed metadata line in {:?}: {:?}",
+					meta_file_path.as_ref(),
+					OsStr::from_bytes(&line.0)
+				),
+			)
+		};
+
+		fn num<T: std::str::FromStr>(value: &[u8]) -> Option<T> {
+			std::str::from_utf8(value).ok()?.parse().ok()
+		}
+
+		let mut rec = MetaRecord::default();
+		let mut has_name = false;
+
+		for line in &record.0 {
+			if line.0 == line::IS_DEDUPLICATED {
+				rec.is_deduplicated = true;
+				continue;
+			}
+			if line.0 == line::IS_APPROXIMATE {
+				rec.is_approximate = true;
+				continue;
+			}
+
+			let (key, value) = match line.0.iter().position(|c| *c == b' ') {
+				Some(space) => (&line.0[..space], &line.0[space + 1..]),
+				None => (&line.0[..], &[][..]),
+			};
+			let str_value = || std::str::from_utf8(value).map_err(|_| invalid(line));
+
+			let parsed: Option<()> = match key {
+				line::PFX_NAME => hex::tagged_rawhex::decode(value).map(|name| {
+					rec.name = name;
+					has_name = true;
+				}),
+				line::PFX_END_MARKER => num(value).map(|n| rec.same_since = Some(n)),
+				line::PFX_HASH => Hash::from_hex(value).ok().map(|h| rec.b3sum = Some(h)),
+				line::PFX_TREE_HASH => Hash::from_hex(value).ok().map(|h| rec.tree_b3sum = Some(h)),
+				b"blksize" => num(value).map(|n| rec.blksize = Some(n)),
+				b"attributes" => {
+					rec.attributes =
+						Some(str_value()?.split_whitespace().map(String::from).collect());
+					Some(())
+				}
+				b"nlink" => num(value).map(|n| rec.nlink = Some(n)),
+				b"uid" => num(value).map(|n| rec.uid = Some(n)),
+				b"gid" => num(value).map(|n| rec.gid = Some(n)),
+				b"mode" => u32::from_str_radix(str_value()?, 8)
+					.ok()
+					.map(|m| rec.mode = Some(m)),
+				// Written by earlier versions for file types statx reports but baktu has no name for
+				b"type" if value.starts_with(b"unknown: ") => Some(()),
+				b"type" => FileType::from_name(str_value()?).map(|t| rec.file_type = Some(t)),
+				b"ino" => num(value).map(|n| rec.ino = Some(n)),
+				b"size" => num(value).map(|n| rec.size = Some(n)),
+				b"blocks" => num(value).map(|n| rec.blocks = Some(n)),
+				b"atime" => Timestamp::parse(str_value()?).map(|t| rec.atime = Some(t)),
+				b"btime" => Timestamp::parse(str_value()?).map(|t| rec.btime = Some(t)),
+				b"ctime" => Timestamp::parse(str_value()?).map(|t| rec.ctime = Some(t)),
+				b"mtime" => Timestamp::parse(str_value()?).map(|t| rec.mtime = Some(t)),
+				b"rdev_major" => num(value).map(|n| rec.rdev_major = Some(n)),
+				b"rdev_minor" => num(value).map(|n| rec.rdev_minor = Some(n)),
+				b"dev_major" => num(value).map(|n| rec.dev_major = Some(n)),
+				b"dev_minor" => num(value).map(|n| rec.dev_minor = Some(n)),
+				b"mnt_id" => num(value).map(|n| rec.mnt_id = Some(n)),
+				b"dio_mem_align" => num(value).map(|n| rec.dio_mem_align = Some(n)),
+				b"dio_offset_align" => num(value).map(|n| rec.dio_offset_align = Some(n)),
+				b"lsattr" => {
+					rec.lsattr = Some(str_value()?.to_owned());
+					Some(())
+				}
+				line::PFX_XATTR => (|| {
+					let (k, rest) = hex::tagged_rawhex::split_first(value.strip_prefix(b"k.")?)?;
+					let v = hex::tagged_rawhex::decode(rest.strip_prefix(b" v.")?)?;
+					rec.xattrs.push((k, v));
+					Some(())
+				})(),
+				// Keys from newer versions of the format, which may add metadata
+				_ => {
+					debug!(
+						"skipping unknown metadata key in {:?}: {:?}",
+						meta_file_path.as_ref(),
+						OsStr::from_bytes(key)
+					);
+					Some(())
+				}
+			};
+
+			parsed.ok_or_else(|| invalid(line))?;
+		}
+
+		if !has_name {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("record without name in {:?}", meta_file_path.as_ref()),
+			));
+		}
+
+		Ok(rec)
+	}
+
+	/// Writes the record in the format read by [`MetaRecord::parse`], including the terminating
+	/// `--` line. Fields are written in the order `baktu snap` uses, omitting absent ones.
+	pub fn write(&self, sink: &mut impl Write) -> io::Result<()> {
+		// First, so we don't waste time while building the hash->path map during dedup
+		if self.is_deduplicated {
+			sink.write_all(line::IS_DEDUPLICATED)?;
+			writeln!(sink)?;
+		}
+		// TODO: (M) switch to space-separated key-raw/hex pairs for all the other keys
+		sink.write_all(line::PFX_NAME)?;
+		sink.write_all(b" ")?;
+		sink.write_all(&hex::tagged_rawhex::encode(false, &self.name))?;
+		writeln!(sink)?;
+		if self.is_approximate {
+			sink.write_all(line::IS_APPROXIMATE)?;
+			writeln!(sink)?;
+		}
+
+		fn field(
+			sink: &mut impl Write,
+			key: &str,
+			value: Option<impl std::fmt::Display>,
+		) -> io::Result<()> {
+			match value {
+				Some(value) => writeln!(sink, "{key} {value}"),
+				None => Ok(()),
+			}
+		}
+		field(sink, "same-since", self.same_since)?;
+		field(sink, "tree-b3sum", self.tree_b3sum.map(|h| h.to_hex()))?;
+		field(sink, "b3sum", self.b3sum.map(|h| h.to_hex()))?;
+		field(sink, "blksize", self.blksize)?;
+		if let Some(attributes) = &self.attributes {
+			write!(sink, "attributes")?;
+			for attribute in attributes {
+				write!(sink, " {attribute}")?;
+			}
+			writeln!(sink)?;
+		}
+		field(sink, "nlink", self.nlink)?;
+		field(sink, "uid", self.uid)?;
+		field(sink, "gid", self.gid)?;
+		field(sink, "mode", self.mode.map(|m| format!("{m:o}")))?;
+		field(sink, "type", self.file_type.map(FileType::name))?;
+		field(sink, "ino", self.ino)?;
+		field(sink, "size", self.size)?;
+		field(sink, "blocks", self.blocks)?;
+		field(sink, "atime", self.atime)?;
+		field(sink, "btime", self.btime)?;
+		field(sink, "ctime", self.ctime)?;
+		field(sink, "mtime", self.mtime)?;
+		field(sink, "rdev_major", self.rdev_major)?;
+		field(sink, "rdev_minor", self.rdev_minor)?;
+		field(sink, "dev_major", self.dev_major)?;
+		field(sink, "dev_minor", self.dev_minor)?;
+		field(sink, "mnt_id", self.mnt_id)?;
+		field(sink, "dio_mem_align", self.dio_mem_align)?;
+		field(sink, "dio_offset_align", self.dio_offset_align)?;
+		field(sink, "lsattr", self.lsattr.as_ref())?;
+		for (key, value) in &self.xattrs {
+			sink.write_all(b"x k.")?;
+			sink.write_all(&hex::tagged_rawhex::encode(true, key))?;
+			sink.write_all(b" v.")?;
+			sink.write_all(&hex::tagged_rawhex::encode(false, value))?;
+			writeln!(sink)?;
+		}
+		writeln!(sink, "--")
+	}
+
+	/// Returns the record of a file named `name` with the `statx()` data `stx`, failing with
+	/// [`io::ErrorKind::Unsupported`] if `statx()` did not return a field recorded for all files.
+	/// Hashes, flags, `lsattr` and extended attributes are left to the caller.
+	pub fn from_statx(name: &[u8], stx: &libc::statx) -> io::Result<MetaRecord> {
+		let require = |flag: u32, field: &str| crate::file::statx::require(stx, flag, field);
+		let timestamp = |ts: libc::statx_timestamp| Timestamp {
+			sec: ts.tv_sec,
+			nsec: ts.tv_nsec,
+		};
+
+		let mut rec = MetaRecord {
+			name: name.to_vec(),
+			blksize: Some(stx.stx_blksize),
+			..Default::default()
+		};
+
+		let attributes = [
+			(libc::STATX_ATTR_COMPRESSED, "compressed"),
+			(libc::STATX_ATTR_IMMUTABLE, "immutable"),
+			(libc::STATX_ATTR_APPEND, "append"),
+			(libc::STATX_ATTR_NODUMP, "nodump"),
+			(libc::STATX_ATTR_ENCRYPTED, "encrypted"),
+			(libc::STATX_ATTR_VERITY, "verity"),
+			(libc::STATX_ATTR_DAX, "dax"),
+		];
+		rec.attributes = Some(
+			attributes
+				.into_iter()
+				.filter(|(flag, _)| {
+					stx.stx_attributes_mask & stx.stx_attributes & *flag as u64 != 0
+				})
+				.map(|(_, name)| name.to_owned())
+				.collect(),
+		);
+
+		require(libc::STATX_NLINK, "nlink")?;
+		rec.nlink = Some(stx.stx_nlink);
+		require(libc::STATX_UID, "uid")?;
+		rec.uid = Some(stx.stx_uid);
+		require(libc::STATX_GID, "gid")?;
+		rec.gid = Some(stx.stx_gid);
+		// ~S_IFMT from https://man7.org/linux/man-pages/man2/statx.2.html
+		require(libc::STATX_MODE, "mode")?;
+		rec.mode = Some(stx.stx_mode as u32 & !libc::S_IFMT);
+		require(libc::STATX_TYPE, "type")?;
+		rec.file_type = FileType::from_mode(stx.stx_mode as u32);
+		require(libc::STATX_INO, "ino")?;
+		rec.ino = Some(stx.stx_ino);
+		require(libc::STATX_SIZE, "size")?;
+		rec.size = Some(stx.stx_size);
+		require(libc::STATX_BLOCKS, "blocks")?;
+		rec.blocks = Some(stx.stx_blocks);
+		require(libc::STATX_ATIME, "atime")?;
+		rec.atime = Some(timestamp(stx.stx_atime));
+		require(libc::STATX_BTIME, "btime")?;
+		rec.btime = Some(timestamp(stx.stx_btime));
+		require(libc::STATX_CTIME, "ctime")?;
+		rec.ctime = Some(timestamp(stx.stx_ctime));
+		require(libc::STATX_MTIME, "mtime")?;
+		rec.mtime = Some(timestamp(stx.stx_mtime));
+
+		if let libc::S_IFCHR | libc::S_IFBLK = stx.stx_mode as u32 & libc::S_IFMT {
+			rec.rdev_major = Some(stx.stx_rdev_major);
+			rec.rdev_minor = Some(stx.stx_rdev_minor);
+		}
+		rec.dev_major = Some(stx.stx_dev_major);
+		rec.dev_minor = Some(stx.stx_dev_minor);
+
+		require(libc::STATX_MNT_ID, "mnt_id")?;
+		rec.mnt_id = Some(stx.stx_mnt_id);
+
+		if stx.stx_mask & libc::STATX_DIOALIGN != 0 {
+			rec.dio_mem_align = Some(stx.stx_dio_mem_align);
+			rec.dio_offset_align = Some(stx.stx_dio_offset_align);
+		}
+
+		Ok(rec)
+	}
+
+	/// Returns the names of the user-visible metadata fields that differ between `self` and
+	/// `other`, ignoring content, as well as fields that change whenever a file is copied or
+	/// merely read, such as `ino`, `ctime` or `atime`
+	pub fn metadata_changes(&self, other: &MetaRecord) -> Vec<&'static str> {
+		let mut changes = Vec::new();
+		let mut check = |name, differs| {
+			if differs {
+				changes.push(name);
+			}
+		};
+		check("type", self.file_type != other.file_type);
+		check("mode", self.mode != other.mode);
+		check("uid", self.uid != other.uid);
+		check("gid", self.gid != other.gid);
+		check("size", self.size != other.size);
+		check("mtime", self.mtime != other.mtime);
+		check(
+			"rdev",
+			(self.rdev_major, self.rdev_minor) != (other.rdev_major, other.rdev_minor),
+		);
+		check("lsattr", self.lsattr != other.lsattr);
+		check("xattrs", self.xattrs != other.xattrs);
+		changes
+	}
 }
 
 impl MetaFile {
+	/// Returns the decoded records of the meta file
+	pub fn meta_records(&self) -> io::Result<Vec<MetaRecord>> {
+		self.records()?
+			.iter()
+			.map(|record| MetaRecord::parse(record, &self.0))
+			.collect()
+	}
+
 	pub fn records(&self) -> io::Result<Vec<Record>> {
 		let mut recs: Vec<Record> = Vec::new();
 		let mut rec: Vec<Line> = Vec::new();
@@ -75,9 +499,63 @@ impl MetaFile {
 		}
 		Ok(recs)
 	}
+
+	/// Replaces the content of the meta file with `records`, via a temporary file in the same
+	/// directory, so readers never see a partially written file. Removes the meta file instead if
+	/// `records` is empty, as meta files are only kept for directories with entries.
+	pub fn write_records(&self, records: &[Record]) -> io::Result<()> {
+		if records.is_empty() {
+			return fs::remove_file(&self.0);
+		}
+
+		let mut tmp_name = self.0.file_name().unwrap_or_default().to_owned();
+		tmp_name.push(".tmp");
+		let tmp_path = self.0.with_file_name(tmp_name);
+
+		let mut out = BufWriter::new(File::create(&tmp_path)?);
+		for record in records {
+			for line in &record.0 {
+				out.write_all(&line.0)?;
+				out.write_all(b"\n")?;
+			}
+			out.write_all(b"--\n")?;
+		}
+		out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+		fs::rename(tmp_path, &self.0)
+	}
 }
 
 impl Record {
+	/// Sets the value of the `key` line, replacing an existing one, or inserting it after the name
+	/// line otherwise
+	pub fn set(&mut self, key: &[u8], value: &[u8]) {
+		let mut new_line = key.to_vec();
+		new_line.push(b' ');
+		new_line.extend_from_slice(value);
+
+		let is_key = |line: &Line| line.0.starts_with(key) && line.0.get(key.len()) == Some(&b' ');
+		if let Some(line) = self.0.iter_mut().find(|line| is_key(line)) {
+			line.0 = new_line;
+			return;
+		}
+		let after_name = self
+			.0
+			.iter()
+			.position(|line| line.0.starts_with(line::PFX_NAME))
+			.map_or(0, |idx| idx + 1);
+		self.0.insert(after_name, Line(new_line));
+	}
+
+	/// Returns the decoded basename of the record's path, if it has a well-formed name line
+	pub fn name(&self) -> Option<Vec<u8>> {
+		self.0.iter().find_map(|line| {
+			line.0
+				.strip_prefix(line::PFX_NAME)
+				.and_then(|rest| rest.strip_prefix(b" "))
+				.and_then(hex::tagged_rawhex::decode)
+		})
+	}
+
 	/// Returns a record's hash and path, if the record is not a deduplicated file
 	// TODO: (M) consider if we want to hex-decode the hash ASAP or treat it as opaque data
 	//	+decode: half the size, reduce risk of mistyping if we're not strict with our types in the
@@ -86,16 +564,23 @@ impl Record {
 	pub fn get_hash_path_opt<P: AsRef<Path>>(
 		&self,
 		meta_file_path: P,
-	) -> Result<Option<(Hash, PathBuf)>, HexError> {
+	) -> io::Result<Option<(Hash, PathBuf)>> {
 		let mut hash: Option<Hash> = None;
 		let mut path: Option<PathBuf> = None;
 
 		for line in &self.0 {
-			match line.parse(&meta_file_path) {
-				Ok(ParsedLine::IsDeduplicated) => return Ok(None),
-				Ok(ParsedLine::Path(p)) => path = Some(p),
-				Ok(ParsedLine::Hash(hex_str)) => hash = Some(Hash::from_hex(hex_str)?),
-				_ => {}
+			match line.parse(&meta_file_path)? {
+				ParsedLine::IsDeduplicated => return Ok(None),
+				ParsedLine::Path(p) => path = Some(p),
+				ParsedLine::Hash(hex_str) => {
+					hash = Some(Hash::from_hex(hex_str).map_err(|e| {
+						io::Error::new(
+							io::ErrorKind::InvalidData,
+							format!("malformed hash in {:?}: {e}", meta_file_path.as_ref()),
+						)
+					})?)
+				}
+				ParsedLine::Other => {}
 			}
 		}
 
@@ -119,7 +604,14 @@ mod test {
 	}
 
 	fn line_name() -> Line {
-		Line([line::PFX_NAME, b" ", TEST_NAME].concat())
+		Line(
+			[
+				line::PFX_NAME,
+				b" ",
+				&hex::tagged_rawhex::encode(false, TEST_NAME),
+			]
+			.concat(),
+		)
 	}
 
 	// b3sum of file containing b"foobar\n"
@@ -142,6 +634,16 @@ mod test {
 			)
 		}
 
+		#[test]
+		fn other() {
+			assert_eq!(
+				ParsedLine::Other,
+				Line(b"blksize 4096".to_vec())
+					.parse(TEST_META_PATH)
+					.unwrap()
+			)
+		}
+
 		#[test]
 		fn path() {
 			assert_eq!(
@@ -151,6 +653,108 @@ mod test {
 		}
 	}
 
+	mod meta_record {
+		use super::*;
+
+		const SAMPLE_LINES: [&[u8]; 13] = [
+			b"is-deduplicated",
+			b"name r-3 a b",
+			b"is-approximate",
+			b"b3sum 534659321d2eea6b13aea4f4c94c3b4f624622295da31506722b47a8eb9d726c",
+			b"attributes nodump",
+			b"uid 1000",
+			b"mode 644",
+			b"type reg",
+			b"size 7",
+			b"mtime 1700000000.000000042",
+			b"lsattr ",
+			b"x k.r-12 user.enc-alg v.r-5 rot-N",
+			b"x k.h 7573657220612e6b6579 v.h 0a",
+		];
+
+		#[test]
+		fn parse() {
+			let record = Record(
+				SAMPLE_LINES
+					.into_iter()
+					.map(|line| Line(line.to_vec()))
+					.collect(),
+			);
+
+			let rec = MetaRecord::parse(&record, TEST_META_PATH).unwrap();
+			assert_eq!(rec.name, b"a b");
+			assert!(rec.is_deduplicated);
+			assert!(rec.is_approximate);
+			assert_eq!(rec.b3sum, Some(Hash::from_hex(TEST_B3SUM).unwrap()));
+			assert_eq!(rec.attributes, Some(vec!["nodump".to_owned()]));
+			assert_eq!(rec.uid, Some(1000));
+			assert_eq!(rec.mode, Some(0o644));
+			assert_eq!(rec.file_type, Some(FileType::Reg));
+			assert_eq!(rec.size, Some(7));
+			assert_eq!(
+				rec.mtime,
+				Some(Timestamp {
+					sec: 1700000000,
+					nsec: 42
+				})
+			);
+			assert_eq!(rec.lsattr.as_deref(), Some(""));
+			assert_eq!(
+				rec.xattrs,
+				vec![
+					(b"user.enc-alg".to_vec(), b"rot-N".to_vec()),
+					(b"user a.key".to_vec(), b"\n".to_vec()),
+				]
+			);
+		}
+
+		#[test]
+		fn write_roundtrip() {
+			let record = Record(
+				SAMPLE_LINES
+					.iter()
+					.map(|line| Line(line.to_vec()))
+					.collect(),
+			);
+			let rec = MetaRecord::parse(&record, TEST_META_PATH).unwrap();
+
+			let mut written = Vec::new();
+			rec.write(&mut written).unwrap();
+			let mut expected = SAMPLE_LINES.join(&b'\n');
+			expected.extend_from_slice(b"\n--\n");
+			assert_eq!(OsStr::from_bytes(&written), OsStr::from_bytes(&expected));
+		}
+
+		#[test]
+		fn parse_unknown_key() {
+			let record = Record(vec![
+				line_name(),
+				Line(b"colour blue".to_vec()),
+				line_hash(),
+			]);
+			let rec = MetaRecord::parse(&record, TEST_META_PATH).unwrap();
+			assert_eq!(rec.b3sum, Some(Hash::from_hex(TEST_B3SUM).unwrap()));
+		}
+
+		#[test]
+		fn parse_unknown_type() {
+			let record = Record(vec![
+				line_name(),
+				Line(b"mode 644".to_vec()),
+				Line(b"type unknown: 61440".to_vec()),
+			]);
+			let rec = MetaRecord::parse(&record, TEST_META_PATH).unwrap();
+			assert_eq!(rec.file_type, None);
+			assert_eq!(rec.mode, Some(0o644));
+		}
+
+		#[test]
+		fn parse_malformed_value() {
+			let record = Record(vec![line_name(), Line(b"uid blue".to_vec())]);
+			assert!(MetaRecord::parse(&record, TEST_META_PATH).is_err());
+		}
+	}
+
 	mod record {
 		use super::*;
 
@@ -175,5 +779,23 @@ mod test {
 
 			assert_eq!(record.get_hash_path_opt(TEST_META_PATH).unwrap(), None);
 		}
+
+		#[test]
+		fn set() {
+			let mut record = Record(vec![
+				Line(line::IS_DEDUPLICATED.to_vec()),
+				line_name(),
+				line_hash(),
+			]);
+			record.set(line::PFX_TREE_HASH, b"aa");
+			record.set(line::PFX_TREE_HASH, b"bb");
+			record.set(b"b3", b"cc");
+
+			assert_eq!(record.0[2].0, b"b3 cc");
+			assert_eq!(record.0[3].0, b"tree-b3sum bb");
+			assert_eq!(record.0[4].0, line_hash().0);
+			assert_eq!(record.0.len(), 5);
+			assert_eq!(record.name(), Some(TEST_NAME.to_vec()));
+		}
 	}
 }
@@ -0,0 +1,74 @@
+//! Step-by-step upgrades of repositories to the current format version
+
+use std::io;
+
+use log::debug;
+
+use crate::repo::{merkle, tag_file::Tag, Repo};
+
+/// An upgrade from format version `from` to `from + 1`
+pub struct Migration {
+	pub from: u32,
+	pub description: &'static str,
+	/// Upgrades the repository content, which may need to update the features in `tag` as well.
+	/// The version is updated and the tag file written afterwards.
+	run: fn(&Repo, &mut Tag) -> io::Result<()>,
+}
+
+/// All migrations, in order. Each must bring the repository to a state fully valid for the next
+/// version, as the tag file is updated after each one.
+static MIGRATIONS: &[Migration] = &[
+	Migration {
+		from: 1,
+		description: "introduce compat/incompat feature lines in the tag file",
+		// Version 1 repositories use no optional features, and the new tag file is written by
+		// `upgrade` itself
+		run: |_repo, _tag| Ok(()),
+	},
+	Migration {
+		from: 2,
+		description: "record metadata digests of directories and root hashes of snapshots",
+		run: seal_all_snapshots,
+	},
+];
+
+fn seal_all_snapshots(repo: &Repo, _tag: &mut Tag) -> io::Result<()> {
+	for site_res in repo.sites()? {
+		let site = site_res?;
+		for snap in site.snapshots_sorted()? {
+			debug!("sealing {:?}", snap.0);
+			merkle::seal(&snap)?;
+		}
+	}
+	Ok(())
+}
+
+/// Returns the migrations needed to bring a repository at `version` to the current version
+pub fn pending(version: u32) -> impl Iterator<Item = &'static Migration> {
+	MIGRATIONS.iter().filter(move |m| m.from >= version)
+}
+
+impl Migration {
+	/// Runs the migration and records the new version in the tag file
+	pub fn apply(&self, repo: &Repo, tag: &mut Tag) -> io::Result<()> {
+		assert_eq!(
+			tag.version, self.from,
+			"migrations must be applied in order"
+		);
+		(self.run)(repo, tag)?;
+		tag.version = self.from + 1;
+		tag.write_to(&repo.0)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::repo::tag_file::CURRENT_VERSION;
+
+	#[test]
+	fn registry_is_complete() {
+		let versions: Vec<u32> = MIGRATIONS.iter().map(|m| m.from).collect();
+		assert_eq!(versions, (1..CURRENT_VERSION).collect::<Vec<_>>());
+	}
+}
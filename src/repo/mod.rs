@@ -1,30 +1,84 @@
+pub mod dedup;
+pub mod export;
+pub mod merkle;
 pub mod meta_file;
+pub mod migrations;
+pub mod parity;
+pub mod rehome;
+pub mod retention;
+pub mod signature;
 pub mod site;
+pub mod snap;
 pub mod snapshot;
+pub mod summary;
 pub mod tag_file;
+pub mod transfer;
 
 use std::path::{Path, PathBuf};
 
-use log::debug;
+use log::{debug, warn};
 
 use self::site::Site;
+use crate::error::{Error, IoResultExt, Result};
 
 pub struct Repo(pub PathBuf);
 
 impl Repo {
+	/// Opens the repository at `dir`, warning if this version of baktu may not read it correctly
+	pub fn open(dir: &Path) -> Result<Repo> {
+		if !Repo::is_valid(dir) {
+			return Err(Error::Repo {
+				path: dir.to_path_buf(),
+				reason: "not a baktu repository".to_owned(),
+			});
+		}
+		let repo = Repo(dir.to_path_buf());
+		if let Err(e) = repo.tag()?.check_readable() {
+			warn!("{e}, results may be incomplete or wrong");
+		}
+		Ok(repo)
+	}
+
+	/// Fails if this version of baktu must not modify the repository, e.g. due to an older format
+	/// version
+	pub fn check_writable(&self) -> Result<()> {
+		self.tag()?.check_writable().map_err(|reason| Error::Repo {
+			path: self.0.clone(),
+			reason,
+		})
+	}
+
+	/// Returns the site named `name`
+	pub fn site(&self, name: &str) -> Result<Site> {
+		let path = self.sites_path().join(name);
+		if !path.is_dir() {
+			return Err(Error::NotFound { path });
+		}
+		Ok(Site(path))
+	}
+
 	pub fn is_valid(dir: &Path) -> bool {
 		tag_file::is_valid(dir.join(tag_file::NAME))
 	}
 
-	pub fn create(dir: &Path) -> std::io::Result<()> {
-		tag_file::create_in(dir)?;
+	pub fn create(dir: &Path) -> Result<()> {
+		tag_file::create_in(dir).at(&dir.join(tag_file::NAME))?;
 
 		debug!("creating sites subdirectory");
-		std::fs::create_dir(dir.join("sites"))
+		let sites_path = dir.join("sites");
+		std::fs::create_dir(&sites_path).at(&sites_path)
+	}
+
+	pub fn tag(&self) -> Result<tag_file::Tag> {
+		tag_file::Tag::read_from(&self.0)
+	}
+
+	pub fn sites_path(&self) -> PathBuf {
+		self.0.join("sites")
 	}
 
-	pub fn sites(&self) -> std::io::Result<Vec<anyhow::Result<Site>>> {
-		self.0.join("sites").read_dir().map(|iter| {
+	pub fn sites(&self) -> std::io::Result<Vec<Result<Site>>> {
+		self.sites_path().read_dir().map(|iter| {
 			iter.filter_map(|dentry_res| dentry_res.ok())
 				.map(Site::from)
 				.collect()
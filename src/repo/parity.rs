@@ -0,0 +1,586 @@
+//! Recovery data for snapshots, an erasure code overlay borrowing ideas from PAR3
+//!
+//! The regular files of a snapshot, including its meta files, are split into input blocks of a
+//! fixed size, with the last block of each file padded with zeros. Runs of up to
+//! [`MAX_GROUP_BLOCKS`] consecutive input blocks form groups, each with its own Reed-Solomon
+//! recovery blocks, so a group can be restored as long as no more of its blocks are damaged than
+//! it has recovery blocks. The BLAKE3 hashes of all blocks are recorded to locate the damage.
+//!
+//! The recovery data lives outside the snapshot, in the `parity/<snapshot>` directory of its site.
+//! Symlinks, including deduplicated files, are not covered, as their backing files are covered by
+//! the recovery data of the snapshots holding them.
+
+use std::{
+	collections::{BTreeSet, HashMap},
+	ffi::OsStr,
+	fs::{self, File, OpenOptions},
+	io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+	os::unix::prelude::{OpenOptionsExt, OsStrExt, OsStringExt, PermissionsExt},
+	path::{Path, PathBuf},
+};
+
+use blake3::Hash;
+use log::debug;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+use crate::{
+	repo::{
+		meta_file::{Line, MetaFile, Record},
+		snapshot::Snapshot,
+	},
+	util::hex::tagged_rawhex,
+};
+
+pub const DIR_NAME: &str = "parity";
+
+/// BRJ file with a header record, followed by a record for each covered file
+const INDEX_FNAME: &str = "index.brj";
+/// The hashes of all input blocks, followed by those of all recovery blocks
+const HASHES_FNAME: &str = "blocks.b3sum";
+/// The recovery blocks, group by group
+const RECOVERY_FNAME: &str = "recovery.bin";
+
+const FORMAT_VERSION: &[u8] = b"1";
+
+/// Maximum number of input blocks per group, keeping groups within the 256 blocks supported by
+/// Reed-Solomon codes over GF(2^8) at up to 100% redundancy
+pub const MAX_GROUP_BLOCKS: u64 = 128;
+
+mod key {
+	pub const FORMAT: &[u8] = b"baktu-parity";
+	pub const BLOCK_SIZE: &[u8] = b"block-size";
+	pub const REDUNDANCY: &[u8] = b"redundancy";
+	pub const PATH: &[u8] = b"path";
+	pub const SIZE: &[u8] = b"size";
+	pub const MODE: &[u8] = b"mode";
+	pub const HASH: &[u8] = b"b3sum";
+}
+
+fn invalid_data(msg: String) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn line(key: &[u8], value: &[u8]) -> Line {
+	Line([key, b" ", value].concat())
+}
+
+/// Returns the recovery data directory of the snapshot
+pub fn sidecar_dir(snap: &Snapshot) -> PathBuf {
+	snap.site().0.join(DIR_NAME).join(snap.name())
+}
+
+/// Removes the recovery data of the snapshot, returning whether there was any. Needed whenever
+/// the snapshot is modified, as repairing would otherwise revert the changes.
+pub fn remove(snap: &Snapshot) -> io::Result<bool> {
+	match fs::remove_dir_all(sidecar_dir(snap)) {
+		Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+		res => res.map(|()| true),
+	}
+}
+
+/// A file covered by recovery data
+#[derive(Debug)]
+struct Entry {
+	/// Path relative to the snapshot directory
+	rel_path: PathBuf,
+	size: u64,
+	mode: u32,
+	b3sum: Hash,
+	/// Index of the file's first input block
+	first_block: u64,
+}
+
+#[derive(Debug)]
+struct Index {
+	block_size: u64,
+	/// Percentage of recovery blocks per input blocks in each group
+	redundancy: u64,
+	entries: Vec<Entry>,
+	/// Total number of input blocks
+	blocks: u64,
+}
+
+impl Index {
+	fn block_count(size: u64, block_size: u64) -> u64 {
+		size.div_ceil(block_size)
+	}
+
+	/// Returns the input blocks of each group, along with its number of recovery blocks
+	fn groups(&self) -> impl Iterator<Item = (std::ops::Range<u64>, u64)> + '_ {
+		(0..self.blocks.div_ceil(MAX_GROUP_BLOCKS)).map(|group| {
+			let start = group * MAX_GROUP_BLOCKS;
+			let end = (start + MAX_GROUP_BLOCKS).min(self.blocks);
+			(start..end, recovery_count(end - start, self.redundancy))
+		})
+	}
+
+	/// Returns the index of the entry holding input block `block`
+	fn entry_of(&self, block: u64) -> usize {
+		self.entries
+			.partition_point(|entry| entry.first_block <= block)
+			- 1
+	}
+
+	fn to_records(&self) -> Vec<Record> {
+		let mut records = vec![Record(vec![
+			line(key::FORMAT, FORMAT_VERSION),
+			line(key::BLOCK_SIZE, self.block_size.to_string().as_bytes()),
+			line(key::REDUNDANCY, self.redundancy.to_string().as_bytes()),
+		])];
+		records.extend(self.entries.iter().map(|entry| {
+			Record(vec![
+				line(
+					key::PATH,
+					&tagged_rawhex::encode(false, entry.rel_path.as_os_str().as_bytes()),
+				),
+				line(key::SIZE, entry.size.to_string().as_bytes()),
+				line(key::MODE, format!("{:o}", entry.mode).as_bytes()),
+				line(key::HASH, entry.b3sum.to_hex().as_bytes()),
+			])
+		}));
+		records
+	}
+
+	fn from_records(records: &[Record], path: &Path) -> io::Result<Self> {
+		let malformed = || invalid_data(format!("malformed parity index {path:?}"));
+		let get = |record: &Record, key: &[u8]| -> io::Result<Vec<u8>> {
+			record
+				.0
+				.iter()
+				.find_map(|line| line.0.strip_prefix(key)?.strip_prefix(b" "))
+				.map(<[u8]>::to_vec)
+				.ok_or_else(malformed)
+		};
+		let get_str = |record: &Record, key: &[u8]| -> io::Result<String> {
+			String::from_utf8(get(record, key)?).map_err(|_| malformed())
+		};
+		let get_num = |record: &Record, key: &[u8], radix: u32| -> io::Result<u64> {
+			u64::from_str_radix(&get_str(record, key)?, radix).map_err(|_| malformed())
+		};
+
+		let (header, records) = records.split_first().ok_or_else(malformed)?;
+		if get(header, key::FORMAT)? != FORMAT_VERSION {
+			return Err(invalid_data(format!(
+				"unsupported parity format in {path:?}"
+			)));
+		}
+		let block_size = get_num(header, key::BLOCK_SIZE, 10)?;
+		let redundancy = get_num(header, key::REDUNDANCY, 10)?;
+		if block_size == 0 || !(1..=100).contains(&redundancy) {
+			return Err(malformed());
+		}
+
+		let mut blocks = 0;
+		let mut entries = Vec::new();
+		for record in records {
+			let rel_path = tagged_rawhex::decode(&get(record, key::PATH)?)
+				.map(|path| PathBuf::from(std::ffi::OsString::from_vec(path)))
+				.ok_or_else(malformed)?;
+			let size = get_num(record, key::SIZE, 10)?;
+			entries.push(Entry {
+				rel_path,
+				size,
+				mode: get_num(record, key::MODE, 8)? as u32,
+				b3sum: Hash::from_hex(get(record, key::HASH)?).map_err(|_| malformed())?,
+				first_block: blocks,
+			});
+			blocks += Self::block_count(size, block_size);
+		}
+		Ok(Index {
+			block_size,
+			redundancy,
+			entries,
+			blocks,
+		})
+	}
+}
+
+/// Returns the number of recovery blocks for a group of `blocks` input blocks
+fn recovery_count(blocks: u64, redundancy: u64) -> u64 {
+	(blocks * redundancy).div_ceil(100)
+}
+
+fn codec(data: u64, recovery: u64) -> io::Result<ReedSolomon> {
+	ReedSolomon::new(data as usize, recovery as usize)
+		.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{e:?}")))
+}
+
+/// Reads the next block from `reader`, padding it with zeros, and returns the number of bytes read
+fn read_block(reader: &mut impl Read, block: &mut [u8]) -> io::Result<usize> {
+	let mut filled = 0;
+	while filled < block.len() {
+		match reader.read(&mut block[filled..]) {
+			Ok(0) => break,
+			Ok(n) => filled += n,
+			Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+			Err(e) => return Err(e),
+		}
+	}
+	block[filled..].fill(0);
+	Ok(filled)
+}
+
+#[derive(Debug, Default)]
+pub struct CreateStats {
+	pub files: u64,
+	pub blocks: u64,
+	pub recovery_blocks: u64,
+}
+
+/// Writes recovery blocks for all regular files in the snapshot, replacing its previous recovery
+/// data, if any. `redundancy` is the percentage of recovery blocks per input block, from 1 to 100.
+pub fn create(snap: &Snapshot, block_size: u64, redundancy: u64) -> io::Result<CreateStats> {
+	if block_size == 0 || !(1..=100).contains(&redundancy) {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"block size must be positive and redundancy within 1-100%",
+		));
+	}
+
+	let sidecar = sidecar_dir(snap);
+	let mut partial_name = std::ffi::OsString::from(".");
+	partial_name.push(snap.name());
+	partial_name.push(".partial");
+	let partial = sidecar.with_file_name(partial_name);
+	if partial.exists() {
+		debug!("removing leftover partial recovery data {partial:?}");
+		fs::remove_dir_all(&partial)?;
+	}
+	fs::create_dir_all(&partial)?;
+
+	let mut index = Index {
+		block_size,
+		redundancy,
+		entries: Vec::new(),
+		blocks: 0,
+	};
+	let mut stats = CreateStats::default();
+	let mut hashes = BufWriter::new(File::create(partial.join(HASHES_FNAME))?);
+	let mut recovery = BufWriter::new(File::create(partial.join(RECOVERY_FNAME))?);
+	let mut recovery_hashes = Vec::new();
+
+	let mut group: Vec<Vec<u8>> = Vec::new();
+	let mut flush_group = |group: &mut Vec<Vec<u8>>| -> io::Result<()> {
+		let count = recovery_count(group.len() as u64, redundancy);
+		let data = group.len() as u64;
+		group.resize(group.len() + count as usize, vec![0; block_size as usize]);
+		codec(data, count)?
+			.encode(&mut *group)
+			.map_err(|e| io::Error::other(format!("{e:?}")))?;
+		for block in &group[data as usize..] {
+			recovery.write_all(block)?;
+			recovery_hashes.push(blake3::hash(block));
+		}
+		stats.recovery_blocks += count;
+		group.clear();
+		Ok(())
+	};
+
+	for entry in walkdir::WalkDir::new(&snap.0).sort_by_file_name() {
+		let entry = entry?;
+		if !entry.file_type().is_file() {
+			continue;
+		}
+		let rel_path = entry
+			.path()
+			.strip_prefix(&snap.0)
+			.expect("walked paths are within the snapshot");
+		let mut reader = BufReader::new(File::open(entry.path())?);
+		let mut hasher = blake3::Hasher::new();
+		let mut size = 0;
+		let first_block = index.blocks;
+		loop {
+			let mut block = vec![0; block_size as usize];
+			let read = read_block(&mut reader, &mut block)?;
+			if read == 0 {
+				break;
+			}
+			hasher.update(&block[..read]);
+			size += read as u64;
+			hashes.write_all(blake3::hash(&block).as_bytes())?;
+			index.blocks += 1;
+			group.push(block);
+			if group.len() as u64 == MAX_GROUP_BLOCKS {
+				flush_group(&mut group)?;
+			}
+		}
+
+		index.entries.push(Entry {
+			rel_path: rel_path.to_path_buf(),
+			size,
+			mode: entry.metadata()?.permissions().mode() & 0o7777,
+			b3sum: hasher.finalize(),
+			first_block,
+		});
+		stats.files += 1;
+	}
+	if !group.is_empty() {
+		flush_group(&mut group)?;
+	}
+	stats.blocks = index.blocks;
+
+	for hash in recovery_hashes {
+		hashes.write_all(hash.as_bytes())?;
+	}
+	hashes
+		.into_inner()
+		.map_err(|e| e.into_error())?
+		.sync_all()?;
+	recovery
+		.into_inner()
+		.map_err(|e| e.into_error())?
+		.sync_all()?;
+	MetaFile(partial.join(INDEX_FNAME)).write_records(&index.to_records())?;
+
+	remove(snap)?;
+	fs::rename(partial, sidecar)?;
+	Ok(stats)
+}
+
+/// Outcome of repairing a damaged file
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+	Repaired,
+	/// Would be repaired, if not for a dry run
+	Repairable,
+	/// Too many blocks of its group are damaged
+	Unrecoverable,
+}
+
+/// A damaged file, with its path relative to the snapshot directory
+#[derive(Debug)]
+pub struct Damage {
+	pub rel_path: PathBuf,
+	pub outcome: Outcome,
+}
+
+/// Checks all files covered by the recovery data of the snapshot, restoring the damaged ones
+/// unless `dry_run`. Returns `None` if the snapshot has no recovery data.
+pub fn repair(snap: &Snapshot, dry_run: bool) -> io::Result<Option<Vec<Damage>>> {
+	let sidecar = sidecar_dir(snap);
+	let index_file = MetaFile(sidecar.join(INDEX_FNAME));
+	let index = match index_file.records() {
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+		res => Index::from_records(&res?, &index_file.0)?,
+	};
+	let hashes = fs::read(sidecar.join(HASHES_FNAME))?;
+	let hash_of = |block: u64| -> Option<Hash> {
+		let start = block as usize * blake3::OUT_LEN;
+		let bytes = hashes.get(start..start + blake3::OUT_LEN)?;
+		Some(Hash::from(<[u8; blake3::OUT_LEN]>::try_from(bytes).ok()?))
+	};
+	let block_size = index.block_size as usize;
+
+	// Find the damaged files, and their damaged blocks
+	let mut damaged_files = BTreeSet::new();
+	let mut damaged_blocks = BTreeSet::new();
+	for (entry_idx, entry) in index.entries.iter().enumerate() {
+		let blocks =
+			entry.first_block..entry.first_block + Index::block_count(entry.size, index.block_size);
+		let path = snap.0.join(&entry.rel_path);
+		let mut reader = match File::open(&path) {
+			Ok(file) => BufReader::new(file),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {
+				damaged_files.insert(entry_idx);
+				damaged_blocks.extend(blocks);
+				continue;
+			}
+			Err(e) => return Err(e),
+		};
+		let mut hasher = blake3::Hasher::new();
+		let mut block = vec![0; block_size];
+		for idx in blocks {
+			let read = read_block(&mut reader, &mut block)?;
+			hasher.update(&block[..read]);
+			if Some(blake3::hash(&block)) != hash_of(idx) {
+				damaged_blocks.insert(idx);
+			}
+		}
+		// Catches appended data, as well as corrupted padding of the last block
+		io::copy(&mut reader, &mut hasher)?;
+		if hasher.finalize() != entry.b3sum {
+			damaged_files.insert(entry_idx);
+		}
+	}
+	if damaged_files.is_empty() {
+		return Ok(Some(Vec::new()));
+	}
+
+	// Reconstruct the damaged blocks, group by group
+	let mut recovery = File::open(sidecar.join(RECOVERY_FNAME))?;
+	let mut recovery_offset = 0;
+	let mut restored: HashMap<u64, Vec<u8>> = HashMap::new();
+	for (blocks, count) in index.groups() {
+		let first_recovery = recovery_offset;
+		recovery_offset += count;
+		if damaged_blocks.range(blocks.clone()).next().is_none() {
+			continue;
+		}
+
+		let mut shards: Vec<Option<Vec<u8>>> = Vec::new();
+		for idx in blocks.clone() {
+			shards.push(if damaged_blocks.contains(&idx) {
+				None
+			} else {
+				Some(read_input_block(snap, &index, idx)?)
+			});
+		}
+		for recovery_idx in first_recovery..first_recovery + count {
+			let mut block = vec![0; block_size];
+			recovery.seek(SeekFrom::Start(recovery_idx * index.block_size))?;
+			let read = read_block(&mut recovery, &mut block)?;
+			let is_intact = read == block_size
+				&& Some(blake3::hash(&block)) == hash_of(index.blocks + recovery_idx);
+			shards.push(is_intact.then_some(block));
+		}
+
+		if shards.iter().filter(|shard| shard.is_none()).count() as u64 > count {
+			debug!("too many damaged blocks in group {blocks:?}");
+			continue;
+		}
+		codec(blocks.end - blocks.start, count)?
+			.reconstruct_data(&mut shards)
+			.map_err(|e| io::Error::other(format!("{e:?}")))?;
+		for (idx, shard) in blocks.clone().zip(shards) {
+			if damaged_blocks.contains(&idx) {
+				restored.insert(idx, shard.expect("data shards are reconstructed"));
+			}
+		}
+	}
+
+	// Rewrite the damaged files from intact and restored blocks, streamed block by block into a
+	// temporary file which only replaces the damaged one once the content checks out
+	let mut damage = Vec::new();
+	for entry_idx in damaged_files {
+		let entry = &index.entries[entry_idx];
+		let blocks =
+			entry.first_block..entry.first_block + Index::block_count(entry.size, index.block_size);
+		let mut replacement = match dry_run {
+			true => None,
+			false => Some(Replacement::create(
+				&snap.0.join(&entry.rel_path),
+				entry.mode,
+			)?),
+		};
+		let mut hasher = blake3::Hasher::new();
+		let mut remaining = entry.size;
+		let mut is_complete = true;
+		for idx in blocks {
+			let read;
+			let block = match restored.get(&idx) {
+				Some(block) => block,
+				None if damaged_blocks.contains(&idx) => {
+					is_complete = false;
+					break;
+				}
+				None => {
+					read = read_input_block(snap, &index, idx)?;
+					&read
+				}
+			};
+			// The last block is padded with zeros
+			let content = &block[..remaining.min(index.block_size) as usize];
+			remaining -= content.len() as u64;
+			hasher.update(content);
+			if let Some(replacement) = &mut replacement {
+				replacement.file.write_all(content)?;
+			}
+		}
+
+		let outcome = if !is_complete || hasher.finalize() != entry.b3sum {
+			if let Some(replacement) = replacement {
+				replacement.discard()?;
+			}
+			Outcome::Unrecoverable
+		} else if let Some(replacement) = replacement {
+			replacement.finish()?;
+			Outcome::Repaired
+		} else {
+			Outcome::Repairable
+		};
+		damage.push(Damage {
+			rel_path: entry.rel_path.clone(),
+			outcome,
+		});
+	}
+	Ok(Some(damage))
+}
+
+/// Reads input block `idx` from the snapshot, which is assumed to be intact
+fn read_input_block(snap: &Snapshot, index: &Index, idx: u64) -> io::Result<Vec<u8>> {
+	let entry = &index.entries[index.entry_of(idx)];
+	let mut file = File::open(snap.0.join(&entry.rel_path))?;
+	file.seek(SeekFrom::Start(
+		(idx - entry.first_block) * index.block_size,
+	))?;
+	let mut block = vec![0; index.block_size as usize];
+	read_block(&mut file, &mut block)?;
+	Ok(block)
+}
+
+/// A temporary file in the same directory as the file it is to replace
+struct Replacement {
+	path: PathBuf,
+	tmp_path: PathBuf,
+	file: File,
+}
+
+impl Replacement {
+	fn create(path: &Path, mode: u32) -> io::Result<Self> {
+		let dir = path
+			.parent()
+			.expect("covered files are within the snapshot");
+		fs::create_dir_all(dir)?;
+		let mut tmp_name = std::ffi::OsString::from(".");
+		tmp_name.push(path.file_name().unwrap_or(OsStr::new("")));
+		tmp_name.push(".baktu-repair");
+		let tmp_path = dir.join(tmp_name);
+
+		let file = OpenOptions::new()
+			.write(true)
+			.create(true)
+			.truncate(true)
+			.mode(mode)
+			.open(&tmp_path)?;
+		file.set_permissions(fs::Permissions::from_mode(mode))?;
+		Ok(Replacement {
+			path: path.to_path_buf(),
+			tmp_path,
+			file,
+		})
+	}
+
+	/// Replaces the file with the content written so far
+	fn finish(self) -> io::Result<()> {
+		self.file.sync_all()?;
+		fs::rename(self.tmp_path, self.path)
+	}
+
+	fn discard(self) -> io::Result<()> {
+		drop(self.file);
+		fs::remove_file(self.tmp_path)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn group_layout() {
+		let index = Index {
+			block_size: 4,
+			redundancy: 10,
+			entries: Vec::new(),
+			blocks: MAX_GROUP_BLOCKS + 5,
+		};
+		let groups: Vec<_> = index.groups().collect();
+		assert_eq!(
+			groups,
+			[
+				(0..MAX_GROUP_BLOCKS, 13),
+				(MAX_GROUP_BLOCKS..MAX_GROUP_BLOCKS + 5, 1)
+			]
+		);
+	}
+}
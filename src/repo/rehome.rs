@@ -0,0 +1,194 @@
+//! Keeping deduplicated files intact while removing parts of a repository
+//!
+//! Deduplicated files are relative symlinks to a backing file, which may be in any snapshot of
+//! any site. Before removing a backing file, its content is moved into one of its surviving
+//! dependents, with the other surviving dependents relinked to it.
+
+use std::{
+	collections::HashMap,
+	ffi::OsStr,
+	fs, io,
+	os::unix::prelude::OsStrExt,
+	path::{Path, PathBuf},
+};
+
+use log::{debug, warn};
+use pathdiff::diff_paths;
+
+use crate::{
+	repo::{
+		meta_file::{line, MetaFile, MetaRecord},
+		parity,
+		snapshot::Snapshot,
+		Repo,
+	},
+	util::ext::PathExt,
+};
+
+/// A deduplicated file, i.e. a symlink to a backing file along with its metadata record
+#[derive(Clone, Debug)]
+pub struct Dependent {
+	/// Path of the symlink
+	pub link: PathBuf,
+	pub meta_file: MetaFile,
+	/// Snapshot containing the symlink, used to prefer older snapshots as new homes
+	pub snapshot: Snapshot,
+}
+
+/// A history interval record, and the path holding the full record it refers to
+#[derive(Debug)]
+struct IntervalRef {
+	path: PathBuf,
+	full_record_path: PathBuf,
+}
+
+/// The deduplication relationships within a repository
+pub struct DependencyIndex {
+	/// Map from backing file path to the deduplicated files referring to it
+	dependents: HashMap<PathBuf, Vec<Dependent>>,
+	intervals: Vec<IntervalRef>,
+}
+
+/// Moving a backing file into a new home, and relinking its other dependents to it
+#[derive(Debug)]
+pub struct Rehoming {
+	pub backing: PathBuf,
+	pub new_home: Dependent,
+	pub relinked: Vec<PathBuf>,
+}
+
+impl DependencyIndex {
+	/// Scans the metadata records of every snapshot in `repo`
+	pub fn build(repo: &Repo) -> io::Result<DependencyIndex> {
+		let mut index = DependencyIndex {
+			dependents: HashMap::new(),
+			intervals: Vec::new(),
+		};
+
+		for site_res in repo.sites()? {
+			let site = site_res?;
+			for snap in site.snapshots_sorted()? {
+				for meta_res in snap.meta_files()? {
+					let meta = meta_res?;
+					let dir = meta.0.parent().expect("meta file has parent").to_path_buf();
+					for rec in meta.meta_records()? {
+						index.add(&snap, &meta, &dir, rec)?;
+					}
+				}
+			}
+		}
+
+		Ok(index)
+	}
+
+	fn add(
+		&mut self,
+		snap: &Snapshot,
+		meta: &MetaFile,
+		dir: &Path,
+		rec: MetaRecord,
+	) -> io::Result<()> {
+		let path = dir.join(OsStr::from_bytes(&rec.name));
+
+		if let Some(since) = rec.same_since {
+			let rel_path = path
+				.strip_prefix(snap.data_dir())
+				.expect("records are within the data dir");
+			let since_snap = snap.0.with_file_name(since.to_string());
+			self.intervals.push(IntervalRef {
+				full_record_path: Snapshot(since_snap).data_dir().join(rel_path),
+				path,
+			});
+		} else if rec.is_deduplicated {
+			let backing = dir.join(fs::read_link(&path)?).lexical_normalize();
+			self.dependents.entry(backing).or_default().push(Dependent {
+				link: path,
+				meta_file: MetaFile(meta.0.clone()),
+				snapshot: snap.clone(),
+			});
+		}
+		Ok(())
+	}
+
+	/// Returns the rehomings needed before removing all paths for which `is_doomed` returns true,
+	/// along with everything below them. Fails if a surviving history interval record would be
+	/// left without its full record.
+	pub fn plan(&self, is_doomed: impl Fn(&Path) -> bool) -> io::Result<Vec<Rehoming>> {
+		if let Some(interval) = self
+			.intervals
+			.iter()
+			.find(|i| !is_doomed(&i.path) && is_doomed(&i.full_record_path))
+		{
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				format!(
+					"the history interval record of {:?} refers to {:?}, which would be removed",
+					interval.path, interval.full_record_path
+				),
+			));
+		}
+
+		let mut rehomings: Vec<Rehoming> = self
+			.dependents
+			.iter()
+			.filter(|(backing, _)| is_doomed(backing))
+			.filter_map(|(backing, dependents)| {
+				let mut survivors: Vec<&Dependent> =
+					dependents.iter().filter(|d| !is_doomed(&d.link)).collect();
+				survivors.sort_by(|a, b| {
+					(a.snapshot.number(), &a.link).cmp(&(b.snapshot.number(), &b.link))
+				});
+				let (new_home, others) = survivors.split_first()?;
+				Some(Rehoming {
+					backing: backing.clone(),
+					new_home: (*new_home).clone(),
+					relinked: others.iter().map(|d| d.link.clone()).collect(),
+				})
+			})
+			.collect();
+		rehomings.sort_by(|a, b| a.backing.cmp(&b.backing));
+		Ok(rehomings)
+	}
+}
+
+impl Rehoming {
+	/// Performs the rehoming. Other dependents are relinked first, going through the new home
+	/// until the backing file is moved in, so an interruption at any point leaves no dangling
+	/// symlinks. The recovery data of the new home's snapshot, if any, is removed, as it no longer
+	/// matches the snapshot.
+	pub fn apply(&self) -> io::Result<()> {
+		let home = &self.new_home.link;
+		for link in &self.relinked {
+			let parent = link.parent().expect("dependent has parent");
+			let target = diff_paths(home, parent).expect("should work for 2 absolute paths");
+			debug!("relinking {link:?} to {target:?}");
+
+			let mut tmp_name = link.file_name().expect("dependent has name").to_owned();
+			tmp_name.push(".baktu-relink");
+			let tmp = link.with_file_name(tmp_name);
+			std::os::unix::fs::symlink(target, &tmp)?;
+			fs::rename(tmp, link)?;
+		}
+
+		debug!("moving {:?} to {home:?}", self.backing);
+		fs::rename(&self.backing, home)?;
+
+		let name = home.file_name().expect("dependent has name").as_bytes();
+		let mut records = self.new_home.meta_file.records()?;
+		for record in records.iter_mut() {
+			if record.name().as_deref() == Some(name) {
+				record.0.retain(|l| l.0 != line::IS_DEDUPLICATED);
+			}
+		}
+		self.new_home.meta_file.write_records(&records)?;
+
+		if parity::remove(&self.new_home.snapshot)? {
+			warn!(
+				"removed the recovery data of {:?}, as its files changed, run `baktu parity create` \
+				to recreate it",
+				self.new_home.snapshot.0
+			);
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,118 @@
+//! Selection of the snapshots to keep when pruning a site
+
+use chrono::{DateTime, Datelike, Local};
+
+#[derive(Debug, Default)]
+pub struct Policy {
+	pub keep_last: usize,
+	pub keep_daily: usize,
+	pub keep_weekly: usize,
+	pub keep_monthly: usize,
+}
+
+impl Policy {
+	pub fn is_empty(&self) -> bool {
+		self.keep_last == 0
+			&& self.keep_daily == 0
+			&& self.keep_weekly == 0
+			&& self.keep_monthly == 0
+	}
+
+	/// Returns, for each of `times` (snapshot creation times, oldest first), the reasons for
+	/// keeping that snapshot. Snapshots with no reasons are to be removed.
+	///
+	/// Like in most backup tools, each periodic rule keeps the newest snapshot of each of the
+	/// latest N periods that contain a snapshot.
+	pub fn reasons_to_keep(&self, times: &[DateTime<Local>]) -> Vec<Vec<&'static str>> {
+		let mut reasons = vec![Vec::new(); times.len()];
+
+		for idx in (0..times.len()).rev().take(self.keep_last) {
+			reasons[idx].push("last");
+		}
+
+		type PeriodFn = fn(&DateTime<Local>) -> (i32, u32);
+		let rules: [(&str, usize, PeriodFn); 3] = [
+			("daily", self.keep_daily, |t| (t.year(), t.ordinal())),
+			("weekly", self.keep_weekly, |t| {
+				let week = t.iso_week();
+				(week.year(), week.week())
+			}),
+			("monthly", self.keep_monthly, |t| (t.year(), t.month())),
+		];
+		for (reason, count, period) in rules {
+			let mut kept = 0;
+			let mut last_period = None;
+			for idx in (0..times.len()).rev() {
+				if kept == count {
+					break;
+				}
+				let cur = period(&times[idx]);
+				if last_period != Some(cur) {
+					last_period = Some(cur);
+					reasons[idx].push(reason);
+					kept += 1;
+				}
+			}
+		}
+
+		reasons
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use chrono::TimeZone;
+
+	use super::*;
+
+	fn at(y: i32, m: u32, d: u32, h: u32) -> DateTime<Local> {
+		Local.with_ymd_and_hms(y, m, d, h, 0, 0).unwrap()
+	}
+
+	#[test]
+	fn keep_last() {
+		let policy = Policy {
+			keep_last: 2,
+			..Default::default()
+		};
+		let times = [at(2024, 1, 1, 0), at(2024, 1, 2, 0), at(2024, 1, 3, 0)];
+		assert_eq!(
+			policy.reasons_to_keep(&times),
+			[vec![], vec!["last"], vec!["last"]]
+		);
+	}
+
+	#[test]
+	fn keep_periodic() {
+		let policy = Policy {
+			keep_daily: 2,
+			keep_weekly: 1,
+			keep_monthly: 2,
+			..Default::default()
+		};
+		let times = [
+			at(2024, 1, 31, 10),
+			at(2024, 2, 26, 10), // Monday
+			at(2024, 2, 28, 10),
+			at(2024, 2, 28, 12),
+		];
+		assert_eq!(
+			policy.reasons_to_keep(&times),
+			[
+				vec!["monthly"],
+				vec!["daily"],
+				vec![],
+				vec!["daily", "weekly", "monthly"],
+			]
+		);
+	}
+
+	#[test]
+	fn empty() {
+		assert!(Policy::default().is_empty());
+		assert_eq!(
+			Policy::default().reasons_to_keep(&[at(2024, 1, 1, 0)]),
+			[Vec::<&str>::new()]
+		);
+	}
+}
@@ -0,0 +1,215 @@
+//! Signatures over the root hash and summary of snapshots
+//!
+//! A signature file is plain text: a header line, followed by `key value` lines holding the
+//! public key, the root hash of the snapshot and the BLAKE3 hash of its summary, and a final
+//! `signature` line. The signature covers all the preceding bytes of the file.
+
+use std::{
+	fs::{self, OpenOptions},
+	io::{self, Read, Write},
+	os::unix::prelude::OpenOptionsExt,
+	path::{Path, PathBuf},
+};
+
+use blake3::Hash;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+
+use crate::{
+	file,
+	repo::{merkle, snapshot::Snapshot, summary},
+	util::hex,
+};
+
+pub const FNAME: &str = "signature.txt";
+
+const HEADER: &str = "baktu snapshot signature version 1";
+
+fn invalid_data(msg: String) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn decode_array<const N: usize>(hex_str: &str) -> Option<[u8; N]> {
+	hex::try_decode(hex_str.as_bytes())?.try_into().ok()
+}
+
+fn encode(bytes: &[u8]) -> String {
+	String::from_utf8(hex::encode(bytes)).expect("hex is ASCII")
+}
+
+/// Generates a key pair, writing the hex-encoded secret key to `secret_path`, which must not
+/// exist, and the public key to the same path with an added `.pub` extension
+pub fn generate_key(secret_path: &Path) -> io::Result<VerifyingKey> {
+	let mut seed = [0u8; 32];
+	fs::File::open("/dev/urandom")?.read_exact(&mut seed)?;
+	let key = SigningKey::from_bytes(&seed);
+
+	let mut public_path = secret_path.as_os_str().to_owned();
+	public_path.push(".pub");
+	let public_path = PathBuf::from(public_path);
+	if public_path.exists() {
+		return Err(io::Error::new(
+			io::ErrorKind::AlreadyExists,
+			format!("{public_path:?} already exists"),
+		));
+	}
+
+	OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.mode(0o600)
+		.open(secret_path)?
+		.write_all(format!("{}\n", encode(key.as_bytes())).as_bytes())?;
+	fs::write(
+		public_path,
+		format!("{}\n", encode(key.verifying_key().as_bytes())),
+	)?;
+	Ok(key.verifying_key())
+}
+
+/// Reads a secret key written by [`generate_key`]
+pub fn read_secret_key(path: &Path) -> io::Result<SigningKey> {
+	decode_array(fs::read_to_string(path)?.trim_end())
+		.map(|seed| SigningKey::from_bytes(&seed))
+		.ok_or_else(|| invalid_data(format!("malformed secret key in {path:?}")))
+}
+
+/// Parses a hex-encoded public key
+pub fn parse_public_key(hex_str: &str) -> io::Result<VerifyingKey> {
+	decode_array(hex_str.trim_end())
+		.and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+		.ok_or_else(|| invalid_data(format!("malformed public key {hex_str:?}")))
+}
+
+/// The signed statement about a snapshot
+#[derive(Debug, PartialEq)]
+struct Manifest {
+	public_key: [u8; 32],
+	root: Hash,
+	summary: Hash,
+}
+
+impl Manifest {
+	/// Returns the manifest for the current state of the snapshot
+	fn of(snap: &Snapshot, public_key: &VerifyingKey) -> io::Result<Self> {
+		let root = merkle::root_hash(snap)?
+			.ok_or_else(|| invalid_data(format!("no root hash recorded in {:?}", snap.0)))?;
+		Ok(Manifest {
+			public_key: public_key.to_bytes(),
+			root,
+			summary: file::b3sum(&snap.0.join(summary::FNAME))?,
+		})
+	}
+
+	fn to_text(&self) -> String {
+		format!(
+			"{HEADER}\npublic-key {}\nroot-b3sum {}\nsummary-b3sum {}\n",
+			encode(&self.public_key),
+			self.root.to_hex(),
+			self.summary.to_hex()
+		)
+	}
+
+	fn parse(text: &str) -> Option<Self> {
+		let mut lines = text.lines();
+		if lines.next()? != HEADER {
+			return None;
+		}
+		let mut value = |key: &str| lines.next()?.strip_prefix(key)?.strip_prefix(' ');
+		let manifest = Manifest {
+			public_key: decode_array(value("public-key")?)?,
+			root: Hash::from_hex(value("root-b3sum")?).ok()?,
+			summary: Hash::from_hex(value("summary-b3sum")?).ok()?,
+		};
+		lines.next().is_none().then_some(manifest)
+	}
+}
+
+/// Signs the root hash and summary of the snapshot with `key`, which must be done after both are
+/// written
+pub fn sign(snap: &Snapshot, key: &SigningKey) -> io::Result<()> {
+	let text = Manifest::of(snap, &key.verifying_key())?.to_text();
+	let signature = key.sign(text.as_bytes());
+	fs::write(
+		snap.0.join(FNAME),
+		format!("{text}signature {}\n", encode(&signature.to_bytes())),
+	)
+}
+
+/// Checks the signature of the snapshot against `public_key`, as well as the root hash and summary
+/// it covers, returning why it does not hold if so. The rest of the metadata is covered by the
+/// root hash, and can be checked via [`merkle::verify`].
+pub fn verify(snap: &Snapshot, public_key: &VerifyingKey) -> io::Result<Result<(), String>> {
+	let data = match fs::read_to_string(snap.0.join(FNAME)) {
+		Err(e) if e.kind() == io::ErrorKind::NotFound => {
+			return Ok(Err("no signature recorded".to_owned()))
+		}
+		res => res?,
+	};
+
+	let parsed = data
+		.trim_end_matches('\n')
+		.rsplit_once('\n')
+		.and_then(|(text, sig_line)| {
+			let text = &data[..text.len() + 1];
+			let signature = decode_array(sig_line.strip_prefix("signature ")?)?;
+			Some((
+				text,
+				Manifest::parse(text)?,
+				Signature::from_bytes(&signature),
+			))
+		});
+	let Some((text, signed, signature)) = parsed else {
+		return Ok(Err("malformed signature file".to_owned()));
+	};
+
+	if signed.public_key != public_key.to_bytes() {
+		return Ok(Err(format!(
+			"signed by a different key: {}",
+			encode(&signed.public_key)
+		)));
+	}
+	if public_key
+		.verify_strict(text.as_bytes(), &signature)
+		.is_err()
+	{
+		return Ok(Err("invalid signature".to_owned()));
+	}
+
+	let current = match Manifest::of(snap, public_key) {
+		Err(e)
+			if matches!(
+				e.kind(),
+				io::ErrorKind::NotFound | io::ErrorKind::InvalidData
+			) =>
+		{
+			return Ok(Err(e.to_string()))
+		}
+		res => res?,
+	};
+	Ok(if current.root != signed.root {
+		Err("root hash changed since signing".to_owned())
+	} else if current.summary != signed.summary {
+		Err("summary changed since signing".to_owned())
+	} else {
+		Ok(())
+	})
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn manifest_roundtrip() {
+		let manifest = Manifest {
+			public_key: [7; 32],
+			root: blake3::hash(b"root"),
+			summary: blake3::hash(b"summary"),
+		};
+		assert_eq!(Manifest::parse(&manifest.to_text()), Some(manifest));
+		assert_eq!(
+			Manifest::parse("baktu snapshot signature version 1\n"),
+			None
+		);
+	}
+}
@@ -1,17 +1,20 @@
 use std::{
-	error::Error,
 	ffi::OsString,
 	fs::{self, File},
 	io,
 	os::unix::prelude::OsStringExt,
 	path::{Path, PathBuf},
+	time::Duration,
 };
 
+use ed25519_dalek::SigningKey;
 use log::debug;
 use serde::Deserialize;
 
 use crate::{
-	repo::Repo,
+	error::{Error, IoResultExt, Result},
+	file::{fs_type::FsType, FileType},
+	repo::{signature, Repo},
 	util::{dsv, ext::PathExt, nsv},
 };
 
@@ -33,9 +36,47 @@ pub mod config_file {
 	pub static DATA: &str = include_str!(concat!("../../templates/", TEMPLATE_NAME_MACRO!()));
 }
 
+/// Names of the files that make up a site's configuration, relative to the site directory
+pub const CONFIG_FILE_NAMES: [&str; 3] = [config_file::NAME, INCLUDES_NAME, EXCLUDES_NAME];
+
 #[derive(Deserialize)]
 pub struct Config {
 	pub exclude: ExcludeCfg,
+
+	#[serde(default)]
+	pub signing: SigningCfg,
+
+	#[serde(default)]
+	pub hooks: HooksCfg,
+}
+
+/// Shell commands run around each `baktu snap`, see the site config template for their environment
+#[derive(Default, Deserialize)]
+pub struct HooksCfg {
+	/// Run before creating the snapshot, aborting it if the command fails
+	#[serde(default)]
+	pub pre_snap: Option<String>,
+
+	/// Run after the snapshot has been created, failing the snapshot if the command fails
+	#[serde(default)]
+	pub post_snap: Option<String>,
+
+	/// Run if the snapshot fails, including due to the other hooks
+	#[serde(default)]
+	pub on_failure: Option<String>,
+}
+
+impl HooksCfg {
+	pub fn is_empty(&self) -> bool {
+		self.pre_snap.is_none() && self.post_snap.is_none() && self.on_failure.is_none()
+	}
+}
+
+#[derive(Default, Deserialize)]
+pub struct SigningCfg {
+	/// File containing the ed25519 secret key to sign snapshots with
+	#[serde(default)]
+	pub key: Option<PathBuf>,
 }
 
 #[derive(Deserialize)]
@@ -43,6 +84,41 @@ pub struct ExcludeCfg {
 	pub cachedir_tag: bool,
 	pub nodump: bool,
 	pub all_eacces: bool,
+
+	#[serde(default)]
+	pub one_file_system: bool,
+
+	/// Maximum size of regular files, in bytes
+	#[serde(default)]
+	pub max_size: Option<u64>,
+
+	#[serde(default, with = "humantime_serde")]
+	pub mtime_older_than: Option<Duration>,
+
+	#[serde(default, with = "humantime_serde")]
+	pub mtime_newer_than: Option<Duration>,
+
+	#[serde(default)]
+	pub types: Vec<FileType>,
+
+	#[serde(default)]
+	pub fs_types: Vec<FsType>,
+}
+
+impl Config {
+	/// Reads the signing key configured via `signing.key`, if any
+	pub fn signing_key(&self) -> Result<Option<SigningKey>> {
+		match &self.signing.key {
+			None => Ok(None),
+			Some(path) => match signature::read_secret_key(&path.tilde_expand()) {
+				Ok(key) => Ok(Some(key)),
+				Err(e) => Err(Error::Config {
+					path: path.clone(),
+					reason: format!("unable to read signing key: {e}"),
+				}),
+			},
+		}
+	}
 }
 
 #[derive(Debug)]
@@ -62,8 +138,7 @@ impl Site {
 		File::create(site_path.join(EXCLUDES_NAME))?;
 
 		debug!("creating site config file");
-		fs::write(site_path.join(config_file::NAME), config_file::DATA)
-			.expect("unable to write to config file");
+		fs::write(site_path.join(config_file::NAME), config_file::DATA)?;
 
 		debug!("creating snapshots dir");
 		fs::create_dir(site_path.join(Self::SNAPSHOTS_DIR_NAME))
@@ -77,14 +152,21 @@ impl Site {
 			})
 	}
 
-	pub fn from(de: std::fs::DirEntry) -> anyhow::Result<Site> {
-		if !de.file_type()?.is_dir() {
-			anyhow::bail!("Not a directory: {:?}", de.path())
+	pub fn from(de: std::fs::DirEntry) -> Result<Site> {
+		if !de.file_type().at(&de.path())?.is_dir() {
+			Err(Error::Corrupt {
+				path: de.path(),
+				reason: "not a directory".to_owned(),
+			})
 		} else {
 			Ok(Site(de.path()))
 		}
 	}
 
+	pub fn name(&self) -> &std::ffi::OsStr {
+		self.0.file_name().expect("site path should not end in ..")
+	}
+
 	pub fn repo(&self) -> Repo {
 		Repo(
 			self.0
@@ -100,10 +182,12 @@ impl Site {
 		self.0.join(Self::SNAPSHOTS_DIR_NAME)
 	}
 
-	pub fn get_config(&self) -> Result<Config, Box<dyn Error>> {
-		Ok(toml::from_str(&std::fs::read_to_string(
-			self.0.join(config_file::NAME),
-		)?)?)
+	pub fn get_config(&self) -> Result<Config> {
+		let path = self.0.join(config_file::NAME);
+		toml::from_str(&std::fs::read_to_string(&path)?).map_err(|e| Error::Config {
+			path,
+			reason: e.to_string(),
+		})
 	}
 
 	/// Returns tilde-expanded paths from the specified NSV file
@@ -131,4 +215,84 @@ impl Site {
 				.collect()
 		})
 	}
+
+	/// Returns the site's validly named snapshots, in sequence order
+	pub fn snapshots_sorted(&self) -> io::Result<Vec<Snapshot>> {
+		let mut snapshots: Vec<Snapshot> = self
+			.snapshots()?
+			.into_iter()
+			.collect::<io::Result<Vec<_>>>()?
+			.into_iter()
+			.filter(|snap| snap.number().is_some())
+			.collect();
+		snapshots.sort_by_key(|snap| snap.number());
+		Ok(snapshots)
+	}
+
+	/// Returns the path for the snapshot following the latest one, i.e. `0`, `1` and so on
+	pub fn next_snapshot_path(&self) -> io::Result<PathBuf> {
+		let next = self
+			.snapshots_sorted()?
+			.last()
+			.and_then(Snapshot::number)
+			.map_or(0, |n| n + 1);
+		Ok(self.snaps_path().join(next.to_string()))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn config_template_parses() {
+		let config: Config = toml::from_str(config_file::DATA).unwrap();
+		assert!(config.exclude.max_size.is_none());
+		assert!(config.exclude.types.is_empty());
+		assert!(config.exclude.fs_types.is_empty());
+		assert!(config.hooks.pre_snap.is_none());
+	}
+
+	#[test]
+	fn config_exclude_predicates() {
+		let config: Config = toml::from_str(
+			"[exclude]\n\
+			cachedir_tag = false\n\
+			nodump = false\n\
+			all_eacces = false\n\
+			max_size = 1048576\n\
+			mtime_older_than = \"2years\"\n\
+			mtime_newer_than = \"10min\"\n\
+			types = [\"sock\", \"fifo\", \"chr\", \"blk\"]\n\
+			fs_types = [\"proc\", \"sysfs\", \"tmpfs\"]\n",
+		)
+		.unwrap();
+
+		assert_eq!(config.exclude.max_size, Some(1024 * 1024));
+		assert_eq!(
+			config.exclude.mtime_newer_than,
+			Some(Duration::from_secs(10 * 60))
+		);
+		assert!(config.exclude.mtime_older_than.is_some());
+		assert_eq!(
+			config.exclude.types,
+			vec![FileType::Sock, FileType::Fifo, FileType::Chr, FileType::Blk]
+		);
+		assert_eq!(
+			config.exclude.fs_types,
+			vec![FsType::Proc, FsType::Sysfs, FsType::Tmpfs]
+		);
+	}
+
+	#[test]
+	fn config_unknown_fs_type() {
+		assert!(toml::from_str::<Config>(
+			"[exclude]\n\
+			cachedir_tag = false\n\
+			nodump = false\n\
+			all_eacces = false\n\
+			fs_types = [\"procfs\"]\n",
+		)
+		.is_err());
+	}
 }
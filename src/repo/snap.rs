@@ -0,0 +1,1302 @@
+//! Creation of snapshots by walking the include paths of a site
+
+use std::{
+	cell::RefCell,
+	collections::{HashMap, HashSet},
+	ffi::{c_char, CString, OsString},
+	fs,
+	io::{self, stdout, ErrorKind, Write},
+	os::unix::prelude::{MetadataExt, OsStrExt},
+	path::{Path, PathBuf},
+	process::{Command, Stdio},
+	time::{Instant, SystemTime},
+};
+
+use chrono::Utc;
+use libc::faccessat;
+use log::{debug, info, trace, warn, LevelFilter};
+use nix::sys::stat::{mknod, Mode, SFlag};
+use serde::Serialize;
+use walkdir::DirEntry;
+
+use crate::{
+	error::{Error, IoResultExt, Result},
+	file::{self, filekey::FileKey, fs_type::FsType, FileType},
+	repo::{
+		self,
+		meta_file::MetaRecord,
+		site::{config_file, ExcludeCfg, Site, EXCLUDES_NAME, INCLUDES_NAME},
+		snapshot::{self, Exclusion, Snapshot},
+		summary::Summary,
+	},
+	util::bytes,
+};
+
+/// Options for [`Site::snapshot`], recorded in the `snap-args.toml` file of the snapshot
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Options {
+	/// Do not error out on nonexistent exclude paths
+	pub allow_nonexistent_exclude_paths: bool,
+
+	/// Do not warn about unexcluded CACHEDIR.TAG files
+	pub no_report_cachedir_tag: bool,
+
+	/// Do not warn about unexcluded files with the nodump attribute
+	pub no_report_nodump: bool,
+
+	/// Confirms `exclude.all_eacces` in the site config, which has no effect otherwise
+	pub confirm_exclude_all_eacces: bool,
+
+	/// Skip all mount points under the include roots, as with `exclude.one_file_system` in the
+	/// site config
+	pub one_file_system: bool,
+
+	/// Only log the changes that would be made, without creating the snapshot
+	pub dry_run: bool,
+
+	/// What to do about errors concerning single source paths, see [`Site::snapshot_with`] to
+	/// decide per error
+	pub on_error: ErrorAction,
+
+	/// Run [`Site::prescan`] once the `pre_snap` hook has run, passing the estimate to
+	/// [`Observer::on_estimate`]
+	#[serde(skip)]
+	pub prescan: bool,
+}
+
+/// What to do about an error concerning a single source path while creating a snapshot, i.e. an
+/// [`Error::Source`], [`Error::Permission`] or [`Error::Unsupported`]. Other errors always abort.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorAction {
+	/// Stop creating the snapshot, returning the error
+	#[default]
+	Abort,
+	/// Leave the path out of the snapshot, along with its children for directories
+	Skip,
+	/// Leave the path out of the snapshot as with `Skip`, recording it in the exclusion log along
+	/// with the error
+	Record,
+}
+
+/// Receives notifications while a snapshot is being created, see [`Site::snapshot_with`]. Closures
+/// deciding about errors are observers as well.
+pub trait Observer {
+	/// Decides what to do about an error concerning a single source path
+	fn on_error(&mut self, e: &Error) -> ErrorAction;
+
+	/// Called with the estimated size of the snapshot before any source path is stored, if
+	/// `options.prescan` is set
+	fn on_estimate(&mut self, _estimate: Estimate) {}
+
+	/// Called after each source path is stored in the snapshot
+	fn on_progress(&mut self, _progress: &Progress) {}
+
+	/// Called for each notable event, in the order they occur
+	fn on_event(&mut self, _event: &Event) {}
+}
+
+impl<F: FnMut(&Error) -> ErrorAction> Observer for F {
+	fn on_error(&mut self, e: &Error) -> ErrorAction {
+		self(e)
+	}
+}
+
+/// Running totals of the paths stored so far while creating a snapshot
+#[derive(Clone, Debug, Default)]
+pub struct Progress {
+	/// Number of source paths stored, of all file types
+	pub files: u64,
+	/// Total content size of the regular files read from the source dataset
+	pub bytes_read: u64,
+	/// Number of files stored as deduplicated ones
+	pub dedup_hits: u64,
+	/// Total size of the files stored as deduplicated ones
+	pub bytes_deduplicated: u64,
+}
+
+/// Estimated size of a snapshot, as counted by [`Site::prescan`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Estimate {
+	/// Number of source paths, of all file types
+	pub files: u64,
+	/// Total content size of the regular files
+	pub bytes: u64,
+}
+
+/// Version of the serialized form of [`Event`], to be increased on incompatible changes. New events
+/// and fields do not count as such, so consumers should ignore those they do not know.
+pub const EVENTS_VERSION: u32 = 1;
+
+/// Notable occurrences while creating a snapshot, see [`Observer::on_event`]. Serializes to a map
+/// with the event name under `event`, e.g. `{"event":"include_root_started","path":"/home"}`.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+	/// The snapshot is about to be created at `snapshot`, after the site has been validated
+	SnapshotStarted {
+		#[serde(serialize_with = "bytes::serialize_path")]
+		site: &'a Path,
+		#[serde(serialize_with = "bytes::serialize_path")]
+		snapshot: &'a Path,
+		dry_run: bool,
+	},
+	/// The include root `path` is about to be walked, as listed in the site's include paths
+	IncludeRootStarted {
+		#[serde(serialize_with = "bytes::serialize_path")]
+		path: &'a Path,
+	},
+	/// `path` has been left out of the snapshot along with its children, for `reason` as recorded
+	/// in the exclusion log
+	Excluded {
+		#[serde(serialize_with = "bytes::serialize_path")]
+		path: &'a Path,
+		reason: &'a str,
+	},
+	/// The regular file `path` of `size` bytes has been stored as a relative symlink to the
+	/// identical `preexisting` one in the repository
+	Deduplicated {
+		#[serde(serialize_with = "bytes::serialize_path")]
+		path: &'a Path,
+		#[serde(serialize_with = "bytes::serialize_path")]
+		preexisting: &'a Path,
+		size: u64,
+	},
+	/// Something that may need attention, without affecting the snapshot
+	Warning {
+		kind: WarningKind,
+		#[serde(serialize_with = "bytes::serialize_path_opt")]
+		path: Option<&'a Path>,
+		message: &'a str,
+	},
+	/// An error, concerning `path` where it is known, and what has been done about it. `abort`
+	/// errors end the snapshot.
+	Error {
+		#[serde(serialize_with = "bytes::serialize_path_opt")]
+		path: Option<&'a Path>,
+		message: String,
+		action: ErrorAction,
+	},
+	/// The snapshot has been completed, with the same `summary` as recorded in it
+	SnapshotFinished {
+		#[serde(serialize_with = "bytes::serialize_path")]
+		snapshot: &'a Path,
+		summary: &'a Summary,
+	},
+}
+
+/// Kinds of [`Event::Warning`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+	/// A valid CACHEDIR.TAG file that is not excluded via `exclude.cachedir_tag`
+	CachedirTag,
+	/// A file named CACHEDIR.TAG without the required signature
+	InvalidCachedirTag,
+	/// A file with the nodump attribute that is not excluded via `exclude.nodump`
+	Nodump,
+	/// The set of excluded paths differs significantly from that of the previous snapshot
+	ExclusionsChanged,
+}
+
+impl Site {
+	/// Creates the next snapshot of the site from its include paths, returning it along with its
+	/// summary. The hooks in the site config are run around it, except for dry runs, which return
+	/// the snapshot that would have been created.
+	pub fn snapshot(&self, options: &Options) -> Result<(Snapshot, Summary)> {
+		self.snapshot_with(options, &mut |_: &Error| options.on_error)
+	}
+
+	/// Quickly counts the paths under the include roots, along with the size of the regular files
+	/// among them, to estimate the progress of [`Site::snapshot`]. Only explicitly excluded paths
+	/// and, with `options.one_file_system`, other mounts are left out, and unreadable paths
+	/// are ignored.
+	pub fn prescan(&self, options: &Options) -> Result<Estimate> {
+		let excludes: HashSet<FileKey> = self
+			.get_excluded()?
+			.iter()
+			.filter_map(|path| FileKey::from_path(path).ok())
+			.collect();
+		let one_file_system = options.one_file_system || self.get_config()?.exclude.one_file_system;
+
+		// As in the snapshot walk, mounts are told apart by device and mount ID
+		let mount = |path: &Path| {
+			file::statx::get(path)
+				.and_then(|stx| file::statx::mount(&stx))
+				.ok()
+		};
+
+		let mut estimate = Estimate::default();
+		for include_root in self.get_included()? {
+			let root_mount = if one_file_system {
+				mount(&include_root)
+			} else {
+				None
+			};
+			let entries = walkdir::WalkDir::new(include_root)
+				.into_iter()
+				.filter_entry(|dir_entry| {
+					dir_entry.metadata().map_or(true, |md| {
+						!excludes.contains(&FileKey {
+							dev: md.dev(),
+							ino: md.ino(),
+						})
+					}) && (root_mount.is_none() || mount(dir_entry.path()) == root_mount)
+				});
+			for entry in entries.filter_map(|entry| entry.ok()) {
+				estimate.files += 1;
+				if entry.file_type().is_file() {
+					estimate.bytes += entry.metadata().map_or(0, |md| md.len());
+				}
+			}
+		}
+		Ok(estimate)
+	}
+
+	/// Creates a snapshot as with [`Site::snapshot`], letting `observer` decide what to do about
+	/// each error concerning a single source path, rather than using `options.on_error`, and
+	/// notifying it of the progress
+	pub fn snapshot_with(
+		&self,
+		options: &Options,
+		observer: &mut impl Observer,
+	) -> Result<(Snapshot, Summary)> {
+		self.repo().check_writable()?;
+
+		let hooks = self.get_config()?.hooks;
+		let snap_path = self.next_snapshot_path()?;
+		let run = |name, command: &Option<String>, error: Option<&Error>| match command {
+			None => Ok(()),
+			Some(_) if options.dry_run => {
+				info!("(fake) running {name} hook");
+				Ok(())
+			}
+			Some(command) => run_hook(name, command, &self.0, &snap_path, error),
+		};
+
+		// Before validating the include paths, as the hook may be what makes them available, e.g.
+		// by mounting a filesystem snapshot
+		let result = run(HOOK_PRE_SNAP, &hooks.pre_snap, None)
+			.and_then(|()| {
+				if options.prescan {
+					observer.on_estimate(self.prescan(options)?);
+				}
+				self.create_snapshot(options, observer, snap_path.clone())
+			})
+			.and_then(|created| {
+				run(HOOK_POST_SNAP, &hooks.post_snap, None)?;
+				Ok(created)
+			});
+		if let Err(e) = &result {
+			// The original error is the more important one to return
+			if let Err(hook_error) = run(HOOK_ON_FAILURE, &hooks.on_failure, Some(e)) {
+				warn!("{hook_error}");
+			}
+		}
+		result
+	}
+
+	fn create_snapshot(
+		&self,
+		options: &Options,
+		observer: &mut impl Observer,
+		snap_path: PathBuf,
+	) -> Result<(Snapshot, Summary)> {
+		let mut summary = Summary::new(Utc::now());
+		let wall_clock = Instant::now();
+
+		let includes = {
+			let result = self.get_included()?;
+			if result.is_empty() {
+				return Err(Error::Config {
+					path: self.0.join(INCLUDES_NAME),
+					reason: format!(
+						"no paths have been included, run `baktu nsv-add-to {INCLUDES_NAME} <PATH>` \
+						first"
+					),
+				});
+			}
+
+			let nonexistent: Vec<_> = result.iter().filter(|path| !path.exists()).collect();
+			if let Some(first) = nonexistent.first() {
+				// Log them all, so we don't have to do an edit-rerun loop in case of multiple
+				// nonexistent ones
+				for p in &nonexistent {
+					log::error!("included path {p:?} doesn't exist")
+				}
+				return Err(Error::Config {
+					path: first.to_path_buf(),
+					reason: format!("included in {INCLUDES_NAME}, but does not exist"),
+				});
+			}
+			result
+		};
+
+		// TODO: (S) decide how to handle exclude paths outside of srcRoot, e.g. error out + add an
+		// allow flag
+		let excludes: HashSet<FileKey> = {
+			let seq = self.get_excluded()?;
+			let nonexistent: Vec<_> = seq.iter().filter(|path| !path.exists()).collect();
+			if let Some(first) = nonexistent.first() {
+				// Log them all, so we don't have to do an edit-rerun loop in case of multiple
+				// nonexistent ones
+				for p in &nonexistent {
+					log::error!("excluded path {p:?} doesn't exist")
+				}
+				return Err(Error::Config {
+					path: first.to_path_buf(),
+					reason: format!("excluded in {EXCLUDES_NAME}, but does not exist"),
+				});
+			}
+			seq.into_iter()
+				.map(|path| {
+					FileKey::from_path(&path).map_err(|source| Error::Source { path, source })
+				})
+				.collect::<Result<_>>()?
+		};
+
+		let site_conf = self.get_config()?;
+
+		// Read before creating the snapshot, so a missing or malformed key is noticed early
+		let signing_key = site_conf.signing_key()?;
+
+		// Shared between the is_included lambda and the rest of the main loop
+		let exclusions: RefCell<Vec<Exclusion>> = RefCell::new(Vec::new());
+		let observer = RefCell::new(observer);
+		let emit = |event: Event| observer.borrow_mut().on_event(&event);
+		let exclude_path = |path: &Path, reason: String| {
+			info!("excluding {path:?} due to {reason}");
+			emit(Event::Excluded {
+				path,
+				reason: &reason,
+			});
+			exclusions.borrow_mut().push(Exclusion {
+				path: path.to_path_buf(),
+				reason,
+			});
+		};
+		// Observers get events before they are logged, e.g. to make room for the message
+		let warning = |kind: WarningKind, path: Option<&Path>, message: String| {
+			emit(Event::Warning {
+				kind,
+				path,
+				message: &message,
+			});
+			warn!("{message}");
+		};
+
+		// Reference point for the mtime-based exclusion predicates
+		let snap_start_time = SystemTime::now();
+
+		// Filesystem types by mount ID, to avoid a statfs() call per path
+		let mut mnt_fs_types: HashMap<u64, Option<FsType>> = HashMap::new();
+
+		let one_file_system = options.one_file_system || site_conf.exclude.one_file_system;
+
+		// Mount of the include root currently being walked, with one_file_system
+		let mut include_root_mount: Option<(u64, u64)> = None;
+
+		let mut is_included = |dir_entry: &DirEntry| -> Result<bool> {
+			trace!("testing is_included({:?})", &dir_entry);
+			let exclude = |reason: String| -> bool {
+				exclude_path(dir_entry.path(), reason);
+				false
+			};
+
+			let exclude_all_eacces_or_fail = |denied_action: &str| -> Result<bool> {
+				if site_conf.exclude.all_eacces && options.confirm_exclude_all_eacces {
+					Ok(exclude(
+						config_file::NAME.to_owned()
+							+ &format!("/exclude.all_eacces ({denied_action})"),
+					))
+				} else {
+					Err(Error::Permission {
+						path: dir_entry.path().to_path_buf(),
+						action: denied_action.to_owned(),
+					})
+				}
+			};
+
+			let stx = match file::statx::get(dir_entry.path()) {
+				Ok(res) => res,
+				Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+					return exclude_all_eacces_or_fail("statx");
+				}
+				Err(source) => {
+					return Err(Error::Source {
+						path: dir_entry.path().to_path_buf(),
+						source,
+					})
+				}
+			};
+
+			// TODO: (S) switch to lexical canonicalization instead:
+			//   1. Fixes hard link aliasing issue
+			//   2. May save us an extra system call in some situations
+			let fk = FileKey::from_statx(&stx).map_err(|e| Error::Unsupported {
+				path: dir_entry.path().to_path_buf(),
+				reason: e.to_string(),
+			})?;
+			let mount = if one_file_system {
+				Some(file::statx::mount(&stx).map_err(|e| Error::Unsupported {
+					path: dir_entry.path().to_path_buf(),
+					reason: e.to_string(),
+				})?)
+			} else {
+				None
+			};
+
+			// TODO: (C) consider flattening the decision tree to improve readability, if we can do
+			// so without increasing the risk of bugs too much
+			// The include root is the first entry walkdir yields for each walk
+			if dir_entry.depth() == 0 {
+				include_root_mount = mount;
+			}
+
+			Ok(if excludes.contains(&fk) {
+				exclude(EXCLUDES_NAME.to_owned())
+			} else if mount.is_some() && mount != include_root_mount {
+				exclude(if options.one_file_system {
+					"--one-file-system (mount point)".to_owned()
+				} else {
+					config_file::NAME.to_owned() + "/exclude.one_file_system (mount point)"
+				})
+			} else {
+				// obeying clippy's lint here would result in less obvious code structure
+				#[allow(clippy::collapsible_else_if)]
+				if site_conf.exclude.cachedir_tag
+					&& dir_entry.file_type().is_dir()
+					&& dir_entry.path().join("CACHEDIR.TAG").exists()
+					&& file::is_valid_cachedir_tag(dir_entry.path().join("CACHEDIR.TAG").as_path())
+				{
+					exclude(config_file::NAME.to_owned() + "/exclude.cachedir_tag")
+				} else {
+					if site_conf.exclude.nodump
+						&& stx.stx_attributes_mask & libc::STATX_ATTR_NODUMP as u64 != 0
+						&& stx.stx_attributes & libc::STATX_ATTR_NODUMP as u64 != 0
+					{
+						exclude(config_file::NAME.to_owned() + "/exclude.nodump")
+					} else if let Some(reason) = predicate_exclude_reason(
+						&site_conf.exclude,
+						dir_entry.path(),
+						&stx,
+						snap_start_time,
+						&mut mnt_fs_types,
+					)? {
+						exclude(reason)
+					} else {
+						fn readable(p: &Path) -> bool {
+							/* TODO: (S) consider switching to nix, as AT_EACCESS is now supported
+								- issue: https://github.com/nix-rust/nix/pull/1995
+								- in since 0.27.0, see
+									https://github.com/nix-rust/nix/blob/master/CHANGELOG.md
+							// recommended in https://github.com/nix-rust/nix/issues/1340
+							const dirfd: libc::c_int = libc::AT_FDCWD;
+							nix::unistd::faccessat(
+								Some(dirfd),
+								p,
+								nix::unistd::AccessFlags::R_OK,
+								AT_EACCESS | nix::fcntl::AtFlags::AT_SYMLINK_NOFOLLOW).is_ok()
+							*/
+
+							// from https://docs.rs/faccess/0.2.4/src/faccess/lib.rs.html#92
+							// modified with AT_SYMLINK_NOFOLLOW
+							let path =
+								CString::new(p.as_os_str().as_bytes()).expect("p can't contain 0");
+
+							unsafe {
+								faccessat(
+									libc::AT_FDCWD,
+									path.as_ptr() as *const c_char,
+									libc::R_OK,
+									libc::AT_EACCESS | libc::AT_SYMLINK_NOFOLLOW,
+								) == 0
+							}
+						}
+
+						if !readable(dir_entry.path()) {
+							exclude_all_eacces_or_fail("faccessat(READ)")?
+						} else {
+							true
+						}
+					}
+				}
+			})
+		};
+
+		// Returns the error if the snapshot is to be aborted, shared between the filter and the
+		// main loop
+		let handle_error = |e: Error| -> Result<()> {
+			if !matches!(
+				e,
+				Error::Source { .. } | Error::Permission { .. } | Error::Unsupported { .. }
+			) {
+				return Err(e);
+			}
+			let action = observer.borrow_mut().on_error(&e);
+			emit(Event::Error {
+				path: e.path(),
+				message: e.to_string(),
+				action,
+			});
+			match action {
+				ErrorAction::Abort => Err(e),
+				ErrorAction::Skip => {
+					warn!("{e}, skipping");
+					Ok(())
+				}
+				ErrorAction::Record => {
+					warn!(
+						"{e}, skipping and recording it in {}",
+						snapshot::EXCLUDED_FNAME
+					);
+					exclude_path(
+						e.path().expect("source path errors have paths"),
+						format!("error ({e})"),
+					);
+					Ok(())
+				}
+			}
+		};
+
+		// walkdir filters can not return errors, so the first one aborting the snapshot is kept
+		// here and returned from the main loop
+		let filter_error: RefCell<Option<Error>> = RefCell::new(None);
+		let mut filter = |dir_entry: &DirEntry| match is_included(dir_entry) {
+			Ok(included) => included,
+			Err(e) => {
+				if let Err(e) = handle_error(e) {
+					filter_error.borrow_mut().get_or_insert(e);
+				}
+				false
+			}
+		};
+
+		let mut xattr_helper = file::xattrs::Helper::init_opt()?;
+
+		let prev_snap = self.snapshots_sorted()?.pop();
+
+		emit(Event::SnapshotStarted {
+			site: &self.0,
+			snapshot: &snap_path,
+			dry_run: options.dry_run,
+		});
+
+		// TODO: (S) abstract over run dryness, so we end up with a single `if options.dry_run`
+		if options.dry_run {
+			info!("(fake) creating snapshot dir {snap_path:?}");
+		} else {
+			info!("creating snapshot dir {snap_path:?}");
+			fs::create_dir(&snap_path)?;
+		}
+
+		let site_config_dir = snap_path.join(snapshot::SITE_CONFIG_DIR_NAME);
+		if options.dry_run {
+			info!("(fake) recording site config and snap arguments in {site_config_dir:?}");
+		} else {
+			info!("recording site config and snap arguments in {site_config_dir:?}");
+			fs::create_dir(&site_config_dir)?;
+			for name in repo::site::CONFIG_FILE_NAMES {
+				fs::copy(self.0.join(name), site_config_dir.join(name))?;
+			}
+			fs::write(
+				site_config_dir.join(snapshot::SNAP_ARGS_FNAME),
+				toml::to_string(options).map_err(io::Error::other)?,
+			)?;
+		}
+
+		// TODO: (S) strongly consider converting to Snapshot type earlier
+		// TODO: (S) smaller-scope: get rid of unnecessary clone
+		let snap_data_path = Snapshot::from_dryable(snap_path.clone(), options.dry_run)?.data_dir();
+		if options.dry_run {
+			info!("(fake) creating snapshot data dir {snap_data_path:?}");
+		} else {
+			info!("creating snapshot data dir {snap_data_path:?}");
+			fs::create_dir(&snap_data_path)?;
+		}
+
+		let meta_name: OsString = {
+			// FIXME: should find a unique META_NAME given the current input file name set, so we
+			// never get collisions. Adjust accordingly if we start supporting things that might
+			// break the invariants, for example incremental snapshotting.
+			snapshot::DEFAULT_META_NAME.into()
+		};
+		let meta_name_fpath = snap_path.join(snapshot::META_NAME_FNAME);
+		if options.dry_run {
+			info!("(fake) writing metadata file name to {meta_name_fpath:?}");
+		} else {
+			info!("writing metadata file name to {meta_name_fpath:?}");
+			std::fs::OpenOptions::new()
+				.create_new(true)
+				.write(true)
+				.open(meta_name_fpath)?
+				.write_all(meta_name.as_bytes())?;
+		}
+
+		// TODO: (S) consider the trade-offs of doing a lazy initialization here, especially if we
+		// switch to duplicate detection mechanisms that require slower initialization. We'll need
+		// this "only" when we have to process regular files, so in the spirit of KISS we can
+		// consider that to always be the case, with rare and unusual exceptions.
+
+		let mut dedup_index = repo::dedup::Index::load(&self.repo())?;
+
+		let mut progress = Progress::default();
+
+		// FIXME: handle roots with the same basename appropriately
+		for include_root in includes {
+			info!("processing include_root {include_root:?}");
+			emit(Event::IncludeRootStarted {
+				path: &include_root,
+			});
+			let include_root = match fs::canonicalize(&include_root).reading(&include_root) {
+				Ok(resolved) => {
+					if resolved != include_root {
+						info!("\twhich resolves to {resolved:?}");
+					}
+					resolved
+				}
+				Err(e) => {
+					handle_error(e)?;
+					continue;
+				}
+			};
+
+			let dst_inc_root_path = snap_data_path.join(
+				include_root
+					.file_name()
+					.expect("canonicalized path can not contain trailing .."),
+			);
+
+			// TODO: (C) improve performance by DIYing the walking, since for each dentry:
+			//   - WalkDir has an fd
+			//   - WalkDir does a statx
+			//   - we do a statx to generate the FileKey
+			//   - we later do a statx again (up to and including DIOALIGN)
+			//   - we'll need an fd for FS_IOC_GETFLAGS
+			let mut entries = walkdir::WalkDir::new(&include_root)
+				.sort_by_file_name()
+				.into_iter()
+				.filter_entry(&mut filter);
+			while let Some(entry) = entries.next() {
+				if let Some(e) = filter_error.borrow_mut().take() {
+					return Err(e);
+				}
+
+				let entry = match entry {
+					Ok(entry) => entry,
+					// e.g. a directory that can not be read, which is already in the snapshot itself
+					Err(e) => {
+						match e.path().map(Path::to_path_buf) {
+							Some(path) => handle_error(Error::Source {
+								path,
+								source: e.into(),
+							})?,
+							None => return Err(e.into()),
+						}
+						continue;
+					}
+				};
+
+				let path = entry.path();
+				debug!("processing path {path:?}");
+
+				if !options.no_report_cachedir_tag
+					&& entry.file_type().is_file()
+					&& entry.file_name() == "CACHEDIR.TAG"
+				{
+					if file::is_valid_cachedir_tag(entry.path()) {
+						warning(
+							WarningKind::CachedirTag,
+							Some(entry.path()),
+							format!(
+								"Found valid and unexcluded CACHEDIR.TAG at {:?}. Rerun with \
+								--no-report-cachedir-tag or enable 'exclude.cachedir_tag' in the \
+								site '{}' to hide this warning.",
+								entry.path(),
+								config_file::NAME
+							),
+						);
+					} else {
+						warning(
+							WarningKind::InvalidCachedirTag,
+							Some(entry.path()),
+							format!(
+								"Found invalid CACHEDIR.TAG at {:?}. Rerun with \
+								--no-report-cachedir-tag to hide this warning.",
+								entry.path()
+							),
+						);
+					}
+				}
+
+				// Everything about the source path is read before anything is written, so skipping
+				// it on errors leaves no trace in the snapshot
+				let read = (|| -> Result<_> {
+					// This should stay even after we switch to finding a meta_name that doesn't
+					// occur in the source file set, so we can fail early and loudly in TOCTOU
+					// situations, instead of risking data corruption
+					if entry.file_type().is_dir() && path.join(&meta_name).exists() {
+						return Err(Error::Unsupported {
+							path: path.to_path_buf(),
+							reason: format!("already contains a file named {meta_name:?}"),
+						});
+					}
+
+					let stx = file::statx::get(path).reading(path)?;
+					require_statx(path, &stx, libc::STATX_TYPE, "type")?;
+					require_statx(path, &stx, libc::STATX_MODE, "mode")?;
+
+					let hash = match stx.stx_mode as u32 & libc::S_IFMT == libc::S_IFREG {
+						true => Some(file::b3sum(path).reading(path)?),
+						false => None,
+					};
+
+					// Only set if we deduplicate the file, thus not for files too small to be
+					// deduplicated, even if there are others with the same content
+					let preexisting_path = match hash {
+						Some(hash) if stx.stx_size >= repo::dedup::MIN_FILE_SIZE => {
+							dedup_index.find(hash, path)?
+						}
+						_ => None,
+					};
+
+					let meta = read_meta(
+						&mut xattr_helper,
+						path,
+						stx,
+						hash,
+						preexisting_path.is_some(),
+						false,
+					)?;
+
+					Ok((stx, hash, preexisting_path, meta))
+				})();
+				let (stx, hash, preexisting_path, meta) = match read {
+					Ok(read) => read,
+					Err(e) => {
+						handle_error(e)?;
+						if entry.file_type().is_dir() {
+							entries.skip_current_dir();
+						}
+						continue;
+					}
+				};
+
+				if !options.no_report_nodump
+					&& stx.stx_attributes_mask & libc::STATX_ATTR_NODUMP as u64 != 0
+					&& stx.stx_attributes & libc::STATX_ATTR_NODUMP as u64 != 0
+				{
+					warning(
+						WarningKind::Nodump,
+						Some(entry.path()),
+						format!(
+							"{:?} is marked as nodump, but not excluded. Rerun with \
+							--no-report-nodump or enable 'exclude.nodump' in the site '{}' to hide \
+							this warning.",
+							entry.path(),
+							config_file::NAME
+						),
+					);
+				}
+
+				let root_rel_path = path.strip_prefix(&include_root).map_err(io::Error::other)?;
+				let dst_path = if root_rel_path == Path::new("") {
+					// include root is a file, not a directory
+					dst_inc_root_path.clone()
+				} else {
+					dst_inc_root_path.join(root_rel_path)
+				};
+
+				debug!("creating at destination {dst_path:?}");
+				// Note that initially we're only focusing on recreating the non-meta state of the
+				// file, as all meta-information should be recorded in the meta dump afterwards.
+				// However recreating more of the meta state (permissions, etc) is a Could, or
+				// ideally even a Should task for later, as this would provide more of the source
+				// state at later stages of the repo's graceful degradation.
+				// The type and mode were required while reading the source path, and regular files
+				// hashed
+				match (stx.stx_mode as u32 & libc::S_IFMT, hash) {
+					(libc::S_IFDIR, _) => {
+						if options.dry_run {
+							info!("(fake) mkdir {dst_path:?}")
+						} else {
+							fs::create_dir(&dst_path).at(&dst_path)?
+						}
+					}
+					(libc::S_IFREG, Some(hash)) => {
+						summary.bytes_read += stx.stx_size;
+						match preexisting_path {
+							Some(preexisting_path) => {
+								summary.bytes_deduplicated += stx.stx_size;
+								progress.dedup_hits += 1;
+								emit(Event::Deduplicated {
+									path,
+									preexisting: &preexisting_path,
+									size: stx.stx_size,
+								});
+								if options.dry_run {
+									info!(
+										"(fake) dedup src={path:?} \
+										dst={dst_path:?} preexisting={preexisting_path:?}"
+									);
+								} else {
+									repo::dedup::link(&preexisting_path, &dst_path)
+										.at(&dst_path)?;
+								}
+							}
+							None => {
+								summary.bytes_written += if options.dry_run {
+									info!("(fake) cp {path:?} {dst_path:?}");
+									stx.stx_size
+								} else {
+									fs::copy(path, &dst_path).at(&dst_path)?
+								};
+
+								if stx.stx_size < repo::dedup::MIN_FILE_SIZE {
+									debug!(
+										"skipping deduplication of file smaller than {} bytes",
+										repo::dedup::MIN_FILE_SIZE
+									);
+								} else {
+									// We use the source path when we're "creating" a dry-run
+									// snapshot, so there's something to compare for subsequent
+									// dedup byte-by-byte checks
+									let backing_path = if options.dry_run {
+										path.to_path_buf()
+									} else {
+										dst_path.clone()
+									};
+
+									trace!("new data, adding to index: {hash:?} {backing_path:?}");
+									if dedup_index.insert(hash, backing_path) {
+										summary.new_unique_hashes += 1;
+									}
+								}
+							}
+						}
+					}
+					(libc::S_IFLNK, _) => {
+						if options.dry_run {
+							info!("(fake) ln -s {dst_path:?}")
+						} else {
+							// TODO: (M) ensure this preserves the symlink as is
+							let target = fs::read_link(path).reading(path)?;
+							std::os::unix::fs::symlink(target, &dst_path).at(&dst_path)?
+						}
+					}
+					(libc::S_IFBLK | libc::S_IFCHR | libc::S_IFIFO | libc::S_IFSOCK, _)
+						if options.dry_run =>
+					{
+						info!("(fake) mknod {dst_path:?}")
+					}
+					(libc::S_IFBLK | libc::S_IFCHR | libc::S_IFIFO | libc::S_IFSOCK, _) => {
+						// S_IFMT bit twiddling from
+						// https://man7.org/linux/man-pages/man2/statx.2.html
+						let (Some(kind), Some(perm)) = (
+							SFlag::from_bits(stx.stx_mode as u32 & libc::S_IFMT),
+							Mode::from_bits(stx.stx_mode as u32 & !libc::S_IFMT),
+						) else {
+							handle_error(Error::Unsupported {
+								path: path.to_path_buf(),
+								reason: format!("unknown mode {:o}", stx.stx_mode),
+							})?;
+							continue;
+						};
+
+						// dev ignored if not CHR/BLK, according to
+						// https://man7.org/linux/man-pages/man2/mknod.2.html
+						match mknod(
+							&dst_path,
+							kind,
+							perm,
+							libc::makedev(stx.stx_rdev_major, stx.stx_rdev_minor),
+						) {
+							Ok(_) => (),
+							Err(nix::errno::Errno::EPERM) => {
+								// TODO: (M) DRY the exclude/exit logic here
+								if site_conf.exclude.all_eacces
+									&& options.confirm_exclude_all_eacces
+								{
+									exclude_path(
+										entry.path(),
+										config_file::NAME.to_owned()
+											+ "/exclude.all_eacces (mknod)",
+									);
+								} else {
+									handle_error(Error::Permission {
+										path: entry.path().to_path_buf(),
+										action: "mknod".to_owned(),
+									})?;
+								}
+								continue;
+							}
+							Err(e) => return Err(io::Error::from(e)).at(&dst_path),
+						};
+					}
+					(ft, _) => {
+						handle_error(Error::Unsupported {
+							path: path.to_path_buf(),
+							reason: format!("unknown file type {ft:o}"),
+						})?;
+						continue;
+					}
+				}
+
+				get_meta_sink(options.dry_run, &dst_path, &meta_name)?.write_all(&meta)?;
+
+				if let Some(ft) = FileType::from_mode(stx.stx_mode as u32) {
+					*summary.files.entry(ft.name().to_owned()).or_default() += 1;
+				}
+
+				progress.files += 1;
+				progress.bytes_read = summary.bytes_read;
+				progress.bytes_deduplicated = summary.bytes_deduplicated;
+				observer.borrow_mut().on_progress(&progress);
+			}
+
+			if let Some(e) = filter_error.borrow_mut().take() {
+				return Err(e);
+			}
+		}
+
+		let exclusions = exclusions.into_inner();
+		for exclusion in &exclusions {
+			// Group by reason, ignoring details such as the denied action or file type
+			let reason = exclusion
+				.reason
+				.split_once(" (")
+				.map_or(exclusion.reason.as_str(), |(reason, _details)| reason);
+			*summary.exclusions.entry(reason.to_owned()).or_default() += 1;
+		}
+		info!(
+			"{} files excluded (not counting children), {} files processed",
+			summary.exclusions_total(),
+			summary.files_total()
+		);
+
+		let excluded_fpath = snap_path.join(snapshot::EXCLUDED_FNAME);
+		if options.dry_run {
+			info!("(fake) writing exclusion log to {excluded_fpath:?}");
+		} else {
+			info!("writing exclusion log to {excluded_fpath:?}");
+			snapshot::write_exclusions(excluded_fpath, &exclusions)?;
+		}
+
+		if let Some(prev_snap) = prev_snap {
+			match prev_snap.exclusions()? {
+				Some(prev_exclusions) => {
+					if let Some(message) = exclusions_change_warning(&prev_exclusions, &exclusions)
+					{
+						warning(WarningKind::ExclusionsChanged, None, message);
+					}
+				}
+				None => debug!("no exclusion log in {prev_snap:?}, skipping comparison"),
+			}
+		}
+
+		let snap = Snapshot(snap_path);
+		if options.dry_run {
+			info!("(fake) computing metadata digests and root hash");
+		} else {
+			let root = repo::merkle::seal(&snap)?;
+			info!("snapshot root hash: {}", root.to_hex());
+		}
+
+		summary.end_time = Utc::now();
+		summary.wall_time_secs = wall_clock.elapsed().as_secs_f64();
+
+		let summary_fpath = snap.0.join(repo::summary::FNAME);
+		if options.dry_run {
+			info!("(fake) writing summary to {summary_fpath:?}");
+		} else {
+			info!("writing summary to {summary_fpath:?}");
+			fs::write(summary_fpath, summary.to_toml().map_err(io::Error::other)?)?;
+		}
+
+		if let Some(key) = signing_key {
+			if options.dry_run {
+				info!("(fake) signing snapshot");
+			} else {
+				info!("signing snapshot");
+				repo::signature::sign(&snap, &key)?;
+			}
+		}
+
+		emit(Event::SnapshotFinished {
+			snapshot: &snap.0,
+			summary: &summary,
+		});
+
+		// xattr_helper: no need for explicit cleanup, as it will automatically have its stdin
+		// closed, and will exit normally
+
+		Ok((snap, summary))
+	}
+}
+
+const HOOK_PRE_SNAP: &str = "pre_snap";
+const HOOK_POST_SNAP: &str = "post_snap";
+const HOOK_ON_FAILURE: &str = "on_failure";
+
+/// Runs the `name` hook `command` via `sh -c` in the `site` directory, describing the snapshot via
+/// environment variables. The hook's stdout goes to stderr, keeping stdout free for baktu's output.
+fn run_hook(
+	name: &str,
+	command: &str,
+	site: &Path,
+	snapshot: &Path,
+	error: Option<&Error>,
+) -> Result<()> {
+	info!("running {name} hook: {command}");
+	let status = match (name, error) {
+		(HOOK_PRE_SNAP, _) => "pending",
+		(_, None) => "success",
+		(_, Some(_)) => "failure",
+	};
+	let mut cmd = Command::new("sh");
+	cmd.arg("-c")
+		.arg(command)
+		.current_dir(site)
+		.stdin(Stdio::null())
+		.stdout(io::stderr())
+		.env("BAKTU_HOOK", name)
+		.env("BAKTU_SITE", site)
+		.env("BAKTU_SNAPSHOT", snapshot)
+		.env("BAKTU_STATUS", status);
+	if let Some(e) = error {
+		cmd.env("BAKTU_ERROR", e.to_string());
+	}
+	let exit_status = cmd.status().map_err(|e| Error::Hook {
+		name: name.to_owned(),
+		reason: format!("unable to run sh: {e}"),
+	})?;
+	match exit_status.success() {
+		true => Ok(()),
+		false => Err(Error::Hook {
+			name: name.to_owned(),
+			reason: exit_status.to_string(),
+		}),
+	}
+}
+
+/// Returns a warning if the set of excluded paths differs significantly from the previous
+/// snapshot's, logging the differing paths at info level
+fn exclusions_change_warning(prev: &[Exclusion], cur: &[Exclusion]) -> Option<String> {
+	// Fraction of the larger exclusion set that needs to change to trigger the warning
+	const CHANGE_WARN_RATIO: f64 = 0.1;
+
+	let prev: HashSet<&Path> = prev.iter().map(|e| e.path.as_path()).collect();
+	let cur: HashSet<&Path> = cur.iter().map(|e| e.path.as_path()).collect();
+
+	let newly_excluded: Vec<_> = cur.difference(&prev).collect();
+	let no_longer_excluded: Vec<_> = prev.difference(&cur).collect();
+
+	for path in &newly_excluded {
+		info!("newly excluded since the previous snapshot: {path:?}");
+	}
+	for path in &no_longer_excluded {
+		info!("no longer excluded since the previous snapshot: {path:?}");
+	}
+
+	let changed = newly_excluded.len() + no_longer_excluded.len();
+	(changed > 0 && changed as f64 > CHANGE_WARN_RATIO * prev.len().max(cur.len()) as f64).then(
+		|| {
+			format!(
+				"The set of excluded paths differs significantly from the previous snapshot: {} \
+				newly excluded, {} no longer excluded. Rerun with `-v` to list them.",
+				newly_excluded.len(),
+				no_longer_excluded.len()
+			)
+		},
+	)
+}
+
+/// Fails with [`Error::Unsupported`] for `path` unless `statx()` returned the `flag` field
+fn require_statx(path: &Path, stx: &libc::statx, flag: u32, field: &str) -> Result<()> {
+	file::statx::require(stx, flag, field).map_err(|e| Error::Unsupported {
+		path: path.to_path_buf(),
+		reason: e.to_string(),
+	})
+}
+
+/// Returns the reason for excluding `path` if it matches any of the size, age, type and filesystem
+/// type predicates in the site config
+fn predicate_exclude_reason(
+	cfg: &ExcludeCfg,
+	path: &Path,
+	stx: &libc::statx,
+	now: SystemTime,
+	mnt_fs_types: &mut HashMap<u64, Option<FsType>>,
+) -> Result<Option<String>> {
+	let cfg_key = |key: &str| format!("{}/exclude.{key}", config_file::NAME);
+
+	require_statx(path, stx, libc::STATX_TYPE, "type")?;
+	let file_type = FileType::from_mode(stx.stx_mode as u32);
+
+	if let Some(ft) = file_type.filter(|ft| cfg.types.contains(ft)) {
+		return Ok(Some(format!("{} ({})", cfg_key("types"), ft.name())));
+	}
+
+	if !cfg.fs_types.is_empty() {
+		require_statx(path, stx, libc::STATX_MNT_ID, "mnt_id")?;
+		let fs_type = match mnt_fs_types.get(&stx.stx_mnt_id) {
+			Some(fs_type) => *fs_type,
+			None => {
+				// statfs() follows symlinks, but a symlink is always on the same mount as its
+				// parent directory
+				let fs_type = if file_type == Some(FileType::Lnk) {
+					FsType::of(path.parent().unwrap_or(path))
+				} else {
+					FsType::of(path)
+				}
+				.reading(path)?;
+				mnt_fs_types.insert(stx.stx_mnt_id, fs_type);
+				fs_type
+			}
+		};
+		if let Some(fs_type) = fs_type.filter(|t| cfg.fs_types.contains(t)) {
+			return Ok(Some(format!(
+				"{} ({})",
+				cfg_key("fs_types"),
+				fs_type.name()
+			)));
+		}
+	}
+
+	if let Some(max_size) = cfg.max_size {
+		require_statx(path, stx, libc::STATX_SIZE, "size")?;
+		if file_type == Some(FileType::Reg) && stx.stx_size > max_size {
+			return Ok(Some(format!(
+				"{} ({} > {max_size} bytes)",
+				cfg_key("max_size"),
+				stx.stx_size
+			)));
+		}
+	}
+
+	// Directories are exempt, as their mtime says little about that of their children
+	if file_type != Some(FileType::Dir) {
+		require_statx(path, stx, libc::STATX_MTIME, "mtime")?;
+		// Files with an mtime in the future are considered to have an age of zero
+		let age = now
+			.duration_since(file::statx::to_system_time(stx.stx_mtime))
+			.unwrap_or_default();
+
+		if let Some(max_age) = cfg.mtime_older_than.filter(|max_age| age > *max_age) {
+			return Ok(Some(format!(
+				"{} ({})",
+				cfg_key("mtime_older_than"),
+				humantime::format_duration(max_age)
+			)));
+		}
+
+		if let Some(min_age) = cfg.mtime_newer_than.filter(|min_age| age < *min_age) {
+			return Ok(Some(format!(
+				"{} ({})",
+				cfg_key("mtime_newer_than"),
+				humantime::format_duration(min_age)
+			)));
+		}
+	}
+
+	Ok(None)
+}
+
+pub(crate) fn get_meta_sink(
+	dry_run: bool,
+	dst_path: &Path,
+	meta_name: &OsString,
+) -> io::Result<Box<dyn Write>> {
+	if dry_run {
+		if log::max_level() >= LevelFilter::Debug {
+			debug!("dumping meta to stdout");
+			Ok(Box::new(stdout()))
+		} else {
+			Ok(Box::new(io::sink()))
+		}
+	} else {
+		// Can be optimized to be calculated once per entering a directory, at the trade-off
+		// of keeping mutable context between loop iterations due to the current flattened
+		// mode of walking the tree
+		let meta_path = dst_path
+			.parent()
+			.expect("parent must exist given definition of dst_path")
+			.join(meta_name);
+		debug!("dumping metadata to {meta_path:?}");
+		Ok(Box::new(
+			std::fs::OpenOptions::new()
+				.append(true)
+				.create(true)
+				.open(meta_path)?,
+		))
+	}
+}
+
+/// Writes the metadata record of `path` to `sink`, see [`read_meta`]
+pub(crate) fn dump_meta(
+	xattr_helper: &mut Option<file::xattrs::Helper>,
+	mut sink: impl Write,
+	path: &Path,
+	stx: libc::statx,
+	hash: Option<blake3::Hash>,
+	is_deduplicated: bool,
+	is_approximate: bool,
+) -> Result<()> {
+	let meta = read_meta(
+		xattr_helper,
+		path,
+		stx,
+		hash,
+		is_deduplicated,
+		is_approximate,
+	)?;
+	Ok(sink.write_all(&meta)?)
+}
+
+/// Returns the metadata record of `path`, reading its flags and extended attributes in addition to
+/// `stx`
+pub(crate) fn read_meta(
+	xattr_helper: &mut Option<file::xattrs::Helper>,
+	path: &Path,
+	stx: libc::statx,
+	hash: Option<blake3::Hash>,
+	is_deduplicated: bool,
+	is_approximate: bool,
+) -> Result<Vec<u8>> {
+	let name = path
+		.file_name()
+		.expect("has last component, not ending in ..")
+		.as_bytes();
+	let mut rec = MetaRecord::from_statx(name, &stx).map_err(|e| Error::Unsupported {
+		path: path.to_path_buf(),
+		reason: e.to_string(),
+	})?;
+	rec.is_deduplicated = is_deduplicated;
+	rec.is_approximate = is_approximate;
+	rec.b3sum = hash;
+
+	use libc::{S_IFBLK, S_IFCHR, S_IFIFO, S_IFLNK, S_IFSOCK};
+	rec.lsattr = match stx.stx_mode as u32 & libc::S_IFMT {
+		// FS_IOC_GETFLAGS not supported on char/block devices, see ioctl supported only
+		// for dirs and regular files, see also
+		// https://bugs.debian.org/cgi-bin/bugreport.cgi?bug=152029
+		S_IFCHR | S_IFBLK => None,
+		// Symlinks:
+		// - https://lore.kernel.org/linux-xfs/20171101235007.GF22894@wotan.suse.de/T/
+		// - getting a fd usable by the ioctl proves to be difficult
+		// Sockets fail too
+		S_IFSOCK | S_IFLNK => None,
+		// Causes hang on ~/.steam/steam.pipe
+		S_IFIFO => None,
+		// At the moment we emulate lsattr(1), and only handle regular files and
+		// directories
+		_otherwise => Some(file::ioctl_getflags::lsattr(path).reading(path)?),
+	};
+
+	rec.xattrs = file::xattrs::get(xattr_helper, path)?;
+
+	// Writes to a Vec can not fail
+	let mut sink = Vec::new();
+	rec.write(&mut sink)?;
+	Ok(sink)
+}
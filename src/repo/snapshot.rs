@@ -1,10 +1,55 @@
-use std::{ffi::OsStr, fs::File, io::Read, os::unix::prelude::OsStrExt, path::PathBuf};
+use std::{
+	ffi::{OsStr, OsString},
+	fs::File,
+	io::{self, Read},
+	os::unix::prelude::{OsStrExt, OsStringExt},
+	path::{Path, PathBuf},
+};
 
-use super::meta_file::MetaFile;
+use crate::{
+	error::{Error, Result},
+	file::FileType,
+	util::{dsv, nsv},
+};
+
+use super::{
+	meta_file::{MetaFile, MetaRecord},
+	site::Site,
+	summary::{self, Summary},
+};
 
 pub const META_NAME_FNAME: &str = "meta_name.cfg.bin";
+/// Name of the metadata files, unless it collides with the source dataset
+pub const DEFAULT_META_NAME: &str = ".baktu.meta.brj";
+pub const EXCLUDED_FNAME: &str = "excluded.nsv";
+pub const SITE_CONFIG_DIR_NAME: &str = "site-config";
+pub const SNAP_ARGS_FNAME: &str = "snap-args.toml";
 
+/// A source path excluded during snapshot creation, along with the reason for its exclusion
 #[derive(Debug)]
+pub struct Exclusion {
+	pub path: PathBuf,
+	pub reason: String,
+}
+
+/// Overwrites `file` with `exclusions`, as NSV entry pairs of path and reason
+pub fn write_exclusions<P: AsRef<Path>>(file: P, exclusions: &[Exclusion]) -> io::Result<()> {
+	dsv::vec_to_file(
+		file,
+		nsv::SEP,
+		exclusions
+			.iter()
+			.flat_map(|e| {
+				[
+					e.path.as_os_str().as_bytes().to_vec(),
+					e.reason.as_bytes().to_vec(),
+				]
+			})
+			.collect(),
+	)
+}
+
+#[derive(Clone, Debug)]
 pub struct Snapshot(pub PathBuf);
 
 impl Snapshot {
@@ -17,24 +62,213 @@ impl Snapshot {
 		}
 	}
 
-	pub fn meta_files(&self) -> std::io::Result<impl Iterator<Item = walkdir::Result<MetaFile>>> {
-		let name = {
-			let mut buffer = Vec::new();
-			File::open(self.0.join(META_NAME_FNAME))?.read_to_end(&mut buffer)?;
-			OsStr::from_bytes(&buffer).to_owned()
-		};
+	/// Returns the name used for the snapshot's metadata files
+	pub fn meta_name(&self) -> io::Result<OsString> {
+		let mut buffer = Vec::new();
+		File::open(self.0.join(META_NAME_FNAME))?.read_to_end(&mut buffer)?;
+		Ok(OsStr::from_bytes(&buffer).to_owned())
+	}
 
-		// TODO: (M) take care of unwrap() below
+	pub fn meta_files(&self) -> std::io::Result<impl Iterator<Item = Result<MetaFile>>> {
+		let name = self.meta_name()?;
+
+		// Errors are passed through, as we can not tell whether they concern a metadata file
 		Ok(walkdir::WalkDir::new(&self.0)
 			.sort_by_file_name()
 			.into_iter()
-			.filter(move |e| e.as_ref().unwrap().file_name() == name)
-			.map(|de_res| de_res.map(|de| MetaFile(de.path().to_path_buf()))))
+			.filter(move |e| e.as_ref().map_or(true, |de| de.file_name() == name))
+			.map(|de_res| {
+				de_res
+					.map(|de| MetaFile(de.path().to_path_buf()))
+					.map_err(Error::from)
+			}))
+	}
+
+	/// Returns all records in the snapshot, along with their paths relative to the data directory
+	pub fn records(&self) -> Result<impl Iterator<Item = Result<(PathBuf, MetaRecord)>>> {
+		let data_dir = self.data_dir();
+		Ok(self.meta_files()?.flat_map(move |meta_res| {
+			let dir_records = meta_res.and_then(|meta| {
+				let rel_dir = meta
+					.0
+					.parent()
+					.and_then(|dir| dir.strip_prefix(&data_dir).ok())
+					.ok_or_else(|| Error::Repo {
+						path: meta.0.clone(),
+						reason: format!("metadata file outside of {data_dir:?}"),
+					})?
+					.to_path_buf();
+				Ok(meta
+					.meta_records()?
+					.into_iter()
+					.map(move |rec| Ok((rel_dir.join(OsStr::from_bytes(&rec.name)), rec))))
+			});
+			match dir_records {
+				Ok(records) => records.collect::<Vec<_>>(),
+				Err(e) => vec![Err(e)],
+			}
+		}))
 	}
 
 	pub fn data_dir(&self) -> PathBuf {
 		self.0.join("data")
 	}
+
+	/// Directory containing copies of the site configuration files and the `baktu snap` arguments
+	/// in effect when the snapshot was created
+	pub fn site_config_dir(&self) -> PathBuf {
+		self.0.join(SITE_CONFIG_DIR_NAME)
+	}
+
+	/// Returns the records of the entries of `rel_dir`, a directory path relative to the data
+	/// directory
+	pub fn dir_records(&self, rel_dir: &Path) -> io::Result<Vec<MetaRecord>> {
+		let dir = self.data_dir().join(rel_dir);
+		match MetaFile(dir.join(self.meta_name()?)).meta_records() {
+			// Meta files are only created for directories with entries
+			Err(e) if e.kind() == io::ErrorKind::NotFound && dir.is_dir() => Ok(Vec::new()),
+			res => res,
+		}
+	}
+
+	/// Returns the record of `rel_path`, a path relative to the data directory, if it exists in
+	/// the snapshot
+	pub fn record(&self, rel_path: &Path) -> io::Result<Option<MetaRecord>> {
+		let (Some(parent), Some(name)) = (rel_path.parent(), rel_path.file_name()) else {
+			return Ok(None);
+		};
+		match self.dir_records(parent) {
+			Ok(records) => Ok(records.into_iter().find(|rec| rec.name == name.as_bytes())),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+			Err(e) => Err(e),
+		}
+	}
+
+	/// Returns the snapshot `since` of the same site, holding the full records of this snapshot's
+	/// `same-since <since>` records
+	pub fn since_snapshot(&self, since: u64) -> Snapshot {
+		Snapshot(
+			self.0
+				.parent()
+				.expect("snapshot should be in a snaps dir")
+				.join(since.to_string()),
+		)
+	}
+
+	/// Returns `rec` itself, or if it is a history interval record, the full record it refers to.
+	/// `rel_path` is the path of the record relative to the data directory.
+	pub fn resolve_record(&self, rel_path: &Path, rec: MetaRecord) -> io::Result<MetaRecord> {
+		let Some(since) = rec.same_since else {
+			return Ok(rec);
+		};
+
+		let since_snap = self.since_snapshot(since);
+		match since_snap.record(rel_path)? {
+			Some(since_rec) if since_rec.same_since.is_none() => Ok(since_rec),
+			_ => Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!(
+					"{rel_path:?} in {:?} refers to missing or non-full record in {:?}",
+					self.0, since_snap.0
+				),
+			)),
+		}
+	}
+
+	/// Returns the path of the repository file holding the content of the regular file at
+	/// `rel_path`, along with its full record. Follows history interval records to the snapshot
+	/// with the full record, and deduplicated files to their backing file.
+	pub fn resolve_content(&self, rel_path: &Path) -> io::Result<Option<(PathBuf, MetaRecord)>> {
+		let Some(rec) = self.record(rel_path)? else {
+			return Ok(None);
+		};
+
+		// The content lives in the snapshot holding the full record
+		let holder = match rec.same_since {
+			Some(since) => self.since_snapshot(since),
+			None => self.clone(),
+		};
+		let rec = self.resolve_record(rel_path, rec)?;
+
+		if rec.file_type != Some(FileType::Reg) {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				format!("{rel_path:?} in {:?} is not a regular file", self.0),
+			));
+		}
+
+		let data_path = holder.data_dir().join(rel_path);
+		if !rec.is_deduplicated {
+			return Ok(Some((data_path, rec)));
+		}
+
+		// Deduplicated files are relative symlinks to their backing file
+		let target = std::fs::read_link(&data_path)?;
+		let backing_path = data_path
+			.parent()
+			.expect("data path should have a parent")
+			.join(target);
+		Ok(Some((backing_path, rec)))
+	}
+
+	/// Returns the snapshot's creation statistics, or `None` for snapshots created before their
+	/// recording
+	pub fn summary(&self) -> io::Result<Option<Summary>> {
+		match std::fs::read_to_string(self.0.join(summary::FNAME)) {
+			Ok(data) => Summary::from_toml(&data).map(Some),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+			Err(e) => Err(e),
+		}
+	}
+
+	pub fn name(&self) -> &OsStr {
+		self.0
+			.file_name()
+			.expect("snapshot path should not end in ..")
+	}
+
+	/// Returns the site containing the snapshot
+	pub fn site(&self) -> Site {
+		Site(
+			self.0
+				.parent()
+				.and_then(Path::parent)
+				.expect("snapshot dir should be within a site")
+				.to_path_buf(),
+		)
+	}
+
+	/// Returns the position of the snapshot in its site's sequence, if it has a valid name
+	pub fn number(&self) -> Option<u64> {
+		self.name().to_str().and_then(|name| name.parse().ok())
+	}
+
+	/// Returns the paths excluded during the snapshot's creation, or `None` for snapshots created
+	/// before exclusion logging
+	pub fn exclusions(&self) -> io::Result<Option<Vec<Exclusion>>> {
+		let entries = match dsv::vec_from_file(self.0.join(EXCLUDED_FNAME), nsv::SEP) {
+			Ok(entries) => entries,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+			Err(e) => return Err(e),
+		};
+
+		if !entries.len().is_multiple_of(2) {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("odd number of entries in {:?}", self.0.join(EXCLUDED_FNAME)),
+			));
+		}
+
+		Ok(Some(
+			entries
+				.chunks_exact(2)
+				.map(|pair| Exclusion {
+					path: PathBuf::from(OsString::from_vec(pair[0].clone())),
+					reason: String::from_utf8_lossy(&pair[1]).into_owned(),
+				})
+				.collect(),
+		))
+	}
 }
 
 impl TryFrom<std::fs::DirEntry> for Snapshot {
@@ -0,0 +1,66 @@
+use std::{collections::BTreeMap, io, path::PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub const FNAME: &str = "summary.toml";
+
+/// Machine-readable statistics about the creation of a snapshot
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Summary {
+	/// Version of `baktu` that created the snapshot
+	pub baktu_version: String,
+	pub start_time: DateTime<Utc>,
+	pub end_time: DateTime<Utc>,
+	pub wall_time_secs: f64,
+	/// Total content size of the regular files read from the source dataset
+	pub bytes_read: u64,
+	/// Total size of the file content copied to the snapshot
+	pub bytes_written: u64,
+	/// Total size of the files represented as deduplicated ones
+	pub bytes_deduplicated: u64,
+	/// Number of file hashes that were not present in the repository before the snapshot
+	pub new_unique_hashes: u64,
+	/// Number of processed paths, by file type
+	pub files: BTreeMap<String, u64>,
+	/// Number of excluded paths (not counting their children), by reason
+	pub exclusions: BTreeMap<String, u64>,
+	/// Source of an imported snapshot, whose start and end times are those of the source, rather
+	/// than of the import
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub imported_from: Option<PathBuf>,
+}
+
+impl Summary {
+	pub fn new(start_time: DateTime<Utc>) -> Self {
+		Summary {
+			baktu_version: env!("CARGO_PKG_VERSION").to_owned(),
+			start_time,
+			end_time: start_time,
+			wall_time_secs: 0.0,
+			bytes_read: 0,
+			bytes_written: 0,
+			bytes_deduplicated: 0,
+			new_unique_hashes: 0,
+			files: BTreeMap::new(),
+			exclusions: BTreeMap::new(),
+			imported_from: None,
+		}
+	}
+
+	pub fn files_total(&self) -> u64 {
+		self.files.values().sum()
+	}
+
+	pub fn exclusions_total(&self) -> u64 {
+		self.exclusions.values().sum()
+	}
+
+	pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+		toml::to_string(self)
+	}
+
+	pub fn from_toml(data: &str) -> io::Result<Self> {
+		toml::from_str(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+	}
+}
@@ -1,13 +1,11 @@
-use std::path::{Path, PathBuf};
-
-// TODO: (P) switch to std::sync::Lazy, see
-//   https://rust-lang.github.io/rfcs/2788-standard-lazy-types.html
-//   https://github.com/rust-lang/rust/pull/105587
+use std::{
+	fmt, fs, io,
+	path::{Path, PathBuf},
+};
 
 use log::debug;
-use once_cell::sync::Lazy;
 
-use crate::util::ext::PathExt;
+use crate::error::{Error, IoResultExt, Result};
 
 // Use macro to work around include_str not accepting string constants
 macro_rules! NAME_MACRO {
@@ -21,16 +19,121 @@ pub static NAME: &str = NAME_MACRO!();
 
 static DATA: &str = include_str!(concat!("../../templates/", NAME_MACRO!()));
 
-// Using Lazy as Option::expect is not const yet: https://github.com/rust-lang/rust/issues/67441
-static DATA_PREFIX: Lazy<&[u8]> = Lazy::new(|| {
-	DATA.lines()
-		.next()
-		.expect("tag file should have at least one line")
-		.as_bytes()
-});
+// Update appropriate doc/repositories/<version>/index.md if you change these
+const VERSION_PREFIX: &str = "baktu repository version ";
+const PFX_COMPAT: &str = "compat ";
+const PFX_INCOMPAT: &str = "incompat ";
+
+/// The repository format version written by this version of baktu
+pub const CURRENT_VERSION: u32 = 3;
+
+/// The optional repository features this version of baktu understands
+pub const KNOWN_FEATURES: &[&str] = &[];
+
+/// The parsed content of a tag file: the repository format version, along with the optional
+/// features in use. Clients may ignore unknown compatible features, but must not modify
+/// repositories with unknown incompatible ones.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tag {
+	pub version: u32,
+	pub compat: Vec<String>,
+	pub incompat: Vec<String>,
+}
+
+impl Tag {
+	pub fn parse(data: &str) -> Result<Tag, String> {
+		let mut lines = data.lines();
+		let version = lines
+			.next()
+			.and_then(|line| line.strip_prefix(VERSION_PREFIX))
+			.and_then(|version| version.parse().ok())
+			.ok_or("missing or malformed version line")?;
+
+		let mut tag = Tag {
+			version,
+			..Default::default()
+		};
+		for line in lines.filter(|line| !line.is_empty()) {
+			if let Some(feature) = line.strip_prefix(PFX_COMPAT) {
+				tag.compat.push(feature.to_owned());
+			} else if let Some(feature) = line.strip_prefix(PFX_INCOMPAT) {
+				tag.incompat.push(feature.to_owned());
+			} else {
+				return Err(format!("malformed line {line:?}"));
+			}
+		}
+		Ok(tag)
+	}
+
+	/// Reads the tag file in `dir`, failing with [`Error::Corrupt`] if it is malformed
+	pub fn read_from(dir: &Path) -> Result<Tag> {
+		let path = dir.join(NAME);
+		let data = fs::read_to_string(&path).at(&path)?;
+		Tag::parse(&data).map_err(|reason| Error::Corrupt { path, reason })
+	}
+
+	/// Replaces the tag file in `dir`, via a temporary file so it is never partially written
+	pub fn write_to(&self, dir: &Path) -> io::Result<()> {
+		let tmp_path = dir.join(format!("{NAME}.tmp"));
+		fs::write(&tmp_path, self.to_string())?;
+		fs::rename(tmp_path, dir.join(NAME))
+	}
+
+	fn unknown(features: &[String]) -> Vec<&str> {
+		features
+			.iter()
+			.map(String::as_str)
+			.filter(|f| !KNOWN_FEATURES.contains(f))
+			.collect()
+	}
+
+	/// Returns why this version of baktu might misread the repository, if it might
+	pub fn check_readable(&self) -> Result<(), String> {
+		if self.version > CURRENT_VERSION {
+			return Err(format!(
+				"repository format version {} is newer than the supported version {CURRENT_VERSION}",
+				self.version
+			));
+		}
+		let unknown = Self::unknown(&self.incompat);
+		if !unknown.is_empty() {
+			return Err(format!(
+				"repository uses unknown incompatible features: {}",
+				unknown.join(", ")
+			));
+		}
+		Ok(())
+	}
+
+	/// Returns why this version of baktu must not modify the repository, if it must not
+	pub fn check_writable(&self) -> Result<(), String> {
+		self.check_readable()?;
+		if self.version < CURRENT_VERSION {
+			return Err(format!(
+				"repository format version {} is older than the current version \
+				{CURRENT_VERSION}, run `baktu upgrade` first",
+				self.version
+			));
+		}
+		Ok(())
+	}
+}
+
+impl fmt::Display for Tag {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "{VERSION_PREFIX}{}", self.version)?;
+		for feature in &self.compat {
+			writeln!(f, "{PFX_COMPAT}{feature}")?;
+		}
+		for feature in &self.incompat {
+			writeln!(f, "{PFX_INCOMPAT}{feature}")?;
+		}
+		Ok(())
+	}
+}
 
 pub fn is_valid(path: PathBuf) -> bool {
-	path.content_starts_with(&DATA_PREFIX)
+	fs::read_to_string(path).is_ok_and(|data| Tag::parse(&data).is_ok())
 }
 
 pub fn create_in(dir: &Path) -> std::io::Result<()> {
@@ -39,3 +142,56 @@ pub fn create_in(dir: &Path) -> std::io::Result<()> {
 	debug!("creating tag file {:?}", tag_file_path);
 	std::fs::write(tag_file_path, DATA)
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn template_is_current() {
+		assert_eq!(
+			Tag::parse(DATA),
+			Ok(Tag {
+				version: CURRENT_VERSION,
+				..Default::default()
+			})
+		);
+	}
+
+	#[test]
+	fn roundtrip() {
+		let data = "baktu repository version 3\ncompat a\nincompat b\nincompat c\n";
+		let tag = Tag::parse(data).unwrap();
+		assert_eq!(tag.compat, ["a"]);
+		assert_eq!(tag.incompat, ["b", "c"]);
+		assert_eq!(tag.to_string(), data);
+	}
+
+	#[test]
+	fn malformed() {
+		assert!(Tag::parse("").is_err());
+		assert!(Tag::parse("baktu repository version x\n").is_err());
+		assert!(Tag::parse("baktu repository version 2\nfrobnicate\n").is_err());
+	}
+
+	#[test]
+	fn checks() {
+		let tag = |data| Tag::parse(data).unwrap();
+		assert!(tag("baktu repository version 1\n").check_readable().is_ok());
+		assert!(tag("baktu repository version 1\n")
+			.check_writable()
+			.is_err());
+		assert!(tag("baktu repository version 3\ncompat x\n")
+			.check_writable()
+			.is_ok());
+		assert!(tag("baktu repository version 3\nincompat x\n")
+			.check_readable()
+			.is_err());
+		assert!(tag("baktu repository version 2\n")
+			.check_writable()
+			.is_err());
+		assert!(tag("baktu repository version 4\n")
+			.check_readable()
+			.is_err());
+	}
+}
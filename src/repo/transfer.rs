@@ -0,0 +1,121 @@
+//! Copying snapshots between repositories
+
+use std::{
+	collections::HashMap,
+	fs::{self, File},
+	io,
+	os::unix::prelude::{MetadataExt, OsStrExt},
+	path::Path,
+};
+
+use blake3::Hash;
+use log::debug;
+use nix::sys::stat::{mknod, Mode, SFlag};
+
+use crate::{
+	file,
+	repo::{meta_file::MetaFile, snapshot::Snapshot},
+};
+
+#[derive(Debug, Default)]
+pub struct CopyStats {
+	pub files: u64,
+	pub bytes: u64,
+	/// Regular files whose content was verified against their recorded BLAKE3 hash
+	pub verified: u64,
+}
+
+/// Copies the snapshot `src` to `dst_path`, which must not exist. Relative symlinks, including
+/// those of deduplicated files, are copied as is, so they resolve once the snapshots they refer
+/// to are copied as well. Regular files in the data directory are verified against their recorded
+/// hashes while being copied.
+///
+/// The copy is made in a temporary sibling directory and renamed at the end, so an interrupted
+/// copy never looks like a snapshot. Leftovers of earlier interrupted copies are removed.
+pub fn copy_snapshot(src: &Snapshot, dst_path: &Path) -> io::Result<CopyStats> {
+	let mut partial_name = std::ffi::OsString::from(".");
+	partial_name.push(src.name());
+	partial_name.push(".partial");
+	let partial = dst_path.with_file_name(partial_name);
+	if partial.exists() {
+		debug!("removing leftover partial copy {partial:?}");
+		fs::remove_dir_all(&partial)?;
+	}
+
+	let meta_name = src.meta_name()?;
+	let data_dir = src.data_dir();
+	let mut stats = CopyStats::default();
+
+	// Recorded hashes of the regular files in each directory of the current path, by depth
+	let mut dir_hashes: Vec<HashMap<Vec<u8>, Hash>> = Vec::new();
+
+	for entry in walkdir::WalkDir::new(&src.0).sort_by_file_name() {
+		let entry = entry?;
+		let rel_path = entry
+			.path()
+			.strip_prefix(&src.0)
+			.expect("walked paths are within the snapshot");
+		let dst = partial.join(rel_path);
+		let file_type = entry.file_type();
+
+		if file_type.is_dir() {
+			fs::create_dir(&dst)?;
+			dir_hashes.truncate(entry.depth());
+			dir_hashes.push(if entry.path().starts_with(&data_dir) {
+				recorded_hashes(&MetaFile(entry.path().join(&meta_name)))?
+			} else {
+				HashMap::new()
+			});
+		} else if file_type.is_symlink() {
+			std::os::unix::fs::symlink(fs::read_link(entry.path())?, &dst)?;
+		} else if file_type.is_file() {
+			let (size, hash) =
+				file::copy_hashed(&mut File::open(entry.path())?, &mut File::create(&dst)?)?;
+			fs::set_permissions(&dst, entry.metadata()?.permissions())?;
+			stats.bytes += size;
+
+			let expected = dir_hashes
+				.get(entry.depth() - 1)
+				.and_then(|hashes| hashes.get(entry.file_name().as_bytes()));
+			if let Some(expected) = expected {
+				if *expected != hash {
+					return Err(io::Error::new(
+						io::ErrorKind::InvalidData,
+						format!(
+							"BLAKE3 mismatch for {:?}: recorded {}, actual {}",
+							entry.path(),
+							expected.to_hex(),
+							hash.to_hex()
+						),
+					));
+				}
+				stats.verified += 1;
+			}
+		} else {
+			let meta = entry.metadata()?;
+			mknod(
+				&dst,
+				SFlag::from_bits_truncate(meta.mode() & libc::S_IFMT),
+				Mode::from_bits_truncate(meta.mode() & !libc::S_IFMT),
+				meta.rdev(),
+			)?;
+		}
+		stats.files += 1;
+	}
+
+	fs::rename(partial, dst_path)?;
+	Ok(stats)
+}
+
+/// Returns the recorded hashes of the non-deduplicated regular files described by `meta_file`
+fn recorded_hashes(meta_file: &MetaFile) -> io::Result<HashMap<Vec<u8>, Hash>> {
+	let records = match meta_file.meta_records() {
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+		res => res?,
+	};
+	Ok(records
+		.into_iter()
+		.filter(|rec| !rec.is_deduplicated)
+		.filter_map(|rec| Some((rec.name, rec.b3sum?)))
+		.collect())
+}
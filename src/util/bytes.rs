@@ -0,0 +1,79 @@
+//! Lossless serialization of byte strings such as file names and extended attribute values, for
+//! use with `#[serde(serialize_with)]`
+
+use std::{os::unix::ffi::OsStrExt, path::Path};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use schemars::JsonSchema;
+use serde::{ser::SerializeMap, Serializer};
+
+// Schema of byte strings serialized by `serialize` in human-readable formats, whose doc comments
+// double as schema descriptions
+/// Byte string, as a string if it is valid UTF-8 and as base64 otherwise. Binary formats such as
+/// CBOR use their native byte strings instead.
+#[derive(JsonSchema)]
+#[serde(untagged)]
+// Only used for its schema
+#[allow(dead_code)]
+pub enum Bytes {
+	/// Byte string that is valid UTF-8
+	Utf8(String),
+	/// Any other byte string
+	Base64 {
+		/// Standard base64 encoding, with padding
+		base64: String,
+	},
+}
+
+/// Serializes `bytes` as a string if they are valid UTF-8, and as a `{"base64": ...}` map
+/// otherwise. Binary formats get the bytes as they are.
+pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+	if !serializer.is_human_readable() {
+		return serializer.serialize_bytes(bytes);
+	}
+	match std::str::from_utf8(bytes) {
+		Ok(text) => serializer.serialize_str(text),
+		Err(_) => {
+			let mut map = serializer.serialize_map(Some(1))?;
+			map.serialize_entry("base64", &STANDARD.encode(bytes))?;
+			map.end()
+		}
+	}
+}
+
+/// Serializes `path` as with [`serialize`]
+pub fn serialize_path<S: Serializer>(path: &&Path, serializer: S) -> Result<S::Ok, S::Error> {
+	serialize(path.as_os_str().as_bytes(), serializer)
+}
+
+/// Serializes `path` as with [`serialize`], or as null if there is none
+pub fn serialize_path_opt<S: Serializer>(
+	path: &Option<&Path>,
+	serializer: S,
+) -> Result<S::Ok, S::Error> {
+	match path {
+		Some(path) => serialize_path(path, serializer),
+		None => serializer.serialize_none(),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	#[derive(serde::Serialize)]
+	struct Wrapper<'a>(#[serde(serialize_with = "super::serialize")] &'a [u8]);
+
+	#[test]
+	fn json() {
+		let json = |bytes| serde_json::to_string(&Wrapper(bytes)).unwrap();
+		assert_eq!(json(b"caf\xc3\xa9 \"x\""), r#""café \"x\"""#);
+		assert_eq!(json(b"\xff\n"), r#"{"base64":"/wo="}"#);
+	}
+
+	#[test]
+	fn cbor() {
+		let mut out = Vec::new();
+		ciborium::into_writer(&Wrapper(b"ab"), &mut out).unwrap();
+		// Major type 2, byte string of length 2
+		assert_eq!(out, b"\x42ab");
+	}
+}
@@ -12,9 +12,7 @@ pub fn vec_from_file<P: AsRef<Path>>(path: P, sep: u8) -> io::Result<Vec<Vec<u8>
 	let mut reader = BufReader::new(file);
 	loop {
 		let mut entry: Vec<u8> = Vec::new();
-		let bytes_read = reader
-			.read_until(sep, &mut entry)
-			.expect("should be able to read all bytes in the file");
+		let bytes_read = reader.read_until(sep, &mut entry)?;
 
 		if bytes_read == 0 {
 			break Ok(result);
@@ -28,9 +26,10 @@ pub fn vec_from_file<P: AsRef<Path>>(path: P, sep: u8) -> io::Result<Vec<Vec<u8>
 	}
 }
 
-/// Overwrites `file` with `xs`, separated by `sep`
+/// Overwrites `file` with `xs`, separated by `sep`, creating it if needed
 pub fn vec_to_file<P: AsRef<Path>>(file: P, sep: u8, xs: Vec<Vec<u8>>) -> io::Result<()> {
 	let mut file = std::fs::OpenOptions::new()
+		.create(true)
 		.write(true)
 		.truncate(true)
 		.open(file)?;
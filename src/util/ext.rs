@@ -3,13 +3,14 @@ use std::{
 	fs::File,
 	io::{self, Read},
 	os::unix::prelude::{OsStrExt, OsStringExt},
-	path::{Path, PathBuf},
+	path::{Component, Path, PathBuf},
 };
 
 pub trait PathExt {
 	fn read_exact(&self, bytes: usize) -> io::Result<Vec<u8>>;
 	fn content_starts_with(&self, prefix: &[u8]) -> bool;
 	fn tilde_expand(&self) -> PathBuf;
+	fn lexical_normalize(&self) -> PathBuf;
 }
 
 impl PathExt for Path {
@@ -27,4 +28,20 @@ impl PathExt for Path {
 	fn tilde_expand(&self) -> PathBuf {
 		OsString::from_vec(tilde_expand::tilde_expand(self.as_os_str().as_bytes())).into()
 	}
+
+	/// Resolves `.` and `..` components without accessing the filesystem, thus without following
+	/// symlinks
+	fn lexical_normalize(&self) -> PathBuf {
+		let mut normalized = PathBuf::new();
+		for component in self.components() {
+			match component {
+				Component::CurDir => {}
+				Component::ParentDir => {
+					normalized.pop();
+				}
+				c => normalized.push(c),
+			}
+		}
+		normalized
+	}
 }
@@ -0,0 +1,55 @@
+use std::ffi::{c_char, c_int, CString, NulError};
+
+extern "C" {
+	// Not exposed by the libc crate
+	fn fnmatch(pattern: *const c_char, string: *const c_char, flags: c_int) -> c_int;
+}
+
+// From <fnmatch.h>
+const FNM_PATHNAME: c_int = 1 << 0;
+
+/// A shell glob pattern, matched via `fnmatch(3)`
+#[derive(Debug)]
+pub struct Glob(CString);
+
+impl Glob {
+	pub fn new(pattern: &str) -> Result<Glob, NulError> {
+		CString::new(pattern).map(Glob)
+	}
+
+	/// Matches a single name, where wildcards also match `/`
+	pub fn matches(&self, name: &[u8]) -> bool {
+		self.fnmatch(name, 0)
+	}
+
+	/// Matches a path, where wildcards do not match `/`
+	pub fn matches_path(&self, path: &[u8]) -> bool {
+		self.fnmatch(path, FNM_PATHNAME)
+	}
+
+	fn fnmatch(&self, string: &[u8], flags: c_int) -> bool {
+		let Ok(string) = CString::new(string) else {
+			return false;
+		};
+		// SAFETY: both arguments are valid NUL-terminated strings
+		unsafe { fnmatch(self.0.as_ptr(), string.as_ptr(), flags) == 0 }
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn matches() {
+		let glob = Glob::new("*.txt").unwrap();
+		assert!(glob.matches(b"a.txt"));
+		assert!(glob.matches(b"dir/a.txt"));
+		assert!(!glob.matches(b"a.md"));
+		assert!(!glob.matches_path(b"dir/a.txt"));
+
+		let glob = Glob::new("src/*/secret").unwrap();
+		assert!(glob.matches_path(b"src/dir/secret"));
+		assert!(!glob.matches_path(b"src/a/b/secret"));
+	}
+}
@@ -30,10 +30,36 @@ pub mod tagged_rawhex {
 
 		result
 	}
+
+	/// Decodes a byte sequence produced by [`encode`], returning `None` if it is malformed
+	pub fn decode(encoded: &[u8]) -> Option<Vec<u8>> {
+		match split_first(encoded)? {
+			(decoded, []) => Some(decoded),
+			_ => None,
+		}
+	}
+
+	/// Decodes the encoded value at the start of `encoded`, returning it along with the remaining
+	/// bytes. Hex values end at the first space, as they can not contain one.
+	pub fn split_first(encoded: &[u8]) -> Option<(Vec<u8>, &[u8])> {
+		if let Some(hex_rest) = encoded.strip_prefix(b"h ") {
+			let end = hex_rest
+				.iter()
+				.position(|c| *c == b' ')
+				.unwrap_or(hex_rest.len());
+			Some((super::try_decode(&hex_rest[..end])?, &hex_rest[end..]))
+		} else {
+			let rest = encoded.strip_prefix(b"r-")?;
+			let space = rest.iter().position(|c| *c == b' ')?;
+			let size: usize = std::str::from_utf8(&rest[..space]).ok()?.parse().ok()?;
+			let raw = &rest[space + 1..];
+			(raw.len() >= size).then(|| (raw[..size].to_vec(), &raw[size..]))
+		}
+	}
 }
 
 /// Produces lowercase-hex encoded data
-fn encode(bytes: &[u8]) -> Vec<u8> {
+pub fn encode(bytes: &[u8]) -> Vec<u8> {
 	let mut result = Vec::new();
 	let hex_char = b"0123456789abcdef";
 	for byte in bytes {
@@ -43,23 +69,72 @@ fn encode(bytes: &[u8]) -> Vec<u8> {
 	result
 }
 
-/// Decodes hex encoded data, assumes even length of input
-pub fn decode(hex_str: &[u8]) -> Vec<u8> {
-	// TODO: (C) add capitals, switch to result, as we want to have more options than panic on a
-	// corrupt meta file
-	assert!(hex_str.len() % 2 == 0);
-
-	fn val_of(c: u8) -> u8 {
+/// Decodes lowercase-hex encoded data, returning `None` if it is not valid
+// TODO: (C) add capitals
+pub fn try_decode(hex_str: &[u8]) -> Option<Vec<u8>> {
+	fn val_of(c: u8) -> Option<u8> {
 		match c {
-			b'0'..=b'9' => c - b'0',
-			b'a'..=b'f' => c - b'a' + 10,
-			// assume no invalid values, as we're specialized for `get-all-xattrs` output
-			c => panic!("unexpected value {c} in hex input"),
+			b'0'..=b'9' => Some(c - b'0'),
+			b'a'..=b'f' => Some(c - b'a' + 10),
+			_ => None,
 		}
 	}
 
+	if !hex_str.len().is_multiple_of(2) {
+		return None;
+	}
 	hex_str
 		.chunks(2)
-		.map(|pair| val_of(pair[0]) << 4 | val_of(pair[1]))
+		.map(|pair| Some(val_of(pair[0])? << 4 | val_of(pair[1])?))
 		.collect()
 }
+
+#[cfg(test)]
+mod test {
+	use super::{tagged_rawhex, try_decode};
+
+	#[test]
+	fn tagged_rawhex_roundtrip() {
+		for (hex_on_space, bytes) in [
+			(false, &b"plain name.txt"[..]),
+			(true, b"key with spaces"),
+			(false, b"line1\nline2"),
+			(false, b"trailing nul\0"),
+			(false, b""),
+		] {
+			let encoded = tagged_rawhex::encode(hex_on_space, bytes);
+			assert_eq!(tagged_rawhex::decode(&encoded).as_deref(), Some(bytes));
+		}
+	}
+
+	#[test]
+	fn tagged_rawhex_split_first() {
+		let line = [
+			tagged_rawhex::encode(true, b"user.key with space"),
+			b" v.".to_vec(),
+			tagged_rawhex::encode(false, b"value with space"),
+		]
+		.concat();
+
+		let (key, rest) = tagged_rawhex::split_first(&line).unwrap();
+		assert_eq!(key, b"user.key with space");
+		let (value, rest) = tagged_rawhex::split_first(rest.strip_prefix(b" v.").unwrap()).unwrap();
+		assert_eq!(value, b"value with space");
+		assert!(rest.is_empty());
+	}
+
+	#[test]
+	fn tagged_rawhex_malformed() {
+		for encoded in [&b"r-5 abc"[..], b"r-x abc", b"h abc", b"h zz", b"x 00"] {
+			assert_eq!(tagged_rawhex::decode(encoded), None);
+		}
+	}
+
+	#[test]
+	fn try_decode_malformed() {
+		assert_eq!(try_decode(b"00ff").as_deref(), Some(&[0, 255][..]));
+		for hex_str in [&b"0"[..], b"zz", b"FF"] {
+			assert_eq!(try_decode(hex_str), None);
+		}
+	}
+}
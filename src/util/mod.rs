@@ -1,4 +1,6 @@
+pub mod bytes;
 pub mod dsv;
 pub mod ext;
+pub mod glob;
 pub mod hex;
 pub mod nsv;
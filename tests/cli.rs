@@ -1,7 +1,79 @@
+use std::{os::unix::ffi::OsStrExt, path::Path};
+
 use assert_cmd::Command;
 use assert_fs::prelude::*;
 use predicates::prelude::*;
 
+/// Returns a `baktu` command, with the `get-all-xattrs` helper in PATH for unprivileged runs
+fn baktu() -> Command {
+	let mut cmd = Command::cargo_bin("baktu").unwrap();
+	cmd.env(
+		"PATH",
+		format!(
+			"{}/xattr-helper:{}",
+			env!("CARGO_MANIFEST_DIR"),
+			std::env::var("PATH").unwrap_or_default()
+		),
+	);
+	cmd
+}
+
+/// Creates a temporary directory containing a `src` source dataset, and a `repo` repository with
+/// an `s` site that includes it
+fn repo_with_site() -> assert_fs::TempDir {
+	let temp = assert_fs::TempDir::new().unwrap();
+
+	temp.child("src/hello.txt")
+		.write_str("hello world\n")
+		.unwrap();
+	temp.child("src/dir/copy.txt")
+		.write_str("hello world\n")
+		.unwrap();
+	temp.child("src/dir/empty").touch().unwrap();
+
+	temp.child("repo").create_dir_all().unwrap();
+	baktu()
+		.current_dir(temp.child("repo"))
+		.arg("init")
+		.assert()
+		.success();
+	baktu()
+		.current_dir(temp.child("repo"))
+		.args(["add-site", "s"])
+		.assert()
+		.success();
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.arg("nsv-add-to")
+		.arg("include-paths.nsv")
+		.arg(temp.child("src").path())
+		.assert()
+		.success();
+
+	temp
+}
+
+/// Runs `baktu snap` with `args` in the `s` site of a [`repo_with_site`] directory
+fn snap(temp: &assert_fs::TempDir, args: &[&str]) {
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.arg("snap")
+		.args(args)
+		.assert()
+		.success();
+}
+
+/// Returns the `excluded.nsv` log of snapshot `snap` of site `s`, as (path, reason) pairs
+fn exclusions(temp: &assert_fs::TempDir, snap: &str) -> Vec<(String, String)> {
+	let path = temp.child(format!("repo/sites/s/snaps/{snap}/excluded.nsv"));
+	let data = String::from_utf8(std::fs::read(path.path()).unwrap()).unwrap();
+	let entries: Vec<_> = data.split_terminator('\0').collect();
+	entries
+		.chunks_exact(2)
+		.map(|pair| (pair[0].to_owned(), pair[1].to_owned()))
+		.collect()
+}
+
 #[test]
 fn version() {
 	Command::cargo_bin("baktu")
@@ -62,5 +134,1326 @@ fn init_succeeds() {
 	// We're explicitly using literal strings here instead of tag_file::{NAME,DATA} to require two
 	// places to be changed when doing repository format changes.
 	temp.child("BAKTU_REPO.TAG")
+		.assert("baktu repository version 3\n");
+}
+
+#[test]
+fn show_config() {
+	let temp = repo_with_site();
+	snap(&temp, &["--one-file-system"]);
+
+	baktu()
+		.current_dir(temp.child("repo"))
+		.args(["show-config", "s/0"])
+		.assert()
+		.success()
+		.stdout(
+			predicate::str::contains("# config.toml\n[exclude]\n")
+				.and(predicate::str::contains(format!(
+					"# include-paths.nsv\n{}\n",
+					temp.child("src").path().display()
+				)))
+				.and(predicate::str::contains("one_file_system = true\n")),
+		);
+}
+
+#[test]
+fn ls() {
+	let temp = repo_with_site();
+	snap(&temp, &[]);
+	snap(&temp, &[]);
+
+	let ls = |location: Option<&str>| {
+		let mut cmd = baktu();
+		cmd.current_dir(temp.child("repo")).arg("ls").args(location);
+		cmd.assert().success()
+	};
+
+	ls(None).stdout("s\t2 snapshots\n");
+	ls(Some("s")).stdout(predicate::str::is_match("(?m)^   0  .* 5 paths .*\n   1  ").unwrap());
+	ls(Some("s/0/src")).stdout(
+		predicate::str::is_match(
+			"^drwx.*   dir\n\
+			-rw-.* 12 .* = hello.txt\n$",
+		)
+		.unwrap(),
+	);
+	ls(Some("s/0/src/dir")).stdout(
+		predicate::str::is_match(
+			"^-rw-.* 12 .*   copy.txt\n\
+			-rw-.* 0 .*   empty\n$",
+		)
+		.unwrap(),
+	);
+	ls(Some("s/1/src/hello.txt"))
+		.stdout(predicate::str::is_match("^-rw-.* = hello.txt\n$").unwrap());
+}
+
+#[test]
+fn cat() {
+	let temp = repo_with_site();
+	snap(&temp, &[]);
+	snap(&temp, &[]);
+
+	let cat = |location: &str| {
+		let mut cmd = baktu();
+		cmd.current_dir(temp.child("repo")).args(["cat", location]);
+		cmd.assert()
+	};
+
+	// Deduplicated within the snapshot, and against the previous snapshot, respectively
+	cat("s/0/src/hello.txt").success().stdout("hello world\n");
+	cat("s/1/src/dir/copy.txt")
+		.success()
+		.stdout("hello world\n");
+
+	cat("s/1/src/dir")
+		.failure()
+		.stderr(predicate::str::contains("not a regular file"));
+
+	temp.child("repo/sites/s/snaps/0/data/src/dir/copy.txt")
+		.write_str("corrupted\n")
+		.unwrap();
+	cat("s/1/src/hello.txt")
+		.failure()
+		.stderr(predicate::str::contains("BLAKE3 mismatch"));
+}
+
+#[test]
+fn export() {
+	let temp = repo_with_site();
+	snap(&temp, &[]);
+	snap(&temp, &[]);
+
+	let export = |args: &[&str]| {
+		let mut cmd = baktu();
+		cmd.current_dir(temp.child("repo")).arg("export").args(args);
+		cmd.assert()
+	};
+	let entries = |archive: &[u8]| {
+		tar::Archive::new(archive)
+			.entries()
+			.unwrap()
+			.map(|entry| {
+				let mut entry = entry.unwrap();
+				let mtime = entry
+					.pax_extensions()
+					.unwrap()
+					.unwrap()
+					.map(|ext| ext.unwrap())
+					.find(|ext| ext.key() == Ok("mtime"))
+					.map(|ext| ext.value().unwrap().to_owned());
+				let mut content = String::new();
+				std::io::Read::read_to_string(&mut entry, &mut content).unwrap();
+				(
+					entry.path().unwrap().to_string_lossy().into_owned(),
+					content,
+					mtime,
+				)
+			})
+			.collect::<Vec<_>>()
+	};
+
+	// Deduplicated within the snapshot, and against the previous snapshot
+	export(&["--format", "pax", "s/1", "s1.tar"])
+		.success()
+		.stdout("");
+	let archive = entries(&std::fs::read(temp.child("repo/s1.tar").path()).unwrap());
+	let paths: Vec<_> = archive.iter().map(|(path, ..)| path.as_str()).collect();
+	assert_eq!(
+		paths,
+		[
+			"src",
+			"src/dir",
+			"src/dir/copy.txt",
+			"src/dir/empty",
+			"src/hello.txt"
+		]
+	);
+	assert_eq!(archive[2].1, "hello world\n");
+	assert_eq!(archive[4].1, "hello world\n");
+	let mtime = std::fs::metadata(temp.child("src/hello.txt").path())
+		.unwrap()
+		.modified()
+		.unwrap()
+		.duration_since(std::time::UNIX_EPOCH)
+		.unwrap();
+	assert_eq!(
+		archive[4].2.as_deref(),
+		Some(format!("{}.{:09}", mtime.as_secs(), mtime.subsec_nanos()).as_str())
+	);
+
+	let output = export(&["s/0"]).success();
+	let archive = entries(&output.get_output().stdout);
+	assert_eq!(archive[0].0, "src");
+	assert_eq!(archive.len(), 5);
+
+	export(&["s/0/src"])
+		.failure()
+		.stderr(predicate::str::contains("invalid snapshot"));
+}
+
+#[test]
+fn meta() {
+	let temp = repo_with_site();
+	temp.child("src/caf\u{e9}.txt").write_str("cafe\n").unwrap();
+	std::fs::write(
+		temp.child("src")
+			.path()
+			.join(std::ffi::OsStr::from_bytes(b"\xff.bin")),
+		"binary name\n",
+	)
+	.unwrap();
+	snap(&temp, &[]);
+
+	let meta = |args: &[&str]| {
+		let mut cmd = baktu();
+		cmd.current_dir(temp.child("repo")).arg("meta").args(args);
+		cmd.assert()
+	};
+	let records = |output: &[u8]| {
+		std::str::from_utf8(output)
+			.unwrap()
+			.lines()
+			.map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+			.collect::<Vec<_>>()
+	};
+
+	let output = meta(&["show", "s/0", "src/dir/copy.txt"]).success();
+	let shown = records(&output.get_output().stdout);
+	assert_eq!(shown.len(), 1);
+	assert_eq!(shown[0]["path"], "src/dir/copy.txt");
+	assert_eq!(shown[0]["name"], "copy.txt");
+	assert_eq!(shown[0]["type"], "reg");
+	assert_eq!(shown[0]["size"], 12);
+	assert_eq!(
+		shown[0]["b3sum"],
+		blake3::hash(b"hello world\n").to_hex().as_str()
+	);
+
+	let output = meta(&["dump", "s/0"]).success();
+	let dumped = records(&output.get_output().stdout);
+	let paths: Vec<_> = dumped.iter().map(|rec| rec["path"].clone()).collect();
+	assert!(paths.contains(&serde_json::json!("src/caf\u{e9}.txt")));
+	assert!(paths.contains(&serde_json::json!({ "base64": "c3JjL/8uYmlu" })));
+	assert_eq!(dumped.len(), 7);
+
+	let output = meta(&["dump", "--format", "cbor", "s/0"]).success();
+	let mut cbor = output.get_output().stdout.as_slice();
+	let mut count = 0;
+	while !cbor.is_empty() {
+		let _: ciborium::Value = ciborium::from_reader(&mut cbor).unwrap();
+		count += 1;
+	}
+	assert_eq!(count, 7);
+
+	meta(&["show", "s/0", "src/missing"])
+		.failure()
+		.stderr(predicate::str::contains("not found"));
+}
+
+#[test]
+fn find() {
+	let temp = repo_with_site();
+	snap(&temp, &[]);
+	temp.child("src/other.md").write_str("other\n").unwrap();
+	snap(&temp, &[]);
+
+	let find = |args: &[&str]| {
+		let mut cmd = baktu();
+		cmd.current_dir(temp.child("repo")).arg("find").args(args);
+		cmd.assert().success()
+	};
+
+	find(&["--name", "*.txt", "--type", "reg"]).stdout(
+		"s/0/src/hello.txt\ns/0/src/dir/copy.txt\ns/1/src/hello.txt\ns/1/src/dir/copy.txt\n",
+	);
+	find(&["--name", "*.md"]).stdout("s/1/src/other.md\n");
+	find(&["--type", "dir", "--name", "d*"]).stdout("s/0/src/dir\ns/1/src/dir\n");
+	find(&[
+		"--b3sum",
+		blake3::hash(b"hello world\n").to_hex().as_str(),
+		"--max-size",
+		"12",
+	])
+	.stdout("s/0/src/hello.txt\ns/0/src/dir/copy.txt\ns/1/src/hello.txt\ns/1/src/dir/copy.txt\n");
+	find(&["--min-size", "13", "--type", "reg"]).stdout("");
+	find(&["--older", "2000-01-01"]).stdout("");
+}
+
+#[test]
+fn log() {
+	let temp = repo_with_site();
+	snap(&temp, &[]);
+	snap(&temp, &[]);
+	temp.child("src/hello.txt")
+		.write_str("hello again\n")
+		.unwrap();
+	snap(&temp, &[]);
+	std::fs::remove_file(temp.child("src/hello.txt")).unwrap();
+	snap(&temp, &[]);
+	temp.child("src/hello.txt")
+		.write_str("hello again\n")
+		.unwrap();
+	snap(&temp, &[]);
+
+	let mut cmd = baktu();
+	cmd.current_dir(temp.child("repo"))
+		.args(["log", "s", "src/hello.txt"]);
+	let output = cmd.assert().success().get_output().stdout.clone();
+	let statuses: Vec<String> = String::from_utf8(output)
+		.unwrap()
+		.lines()
+		.map(|line| {
+			line.split_whitespace()
+				.skip(3)
+				.collect::<Vec<_>>()
+				.join(" ")
+		})
+		.collect();
+	assert_eq!(
+		statuses,
+		[
+			"added reg, 12 B",
+			"unchanged",
+			"content mtime",
+			"deleted",
+			"reappeared content unchanged, mtime",
+		]
+	);
+
+	let mut cmd = baktu();
+	cmd.current_dir(temp.child("repo"))
+		.args(["log", "s", "src/missing.txt"]);
+	cmd.assert()
+		.failure()
+		.stderr(predicate::str::contains("not found in any snapshot"));
+}
+
+#[test]
+fn prune() {
+	let temp = repo_with_site();
+	snap(&temp, &[]);
+	snap(&temp, &[]);
+
+	let prune = |args: &[&str]| {
+		let mut cmd = baktu();
+		cmd.current_dir(temp.child("repo/sites/s"))
+			.arg("prune")
+			.args(args);
+		cmd.assert()
+	};
+
+	prune(&[])
+		.failure()
+		.stderr(predicate::str::contains("no --keep-* option given"));
+
+	let plan = || {
+		predicate::str::contains("remove     0")
+			.and(predicate::str::contains("keep       1"))
+			.and(predicate::str::contains(
+				"move    \"sites/s/snaps/0/data/src/dir/copy.txt\" -> \
+			\"sites/s/snaps/1/data/src/dir/copy.txt\"",
+			))
+			.and(predicate::str::contains(
+				"relink  \"sites/s/snaps/1/data/src/hello.txt\"",
+			))
+	};
+	prune(&["--keep-last", "1", "--dry-run"])
+		.success()
+		.stdout(plan());
+	temp.child("repo/sites/s/snaps/0")
+		.assert(predicate::path::is_dir());
+
+	prune(&["--keep-last", "1"]).success().stdout(plan());
+	temp.child("repo/sites/s/snaps/0")
+		.assert(predicate::path::missing());
+	temp.child("repo/sites/s/snaps/1/data/src/dir/copy.txt")
+		.assert(predicate::path::is_file().and(predicate::path::is_symlink().not()));
+
+	for location in ["s/1/src/hello.txt", "s/1/src/dir/copy.txt"] {
+		let mut cmd = baktu();
+		cmd.current_dir(temp.child("repo")).args(["cat", location]);
+		cmd.assert().success().stdout("hello world\n");
+	}
+
+	// The next snapshot still deduplicates against the moved backing file
+	snap(&temp, &[]);
+	temp.child("repo/sites/s/snaps/2/data/src/dir/copy.txt")
+		.assert(predicate::path::is_symlink());
+}
+
+#[test]
+fn purge() {
+	let temp = repo_with_site();
+	snap(&temp, &[]);
+
+	// A second site, deduplicating against the first
+	baktu()
+		.current_dir(temp.child("repo"))
+		.args(["add-site", "t"])
+		.assert()
+		.success();
+	baktu()
+		.current_dir(temp.child("repo/sites/t"))
+		.arg("nsv-add-to")
+		.arg("include-paths.nsv")
+		.arg(temp.child("src/dir").path())
+		.assert()
+		.success();
+	baktu()
+		.current_dir(temp.child("repo/sites/t"))
+		.arg("snap")
+		.assert()
+		.success();
+
+	let mut cmd = baktu();
+	cmd.current_dir(temp.child("repo"))
+		.args(["purge", "s", "src/d*"]);
+	cmd.assert().success().stdout(
+		"purge   \"sites/s/snaps/0/data/src/dir\"\n\
+		move    \"sites/s/snaps/0/data/src/dir/copy.txt\" -> \
+		\"sites/s/snaps/0/data/src/hello.txt\"\n\
+		relink  \"sites/t/snaps/0/data/dir/copy.txt\"\n",
+	);
+
+	temp.child("repo/sites/s/snaps/0/data/src/dir")
+		.assert(predicate::path::missing());
+	let mut cmd = baktu();
+	cmd.current_dir(temp.child("repo"))
+		.args(["find", "--name", "*.txt"]);
+	cmd.assert()
+		.success()
+		.stdout("s/0/src/hello.txt\nt/0/dir/copy.txt\n");
+	for location in ["s/0/src/hello.txt", "t/0/dir/copy.txt"] {
+		let mut cmd = baktu();
+		cmd.current_dir(temp.child("repo")).args(["cat", location]);
+		cmd.assert().success().stdout("hello world\n");
+	}
+
+	// The digests were updated along with the meta files
+	let mut cmd = baktu();
+	cmd.current_dir(temp.child("repo")).arg("fsck");
+	cmd.assert().success().stdout("s/0\tok\nt/0\tok\n");
+}
+
+#[test]
+fn fsck_and_diff() {
+	let temp = repo_with_site();
+	snap(&temp, &[]);
+	temp.child("src/hello.txt").write_str("changed\n").unwrap();
+	temp.child("src/new.txt").touch().unwrap();
+	snap(&temp, &[]);
+
+	let fsck = |args: &[&str]| {
+		let mut cmd = baktu();
+		cmd.current_dir(temp.child("repo")).arg("fsck").args(args);
+		cmd.assert()
+	};
+	fsck(&[]).success().stdout("s/0\tok\ns/1\tok\n");
+
+	let mut cmd = baktu();
+	cmd.current_dir(temp.child("repo"))
+		.args(["diff", "s/0", "s/1"]);
+	cmd.assert().success().stdout(
+		predicate::str::contains("content   src/hello.txt")
+			.and(predicate::str::contains("added     src/new.txt\n"))
+			.and(predicate::str::contains("src/dir/copy.txt").not()),
+	);
+
+	// Modified content is only found by a full check
+	temp.child("repo/sites/s/snaps/1/data/src/hello.txt")
+		.write_str("corrupt\n")
+		.unwrap();
+	fsck(&["--quick", "s/1"]).success().stdout("s/1\tok\n");
+	fsck(&["s/1"]).failure().stdout(predicate::str::starts_with(
+		"s/1/src/hello.txt\tBLAKE3 mismatch",
+	));
+
+	// Modified metadata is found by both
+	let meta_file = temp.child("repo/sites/s/snaps/0/data/src/dir/.baktu.meta.brj");
+	let meta = std::fs::read_to_string(meta_file.path()).unwrap();
+	meta_file
+		.write_str(&meta.replacen("\nuid ", "\nuid 1", 1))
+		.unwrap();
+	fsck(&["--quick", "s/0"])
+		.failure()
+		.stdout(predicate::str::starts_with(
+			"s/0/src/dir\tmetadata digest mismatch",
+		));
+}
+
+#[test]
+fn signatures() {
+	let temp = repo_with_site();
+	let mut cmd = baktu();
+	cmd.current_dir(&temp).args(["gen-key", "site.key"]);
+	let public_key = String::from_utf8(cmd.assert().success().get_output().stdout.clone()).unwrap();
+	temp.child("site.key.pub").assert(public_key.as_str());
+
+	let config = temp.child("repo/sites/s/config.toml");
+	let data = std::fs::read_to_string(config.path()).unwrap();
+	config
+		.write_str(&data.replace(
+			"#key = \"~/.config/baktu/site.key\"",
+			&format!("key = {:?}", temp.child("site.key").path()),
+		))
+		.unwrap();
+	snap(&temp, &[]);
+
+	let verify = |key: &str| {
+		let mut cmd = baktu();
+		cmd.current_dir(temp.child("repo"))
+			.args(["verify-signature", "--public-key", key, "s"]);
+		cmd.assert()
+	};
+	let key_file = temp.child("site.key.pub");
+	let key_file = key_file.path().to_str().unwrap();
+	verify(public_key.trim_end()).success().stdout("s/0\tok\n");
+	verify(key_file).success().stdout("s/0\tok\n");
+
+	let mut cmd = baktu();
+	cmd.current_dir(&temp).args(["gen-key", "other.key"]);
+	let other_key = String::from_utf8(cmd.assert().success().get_output().stdout.clone()).unwrap();
+	verify(other_key.trim_end())
+		.failure()
+		.stdout(predicate::str::starts_with(
+			"s/0\tsigned by a different key",
+		));
+
+	temp.child("repo/sites/s/snaps/0/summary.toml")
+		.write_str("bytes_read = 0\n")
+		.unwrap();
+	verify(key_file)
+		.failure()
+		.stdout("s/0\tsummary changed since signing\n");
+}
+
+#[test]
+fn parity() {
+	let temp = repo_with_site();
+	snap(&temp, &[]);
+	let parity = |args: &[&str]| {
+		let mut cmd = baktu();
+		cmd.current_dir(temp.child("repo")).arg("parity").args(args);
+		cmd.assert()
+	};
+	parity(&["create", "--block-size", "16", "s/0"])
+		.success()
+		.stdout(predicate::str::starts_with("s/0\t"));
+	temp.child("repo/sites/s/parity/0")
+		.assert(predicate::path::is_dir());
+	parity(&["repair"]).success().stdout("s/0\tok\n");
+
+	// Flip a byte of both data and metadata, and lose a file entirely
+	let flip = |rel_path: &str| {
+		let path = temp.child("repo/sites/s/snaps/0").child(rel_path);
+		let mut data = std::fs::read(path.path()).unwrap();
+		data[5] ^= 1;
+		std::fs::write(path.path(), data).unwrap();
+	};
+	flip("data/src/dir/copy.txt");
+	flip("data/src/.baktu.meta.brj");
+	std::fs::remove_file(temp.child("repo/sites/s/snaps/0/meta_name.cfg.bin")).unwrap();
+	let fsck = || {
+		let mut cmd = baktu();
+		cmd.current_dir(temp.child("repo")).arg("fsck");
+		cmd.assert()
+	};
+	fsck().failure();
+
+	let damage = "s/0/data/src/.baktu.meta.brj\t{}\n\
+		s/0/data/src/dir/copy.txt\t{}\n\
+		s/0/meta_name.cfg.bin\t{}\n";
+	parity(&["repair", "-n"])
+		.success()
+		.stdout(damage.replace("{}", "repairable"));
+	parity(&["repair"])
+		.success()
+		.stdout(damage.replace("{}", "repaired"));
+	fsck().success().stdout("s/0\tok\n");
+
+	// Stale recovery data is removed along with changes to the snapshot
+	let mut cmd = baktu();
+	cmd.current_dir(temp.child("repo"))
+		.args(["purge", "s", "src/dir"]);
+	cmd.assert().success();
+	temp.child("repo/sites/s/parity/0")
+		.assert(predicate::path::missing());
+}
+
+#[test]
+fn import_rsnapshot() {
+	let temp = repo_with_site();
+
+	// daily.0 is the newer one, with notes.txt hard linked from daily.1 as with rsnapshot
+	temp.child("daily.1/localhost/home/notes.txt")
+		.write_str("unchanged notes\n")
+		.unwrap();
+	temp.child("daily.1/localhost/home/todo.txt")
+		.write_str("old todo\n")
+		.unwrap();
+	temp.child("daily.0/localhost/home")
+		.create_dir_all()
+		.unwrap();
+	std::fs::hard_link(
+		temp.child("daily.1/localhost/home/notes.txt").path(),
+		temp.child("daily.0/localhost/home/notes.txt").path(),
+	)
+	.unwrap();
+	temp.child("daily.0/localhost/home/todo.txt")
+		.write_str("new todo\n")
+		.unwrap();
+	let set_mtime = |dir: &str, secs: u64| {
+		std::fs::File::open(temp.child(dir).path())
+			.unwrap()
+			.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+			.unwrap();
+	};
+	set_mtime("daily.1", 1_700_049_600);
+	set_mtime("daily.0", 1_700_136_000);
+
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.arg("import-rsnapshot")
+		.arg(temp.child("daily.0").path())
+		.arg(temp.child("daily.1").path())
+		.assert()
+		.success();
+
+	let run = |args: &[&str]| {
+		let mut cmd = baktu();
+		cmd.current_dir(temp.child("repo")).args(args);
+		cmd.assert().success()
+	};
+	run(&["cat", "s/0/localhost/home/todo.txt"]).stdout("old todo\n");
+	run(&["cat", "s/1/localhost/home/todo.txt"]).stdout("new todo\n");
+	run(&["cat", "s/1/localhost/home/notes.txt"]).stdout("unchanged notes\n");
+	run(&["ls", "s/1/localhost/home"])
+		.stdout(predicate::str::is_match("= notes.txt\n.*   todo.txt\n$").unwrap());
+	run(&["ls", "s"]).stdout(predicate::str::contains("2023-11-15"));
+	run(&["fsck"]);
+
+	let meta = std::fs::read_to_string(
+		temp.child("repo/sites/s/snaps/1/data/localhost/home/.baktu.meta.brj")
+			.path(),
+	)
+	.unwrap();
+	assert_eq!(meta.matches("is-approximate\n").count(), 2);
+}
+
+#[test]
+fn import_rsnapshot_failure() {
+	let temp = repo_with_site();
+	temp.child("daily.1/home/notes.txt")
+		.write_str("notes\n")
+		.unwrap();
+	temp.child("daily.0/home/notes.txt")
+		.write_str("notes\n")
+		.unwrap();
+	temp.child("daily.0/home/.baktu.meta.brj").touch().unwrap();
+	std::fs::File::open(temp.child("daily.1").path())
+		.unwrap()
+		.set_modified(std::time::UNIX_EPOCH)
+		.unwrap();
+
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.arg("import-rsnapshot")
+		.arg(temp.child("daily.0").path())
+		.arg(temp.child("daily.1").path())
+		.assert()
+		.code(65)
+		.stderr(predicate::str::contains(
+			"already contains a file named \".baktu.meta.brj\"",
+		));
+
+	// The earlier import is complete and kept, the failed one removed
+	temp.child("repo/sites/s/snaps/0/summary.toml")
+		.assert(predicate::path::exists());
+	temp.child("repo/sites/s/snaps/1")
+		.assert(predicate::path::missing());
+	baktu()
+		.current_dir(temp.child("repo"))
+		.arg("fsck")
+		.assert()
+		.success();
+}
+
+#[test]
+fn snap_summary() {
+	let temp = repo_with_site();
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.args(["nsv-add-to", "exclude-paths.nsv"])
+		.arg(temp.child("src/dir/empty").path())
+		.assert()
+		.success();
+
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.arg("snap")
+		.assert()
+		.success()
+		.stderr(
+			predicate::str::is_match(format!(
+				"^snapshot {:?} done in [0-9.]+s: 4 paths processed \\(2 dir, 2 reg\\), 1 excluded\n\
+				read 24 B, wrote 12 B, deduplicated 12 B, 1 new unique hashes\n$",
+				temp.child("repo/sites/s/snaps/0").path()
+			))
+			.unwrap(),
+		);
+
+	let summary = std::fs::read_to_string(temp.child("repo/sites/s/snaps/0/summary.toml")).unwrap();
+	let mut summary: toml::Table = summary.parse().unwrap();
+	for key in ["start_time", "end_time", "wall_time_secs"] {
+		assert!(summary.remove(key).is_some(), "{key} missing");
+	}
+	let expected: toml::Table = format!(
+		"baktu_version = {:?}\n\
+		bytes_read = 24\n\
+		bytes_written = 12\n\
+		bytes_deduplicated = 12\n\
+		new_unique_hashes = 1\n\
+		files = {{ dir = 2, reg = 2 }}\n\
+		exclusions = {{ \"exclude-paths.nsv\" = 1 }}\n",
+		env!("CARGO_PKG_VERSION")
+	)
+	.parse()
+	.unwrap();
+	assert_eq!(summary, expected);
+
+	// Quiet runs only record it
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.args(["snap", "-q"])
+		.assert()
+		.success()
+		.stderr("");
+	temp.child("repo/sites/s/snaps/1/summary.toml")
+		.assert(predicate::path::is_file());
+}
+
+#[test]
+fn snap_exclusion_log() {
+	let temp = repo_with_site();
+	let dir = temp.child("src/dir");
+	let exclude = |subcommand: &str| {
+		baktu()
+			.current_dir(temp.child("repo/sites/s"))
+			.args([subcommand, "exclude-paths.nsv"])
+			.arg(dir.path())
+			.assert()
+			.success();
+	};
+
+	exclude("nsv-add-to");
+	snap(&temp, &[]);
+	assert_eq!(
+		exclusions(&temp, "0"),
+		[(
+			dir.to_str().unwrap().to_owned(),
+			"exclude-paths.nsv".to_owned()
+		)]
+	);
+
+	// Unchanged exclusions are not worth a warning
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.arg("snap")
+		.assert()
+		.success()
+		.stderr(predicate::str::contains("differs significantly").not());
+
+	exclude("nsv-rm-from");
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.args(["snap", "-v"])
+		.assert()
+		.success()
+		.stderr(
+			predicate::str::contains(
+				"The set of excluded paths differs significantly from the previous snapshot: 0 \
+				newly excluded, 1 no longer excluded.",
+			)
+			.and(predicate::str::contains(format!(
+				"no longer excluded since the previous snapshot: {:?}",
+				dir.path()
+			))),
+		);
+	assert_eq!(exclusions(&temp, "2"), []);
+}
+
+#[test]
+fn snap_exclude_predicates() {
+	let temp = repo_with_site();
+	temp.child("src/big.bin").write_binary(&[0; 100]).unwrap();
+	let old = temp.child("src/old.txt");
+	old.write_str("old\n").unwrap();
+	std::fs::File::options()
+		.write(true)
+		.open(old.path())
+		.unwrap()
+		.set_modified(
+			std::time::SystemTime::now() - std::time::Duration::from_secs(6 * 366 * 86400),
+		)
+		.unwrap();
+	std::os::unix::fs::symlink("hello.txt", temp.child("src/link").path()).unwrap();
+	// On procfs wherever this runs
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.args(["nsv-add-to", "include-paths.nsv", "/proc/version"])
+		.assert()
+		.success();
+
+	let config = temp.child("repo/sites/s/config.toml");
+	let data = std::fs::read_to_string(config.path()).unwrap();
+	config
+		.write_str(
+			&data
+				.replace("#max_size =", "max_size = 50")
+				.replace("#mtime_older_than", "mtime_older_than")
+				.replace("\ntypes = []", "\ntypes = [\"lnk\"]")
+				.replace("fs_types = []", "fs_types = [\"proc\"]"),
+		)
+		.unwrap();
+	snap(&temp, &[]);
+
+	let src = |name: &str| temp.child("src").child(name).to_str().unwrap().to_owned();
+	assert_eq!(
+		exclusions(&temp, "0"),
+		[
+			(
+				src("big.bin"),
+				"config.toml/exclude.max_size (100 > 50 bytes)".to_owned()
+			),
+			(src("link"), "config.toml/exclude.types (lnk)".to_owned()),
+			(
+				src("old.txt"),
+				"config.toml/exclude.mtime_older_than (5years)".to_owned()
+			),
+			(
+				"/proc/version".to_owned(),
+				"config.toml/exclude.fs_types (proc)".to_owned()
+			),
+		]
+	);
+	baktu()
+		.current_dir(temp.child("repo"))
+		.args(["ls", "s/0/src"])
+		.assert()
+		.success()
+		.stdout(
+			predicate::str::contains("hello.txt").and(
+				predicate::str::contains("big.bin")
+					.or(predicate::str::contains("link"))
+					.or(predicate::str::contains("old.txt"))
+					.not(),
+			),
+		);
+	temp.child("repo/sites/s/snaps/0/data/version")
+		.assert(predicate::path::missing());
+}
+
+#[test]
+fn snap_one_file_system() {
+	let temp = repo_with_site();
+	// Has devpts mounted on /dev/pts wherever this runs
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.args(["nsv-add-to", "include-paths.nsv", "/dev"])
+		.assert()
+		.success();
+
+	// Unprivileged runs can not recreate device files
+	snap(
+		&temp,
+		&["--one-file-system", "--prescan", "--on-error", "record"],
+	);
+
+	assert!(exclusions(&temp, "0").contains(&(
+		"/dev/pts".to_owned(),
+		"--one-file-system (mount point)".to_owned()
+	)));
+	baktu()
+		.current_dir(temp.child("repo"))
+		.args(["ls", "s/0/dev"])
+		.assert()
+		.success()
+		.stdout(predicate::str::contains("pts").not());
+}
+
+#[test]
+fn snap_on_error() {
+	let temp = repo_with_site();
+	// Can not be represented in the snapshot, as it would collide with the metadata files
+	temp.child("src/dir/.baktu.meta.brj").touch().unwrap();
+
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.arg("snap")
+		.assert()
+		.code(65)
+		.stderr(predicate::str::contains(
+			"already contains a file named \".baktu.meta.brj\"",
+		));
+
+	snap(&temp, &["--on-error", "record"]);
+	let excluded = std::fs::read(temp.child("repo/sites/s/snaps/1/excluded.nsv")).unwrap();
+	let excluded = String::from_utf8(excluded).unwrap();
+	assert!(excluded.starts_with(&format!(
+		"{}\0error (",
+		temp.child("src/dir").path().display()
+	)));
+
+	baktu()
+		.current_dir(temp.child("repo"))
+		.args(["ls", "s/1/src"])
+		.assert()
+		.success()
+		.stdout(predicate::str::contains("hello.txt").and(predicate::str::contains("dir").not()));
+	baktu()
+		.current_dir(temp.child("repo"))
+		.args(["fsck", "s/1"])
+		.assert()
+		.success();
+}
+
+#[test]
+fn snap_progress() {
+	let temp = repo_with_site();
+
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.args(["snap", "-v", "--prescan"])
+		.assert()
+		.success()
+		.stderr(predicate::str::contains("pre-scan counted 5 paths, 24 B"));
+	temp.child("repo/sites/s/snaps/0")
+		.assert(predicate::path::is_dir());
+
+	// Not a terminal, so a line per interval rather than a line redrawn in place
+	let output = baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.args(["snap", "-q", "--prescan", "--progress-interval", "0s"])
+		.assert()
+		.success();
+	let stderr = String::from_utf8(output.get_output().stderr.clone()).unwrap();
+	let lines: Vec<_> = stderr.lines().collect();
+	assert_eq!(lines.len(), 5, "{stderr}");
+	assert!(lines[0].starts_with("progress: 1/5 paths, "), "{stderr}");
+	assert!(
+		predicate::str::is_match(
+			r"^progress: 5/5 paths, 24 B/24 B \([0-9.]+ (B|KiB|MiB)/s\), [0-9]+ deduplicated, ETA 0s$"
+		)
+		.unwrap()
+		.eval(lines[4]),
+		"{stderr}"
+	);
+
+	// Far apart by default
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.args(["snap", "-q", "--progress"])
+		.assert()
+		.success()
+		.stderr("");
+}
+
+#[test]
+fn snap_events() {
+	let temp = repo_with_site();
+	temp.child("src/CACHEDIR.TAG")
+		.write_str("not a tag")
+		.unwrap();
+	let events_path = temp.child("events.jsonl");
+	let events = || -> Vec<serde_json::Value> {
+		std::fs::read_to_string(events_path.path())
+			.unwrap()
+			.lines()
+			.map(|line| serde_json::from_str(line).unwrap())
+			.collect()
+	};
+
+	snap(&temp, &["--events", events_path.to_str().unwrap()]);
+	let first = events();
+	assert!(first.iter().all(|event| event["version"] == 1));
+	let names: Vec<_> = first
+		.iter()
+		.map(|event| event["event"].as_str().unwrap())
+		.collect();
+	assert_eq!(
+		names,
+		[
+			"snapshot_started",
+			"include_root_started",
+			"warning",
+			"deduplicated",
+			"snapshot_finished"
+		]
+	);
+	assert_eq!(first[2]["kind"], "invalid_cachedir_tag");
+	assert_eq!(
+		first[3]["path"].as_str().unwrap(),
+		temp.child("src/hello.txt").to_str().unwrap()
+	);
+	assert!(first[3]["preexisting"]
+		.as_str()
+		.unwrap()
+		.ends_with("snaps/0/data/src/dir/copy.txt"));
+	assert_eq!(first[4]["summary"]["files"]["reg"], 4);
+
+	// Appended to, with the aborting error last
+	temp.child("src/dir/.baktu.meta.brj").touch().unwrap();
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.args(["snap", "--events", events_path.to_str().unwrap()])
+		.assert()
+		.code(65);
+	let second = &events()[first.len()..];
+	let last = second.last().unwrap();
+	assert_eq!(last["event"], "error");
+	assert_eq!(last["action"], "abort");
+	assert_eq!(
+		last["path"].as_str().unwrap(),
+		temp.child("src/dir").to_str().unwrap()
+	);
+	assert_eq!(
+		second
+			.iter()
+			.filter(|event| event["event"] == "error")
+			.count(),
+		1
+	);
+}
+
+#[test]
+fn snap_hooks() {
+	let temp = repo_with_site();
+	let config = temp.child("repo/sites/s/config.toml");
+	let template = std::fs::read_to_string(config.path()).unwrap();
+	let set_hooks = |pre_snap: &str| {
+		let log = |name| {
+			format!("echo {name} $BAKTU_STATUS $BAKTU_SNAPSHOT $BAKTU_ERROR >> ../../../hooks.log")
+		};
+		config
+			.write_str(&template.replace(
+				"[hooks]\n",
+				&format!(
+					"[hooks]\npre_snap = {:?}\npost_snap = {:?}\non_failure = {:?}\n",
+					format!("{}; {pre_snap}", log("pre")),
+					log("post"),
+					log("failure")
+				),
+			))
+			.unwrap();
+	};
+	let hooks_log = temp.child("hooks.log");
+	let snaps = temp.child("repo/sites/s/snaps");
+
+	set_hooks("true");
+	snap(&temp, &[]);
+	hooks_log.assert(format!(
+		"pre pending {0}\npost success {0}\n",
+		snaps.child("0").path().display()
+	));
+
+	// A failing pre hook aborts before the snapshot directory is created
+	std::fs::remove_file(hooks_log.path()).unwrap();
+	set_hooks("exit 3");
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.arg("snap")
+		.assert()
+		.code(69)
+		.stderr(predicate::str::contains("pre_snap hook failed"));
+	snaps.child("1").assert(predicate::path::missing());
+	hooks_log.assert(format!(
+		"pre pending {0}\nfailure failure {0} pre_snap hook failed: exit status: 3\n",
+		snaps.child("1").path().display()
+	));
+
+	// The pre-scan counts what the pre hook makes available
+	std::fs::remove_file(hooks_log.path()).unwrap();
+	set_hooks("echo new > ../../../src/new.txt");
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.args(["snap", "-v", "--prescan"])
+		.assert()
+		.success()
+		.stderr(predicate::str::contains("pre-scan counted 6 paths, 28 B"));
+
+	// Archives are refused rather than snapshotted without running the hooks
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.args(["snap", "--from-tar", "-"])
+		.write_stdin(tar::Builder::new(Vec::new()).into_inner().unwrap())
+		.assert()
+		.code(64)
+		.stderr(predicate::str::contains("has hooks"));
+	snaps.child("1").assert(predicate::path::is_dir());
+	snaps.child("2").assert(predicate::path::missing());
+}
+
+#[test]
+fn snap_from_tar() {
+	let temp = repo_with_site();
+
+	let new_header = |mode| {
+		let mut header = tar::Header::new_ustar();
+		header.set_mode(mode);
+		header.set_uid(0);
+		header.set_gid(0);
+		header.set_mtime(1_700_000_000);
+		header.set_size(0);
+		header
+	};
+	let mut builder = tar::Builder::new(Vec::new());
+	let mut header = new_header(0o750);
+	header.set_entry_type(tar::EntryType::Directory);
+	builder
+		.append_data(&mut header, "./etc", std::io::empty())
+		.unwrap();
+
+	let content = b"setting = 1\n";
+	builder
+		.append_pax_extensions([
+			("mtime", b"1700000000.5".as_slice()),
+			("uid", b"1234"),
+			("SCHILY.xattr.user.origin", b"appliance"),
+		])
+		.unwrap();
+	let mut header = new_header(0o640);
+	header.set_size(content.len() as u64);
+	builder
+		.append_data(&mut header, "./etc/app.conf", content.as_slice())
+		.unwrap();
+
+	let mut header = new_header(0o640);
+	header.set_entry_type(tar::EntryType::Link);
+	builder
+		.append_link(&mut header, "./etc/app.conf.bak", "./etc/app.conf")
+		.unwrap();
+	let archive = builder.into_inner().unwrap();
+	temp.child("backup.tar").write_binary(&archive).unwrap();
+
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.args(["snap", "--from-tar"])
+		.arg(temp.child("backup.tar").path())
+		.assert()
+		.success();
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.args(["snap", "--from-tar", "-"])
+		.write_stdin(archive)
+		.assert()
+		.success();
+
+	let run = |args: &[&str]| {
+		let mut cmd = baktu();
+		cmd.current_dir(temp.child("repo")).args(args);
+		cmd.assert().success()
+	};
+	run(&["cat", "s/0/etc/app.conf.bak"]).stdout("setting = 1\n");
+	run(&["ls", "s/0/etc"])
+		.stdout(predicate::str::is_match("   app.conf\n.* = app.conf.bak\n$").unwrap());
+	run(&["ls", "s/1/etc"])
+		.stdout(predicate::str::is_match("= app.conf\n.* = app.conf.bak\n$").unwrap());
+	run(&["fsck"]);
+
+	let meta = std::fs::read_to_string(
+		temp.child("repo/sites/s/snaps/1/data/etc/.baktu.meta.brj")
+			.path(),
+	)
+	.unwrap();
+	assert!(meta.contains("uid 1234\n"));
+	assert!(meta.contains("mode 640\n"));
+	assert!(meta.contains("mtime 1700000000.500000000\n"));
+	assert!(meta.contains("user.origin"));
+}
+
+#[test]
+fn snap_from_tar_symlink_traversal() {
+	let temp = repo_with_site();
+	temp.child("outside/victim").write_str("intact\n").unwrap();
+
+	let new_header = |entry_type, mode| {
+		let mut header = tar::Header::new_ustar();
+		header.set_entry_type(entry_type);
+		header.set_mode(mode);
+		header.set_uid(0);
+		header.set_gid(0);
+		header.set_mtime(1_700_000_000);
+		header.set_size(0);
+		header
+	};
+	let mut builder = tar::Builder::new(Vec::new());
+	let mut append_symlink = |path: &str, target: &Path| {
+		let mut header = new_header(tar::EntryType::Symlink, 0o777);
+		builder.append_link(&mut header, path, target).unwrap();
+	};
+	append_symlink("victim", temp.child("outside/victim").path());
+	append_symlink("a", temp.child("outside").path());
+	let mut append_file = |path: &str| {
+		let mut header = new_header(tar::EntryType::Regular, 0o644);
+		header.set_size(6);
+		builder
+			.append_data(&mut header, path, b"pwned\n".as_slice())
+			.unwrap();
+	};
+	// Replaces the symlink rather than writing through it
+	append_file("victim");
+	append_file("a/f");
+	let archive = builder.into_inner().unwrap();
+
+	baktu()
+		.current_dir(temp.child("repo/sites/s"))
+		.args(["snap", "--from-tar", "-"])
+		.write_stdin(archive)
+		.assert()
+		.code(65)
+		.stderr(predicate::str::contains(
+			"\"a/f\": archive entry is within \"a\", which is not a directory",
+		));
+	temp.child("outside/victim").assert("intact\n");
+	temp.child("outside/f").assert(predicate::path::missing());
+
+	// No incomplete snapshot is left behind
+	temp.child("repo/sites/s/snaps/0")
+		.assert(predicate::path::missing());
+	baktu()
+		.current_dir(temp.child("repo"))
+		.arg("fsck")
+		.assert()
+		.success();
+}
+
+#[test]
+fn clone_and_pull() {
+	let temp = repo_with_site();
+	snap(&temp, &[]);
+
+	let mut cmd = baktu();
+	cmd.current_dir(&temp).args(["clone", "repo", "copy"]);
+	cmd.assert()
+		.success()
+		.stdout(predicate::str::starts_with("s/0\t"));
+
+	snap(&temp, &[]);
+	let pull = || {
+		let mut cmd = baktu();
+		cmd.current_dir(temp.child("copy"))
+			.args(["pull", "../repo"]);
+		cmd.assert()
+	};
+	pull()
+		.success()
+		.stdout(predicate::str::starts_with("s/1\t"));
+	pull().success().stdout("");
+
+	// Deduplicated against the previous snapshot
+	temp.child("copy/sites/s/snaps/1/data/src/dir/copy.txt")
+		.assert(predicate::path::is_symlink());
+	let mut cmd = baktu();
+	cmd.current_dir(temp.child("copy"))
+		.args(["cat", "s/1/src/dir/copy.txt"]);
+	cmd.assert().success().stdout("hello world\n");
+
+	temp.child("src/hello.txt").write_str("changed\n").unwrap();
+	snap(&temp, &[]);
+	temp.child("repo/sites/s/snaps/2/data/src/hello.txt")
+		.write_str("corrupt\n")
+		.unwrap();
+	pull()
+		.failure()
+		.stderr(predicate::str::contains("BLAKE3 mismatch"));
+	temp.child("copy/sites/s/snaps/2")
+		.assert(predicate::path::missing());
+}
+
+/// Returns a temporary copy of a version 1 repository with a single snapshot, created by the
+/// baktu version preceding the introduction of repository format versioning
+fn repo_v1() -> assert_fs::TempDir {
+	let temp = assert_fs::TempDir::new().unwrap();
+	// Not assert_fs' copy_from, as that does not preserve symlinks
+	Command::new("cp")
+		.arg("-a")
+		.arg("tests/fixtures/repo-v1")
+		.arg(temp.child("repo").path())
+		.assert()
+		.success();
+	temp
+}
+
+#[test]
+fn upgrade_v1() {
+	let temp = repo_v1();
+	let repo = temp.child("repo");
+
+	// Readable, but not writable before upgrading
+	let mut cmd = baktu();
+	cmd.current_dir(&repo).args(["cat", "s/0/src/hello.txt"]);
+	cmd.assert().success().stdout("hello world\n");
+	let mut cmd = baktu();
+	cmd.current_dir(&repo).args(["add-site", "t"]);
+	cmd.assert()
+		.failure()
+		.stderr(predicate::str::contains("run `baktu upgrade` first"));
+
+	let mut cmd = baktu();
+	cmd.current_dir(&repo).args(["upgrade", "--dry-run"]);
+	cmd.assert()
+		.success()
+		.stdout(predicate::str::starts_with("version 1 -> 2: "));
+	repo.child("BAKTU_REPO.TAG")
 		.assert("baktu repository version 1\n");
+
+	let mut cmd = baktu();
+	cmd.current_dir(&repo).arg("upgrade");
+	cmd.assert().success().stdout(
+		predicate::str::starts_with("version 1 -> 2: ")
+			.and(predicate::str::contains("\nversion 2 -> 3: ")),
+	);
+	repo.child("BAKTU_REPO.TAG")
+		.assert("baktu repository version 3\n");
+	let mut cmd = baktu();
+	cmd.current_dir(&repo).arg("fsck");
+	cmd.assert().success().stdout("s/0\tok\n");
+
+	let mut cmd = baktu();
+	cmd.current_dir(&repo).arg("upgrade");
+	cmd.assert().success().stdout("");
+	let mut cmd = baktu();
+	cmd.current_dir(&repo).args(["add-site", "t"]);
+	cmd.assert().success();
+	let mut cmd = baktu();
+	cmd.current_dir(&repo).args(["cat", "s/0/src/dir/copy.txt"]);
+	cmd.assert().success().stdout("hello world\n");
+}
+
+#[test]
+fn unknown_incompat_feature() {
+	let temp = repo_with_site();
+	temp.child("repo/BAKTU_REPO.TAG")
+		.write_str("baktu repository version 3\nincompat frobnication\n")
+		.unwrap();
+
+	let mut cmd = baktu();
+	cmd.current_dir(temp.child("repo/sites/s")).arg("snap");
+	cmd.assert().failure().stderr(predicate::str::contains(
+		"unknown incompatible features: frobnication",
+	));
+	temp.child("repo/sites/s/snaps/0")
+		.assert(predicate::path::missing());
+
+	// Still readable, with a warning
+	let mut cmd = baktu();
+	cmd.current_dir(temp.child("repo")).arg("ls");
+	cmd.assert()
+		.success()
+		.stdout("s\t0 snapshots\n")
+		.stderr(predicate::str::contains(
+			"results may be incomplete or wrong",
+		));
 }
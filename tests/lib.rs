@@ -0,0 +1,114 @@
+//! Tests of the library API, in a binary of their own as they modify the process environment
+
+use std::path::Path;
+
+use assert_cmd::Command;
+use assert_fs::prelude::*;
+use baktu::repo::{meta_file::MetaFile, snap::Options, Repo};
+
+#[test]
+fn library_api() {
+	// The library spawns the `get-all-xattrs` helper from PATH for unprivileged runs. Setting it
+	// is only sound as the single test of this binary, with no other thread reading the environment
+	std::env::set_var(
+		"PATH",
+		format!(
+			"{}/xattr-helper:{}",
+			env!("CARGO_MANIFEST_DIR"),
+			std::env::var("PATH").unwrap_or_default()
+		),
+	);
+
+	let temp = assert_fs::TempDir::new().unwrap();
+	temp.child("src/hello.txt")
+		.write_str("hello world\n")
+		.unwrap();
+	temp.child("src/dir/copy.txt")
+		.write_str("hello world\n")
+		.unwrap();
+	temp.child("src/dir/empty").touch().unwrap();
+	temp.child("repo").create_dir_all().unwrap();
+	let baktu = |dir: &str, args: &[&Path]| {
+		Command::cargo_bin("baktu")
+			.unwrap()
+			.current_dir(temp.child(dir))
+			.args(args)
+			.assert()
+			.success();
+	};
+	baktu("repo", &[Path::new("init")]);
+	baktu("repo", &[Path::new("add-site"), Path::new("s")]);
+	baktu(
+		"repo/sites/s",
+		&[
+			Path::new("nsv-add-to"),
+			Path::new("include-paths.nsv"),
+			temp.child("src").path(),
+		],
+	);
+
+	assert!(matches!(
+		Repo::open(temp.child("src").path()),
+		Err(baktu::Error::Repo { .. })
+	));
+	let repo = Repo::open(temp.child("repo").path()).unwrap();
+	assert!(matches!(
+		repo.site("missing"),
+		Err(baktu::Error::NotFound { .. })
+	));
+
+	let site = repo.site("s").unwrap();
+	let (snap, summary) = site.snapshot(&Options::default()).unwrap();
+	assert_eq!(snap.0, temp.child("repo/sites/s/snaps/0").path());
+	assert_eq!(summary.files_total(), 5);
+
+	let mut paths: Vec<_> = snap
+		.records()
+		.unwrap()
+		.map(|rec| rec.unwrap().0.to_string_lossy().into_owned())
+		.collect();
+	paths.sort();
+	assert_eq!(
+		paths,
+		[
+			"src",
+			"src/dir",
+			"src/dir/copy.txt",
+			"src/dir/empty",
+			"src/hello.txt"
+		]
+	);
+
+	// Records are written such that parsing skips none of their keys
+	for entry in walkdir::WalkDir::new(&snap.0) {
+		let entry = entry.unwrap();
+		if entry.file_name() != ".baktu.meta.brj" {
+			continue;
+		}
+		let meta_file = MetaFile(entry.into_path());
+		let mut rewritten = Vec::new();
+		for rec in meta_file.meta_records().unwrap() {
+			rec.write(&mut rewritten).unwrap();
+		}
+		assert_eq!(
+			String::from_utf8_lossy(&rewritten),
+			String::from_utf8_lossy(&std::fs::read(&meta_file.0).unwrap()),
+			"{:?}",
+			meta_file.0
+		);
+	}
+
+	// Source errors are returned rather than exiting
+	temp.child("repo/sites/s/exclude-paths.nsv")
+		.write_binary(b"/nonexistent\0")
+		.unwrap();
+	match site.snapshot(&Options::default()) {
+		Err(baktu::Error::Config { path, .. }) => assert_eq!(path, Path::new("/nonexistent")),
+		res => panic!("unexpected result {:?}", res.map(|(snap, _)| snap)),
+	}
+
+	temp.child("repo/BAKTU_REPO.TAG")
+		.write_str("baktu repository version x\n")
+		.unwrap();
+	assert!(matches!(repo.tag(), Err(baktu::Error::Corrupt { .. })));
+}